@@ -0,0 +1,80 @@
+// Counting sort of several independent arrays (entries) of the same value buffer in a single count, scan and sort sequence
+// The histogram of each entry is laid out at its count offset in the shared count buffer, so scanning the whole buffer at once
+// gives, for each bucket, the number of values of the previous entries (values_before) plus the end of the bucket in its entry
+// The sorted ids of an entry (indices in the value buffer) are written into its own range of the sorting id buffer
+
+struct BatchEntry {
+    value_start: u32,
+    value_end: u32,
+    count_offset: u32,
+    key_range: u32,
+    // Number of values of the entries whose histogram comes first in the count buffer
+    values_before: u32,
+}
+
+@group(0) @binding(0) var<storage, read> values : array<u32>;
+@group(0) @binding(1) var<storage, read_write> counting : array<atomic<u32>>;
+// Sorted by value_start
+@group(0) @binding(2) var<storage, read> entries : array<BatchEntry>;
+@group(0) @binding(3) var<storage, read_write> sorting_idx : array<u32>;
+
+const NO_ENTRY: u32 = 0xffffffffu;
+
+// Entry of the value at index, NO_ENTRY for the values between entries
+fn find_entry(index: u32) -> u32 {
+    var low = 0u;
+    var high = arrayLength(&entries);
+    while (low < high) {
+        let mid = (low + high) / 2u;
+        if (entries[mid].value_start <= index) {
+            low = mid + 1u;
+        } else {
+            high = mid;
+        }
+    }
+
+    if (low == 0u || index >= entries[low - 1u].value_end) { return NO_ENTRY; }
+    return low - 1u;
+}
+
+// Keys out of the key range of their entry are clamped so they never reach the histogram of another entry
+fn bucket(entry: BatchEntry, value: u32) -> u32 {
+    return entry.count_offset + min(value, entry.key_range - 1u);
+}
+
+@compute @workgroup_size(#WORKGROUP_SIZE)
+fn count_batched(
+    @builtin(local_invocation_id) localInvocationId : vec3<u32>,
+    @builtin(workgroup_id) workgroupId : vec3<u32>,
+    @builtin(num_workgroups) numWorkgroups : vec3<u32>,
+) {
+    let wid: u32 = linear_workgroup_index(workgroupId, numWorkgroups);
+    let gid: u32 = wid * u32(#WORKGROUP_SIZE) + localInvocationId.x;
+
+    if (gid >= arrayLength(&values)) { return; }
+
+    let entry_index = find_entry(gid);
+    if (entry_index == NO_ENTRY) { return; }
+
+    atomicAdd(&counting[bucket(entries[entry_index], values[gid])], 1u);
+}
+
+@compute @workgroup_size(#WORKGROUP_SIZE)
+// Not stable as done in parallel (as the regular sort step)
+fn sort_batched(
+    @builtin(local_invocation_id) localInvocationId : vec3<u32>,
+    @builtin(workgroup_id) workgroupId : vec3<u32>,
+    @builtin(num_workgroups) numWorkgroups : vec3<u32>,
+) {
+    let wid: u32 = linear_workgroup_index(workgroupId, numWorkgroups);
+    let gid: u32 = wid * u32(#WORKGROUP_SIZE) + localInvocationId.x;
+
+    if (gid >= arrayLength(&values)) { return; }
+
+    let entry_index = find_entry(gid);
+    if (entry_index == NO_ENTRY) { return; }
+
+    let entry = entries[entry_index];
+    let count = atomicSub(&counting[bucket(entry, values[gid])], 1u);
+    sorting_idx[entry.value_start + count - 1u - entry.values_before] = gid;
+}
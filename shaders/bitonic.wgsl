@@ -0,0 +1,144 @@
+// Bitonic sort of (key, id) pairs padded to a power of two with (0xffffffff, 0xffffffff) pairs
+// Pairs are compared by key then by id so the sort is stable and the padding ends up after the values
+// Blocks of BLOCK_SIZE (2 * WORKGROUP_SIZE) pairs are sorted and merged in workgroup memory, bigger merge steps compare pairs in global memory
+
+struct BitonicStep {
+    // Size of the bitonic sequences being merged (pairs at index i are sorted ascending when bit merge_size of i is 0)
+    merge_size: u32,
+    // Distance between compared pairs (only used by global_merge_step)
+    compare_distance: u32,
+}
+
+@group(0) @binding(0) var<storage, read> values : array<u32>;
+@group(0) @binding(1) var<storage, read_write> pairs : array<vec2<u32>>;
+@group(0) @binding(2) var<storage, read_write> sorting_idx : array<u32>;
+
+@group(1) @binding(0) var<uniform> step : BitonicStep;
+
+var<workgroup> block : array<vec2<u32>, #BLOCK_SIZE>;
+
+fn pair_less(a: vec2<u32>, b: vec2<u32>) -> bool {
+    return a.x < b.x || (a.x == b.x && a.y < b.y);
+}
+
+fn need_swap(a: vec2<u32>, b: vec2<u32>, ascending: bool) -> bool {
+    return select(pair_less(a, b), pair_less(b, a), ascending);
+}
+
+// Index of the first pair compared by the invocation at the given distance
+fn first_compared_index(invocation: u32, distance: u32) -> u32 {
+    return 2u * distance * (invocation / distance) + invocation % distance;
+}
+
+fn compare_and_swap_in_block(block_start: u32, lid: u32, distance: u32, merge_size: u32) {
+    let i = first_compared_index(lid, distance);
+    let a = block[i];
+    let b = block[i + distance];
+    if (need_swap(a, b, ((block_start + i) & merge_size) == 0u)) {
+        block[i] = b;
+        block[i + distance] = a;
+    }
+}
+
+@compute @workgroup_size(#WORKGROUP_SIZE)
+fn init_pairs(
+    @builtin(local_invocation_id) localInvocationId : vec3<u32>,
+    @builtin(workgroup_id) workgroupId : vec3<u32>,
+    @builtin(num_workgroups) numWorkgroups : vec3<u32>,
+) {
+    let gid: u32 = linear_workgroup_index(workgroupId, numWorkgroups) * u32(#WORKGROUP_SIZE) + localInvocationId.x;
+
+    if (gid >= arrayLength(&pairs)) { return; }
+
+    if (gid < arrayLength(&values)) {
+        pairs[gid] = vec2<u32>(values[gid], gid);
+    } else {
+        pairs[gid] = vec2<u32>(0xffffffffu, 0xffffffffu);
+    }
+}
+
+// Fully sort each block (every merge size up to BLOCK_SIZE)
+@compute @workgroup_size(#WORKGROUP_SIZE)
+fn local_sort(
+    @builtin(local_invocation_id) localInvocationId : vec3<u32>,
+    @builtin(workgroup_id) workgroupId : vec3<u32>,
+    @builtin(num_workgroups) numWorkgroups : vec3<u32>,
+) {
+    let lid: u32 = localInvocationId.x;
+    let block_start: u32 = linear_workgroup_index(workgroupId, numWorkgroups) * u32(#BLOCK_SIZE);
+
+    // Extra workgroups of a folded dispatch
+    if (block_start >= arrayLength(&pairs)) { return; }
+
+    block[lid] = pairs[block_start + lid];
+    block[lid + u32(#WORKGROUP_SIZE)] = pairs[block_start + lid + u32(#WORKGROUP_SIZE)];
+    workgroupBarrier();
+
+    for (var merge_size = 2u; merge_size <= u32(#BLOCK_SIZE); merge_size = merge_size << 1u) {
+        for (var distance = merge_size >> 1u; distance > 0u; distance = distance >> 1u) {
+            compare_and_swap_in_block(block_start, lid, distance, merge_size);
+            workgroupBarrier();
+        }
+    }
+
+    pairs[block_start + lid] = block[lid];
+    pairs[block_start + lid + u32(#WORKGROUP_SIZE)] = block[lid + u32(#WORKGROUP_SIZE)];
+}
+
+// Last steps of a merge of sequences bigger than a block (distances lower than BLOCK_SIZE)
+@compute @workgroup_size(#WORKGROUP_SIZE)
+fn local_merge(
+    @builtin(local_invocation_id) localInvocationId : vec3<u32>,
+    @builtin(workgroup_id) workgroupId : vec3<u32>,
+    @builtin(num_workgroups) numWorkgroups : vec3<u32>,
+) {
+    let lid: u32 = localInvocationId.x;
+    let block_start: u32 = linear_workgroup_index(workgroupId, numWorkgroups) * u32(#BLOCK_SIZE);
+
+    if (block_start >= arrayLength(&pairs)) { return; }
+
+    block[lid] = pairs[block_start + lid];
+    block[lid + u32(#WORKGROUP_SIZE)] = pairs[block_start + lid + u32(#WORKGROUP_SIZE)];
+    workgroupBarrier();
+
+    for (var distance = u32(#WORKGROUP_SIZE); distance > 0u; distance = distance >> 1u) {
+        compare_and_swap_in_block(block_start, lid, distance, step.merge_size);
+        workgroupBarrier();
+    }
+
+    pairs[block_start + lid] = block[lid];
+    pairs[block_start + lid + u32(#WORKGROUP_SIZE)] = block[lid + u32(#WORKGROUP_SIZE)];
+}
+
+// Merge step comparing pairs at a distance of at least BLOCK_SIZE
+@compute @workgroup_size(#WORKGROUP_SIZE)
+fn global_merge_step(
+    @builtin(local_invocation_id) localInvocationId : vec3<u32>,
+    @builtin(workgroup_id) workgroupId : vec3<u32>,
+    @builtin(num_workgroups) numWorkgroups : vec3<u32>,
+) {
+    let gid: u32 = linear_workgroup_index(workgroupId, numWorkgroups) * u32(#WORKGROUP_SIZE) + localInvocationId.x;
+
+    if (gid >= arrayLength(&pairs) / 2u) { return; }
+
+    let i = first_compared_index(gid, step.compare_distance);
+    let a = pairs[i];
+    let b = pairs[i + step.compare_distance];
+    if (need_swap(a, b, (i & step.merge_size) == 0u)) {
+        pairs[i] = b;
+        pairs[i + step.compare_distance] = a;
+    }
+}
+
+@compute @workgroup_size(#WORKGROUP_SIZE)
+fn write_ids(
+    @builtin(local_invocation_id) localInvocationId : vec3<u32>,
+    @builtin(workgroup_id) workgroupId : vec3<u32>,
+    @builtin(num_workgroups) numWorkgroups : vec3<u32>,
+) {
+    let gid: u32 = linear_workgroup_index(workgroupId, numWorkgroups) * u32(#WORKGROUP_SIZE) + localInvocationId.x;
+
+    if (gid >= arrayLength(&sorting_idx)) { return; }
+
+    sorting_idx[gid] = pairs[gid].y;
+}
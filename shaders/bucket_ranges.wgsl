@@ -0,0 +1,23 @@
+@group(0) @binding(0) var<storage, read_write> counting : array<u32>;
+
+// [start, end) range of each bucket in the sorting id buffer
+@group(1) @binding(0) var<storage, read_write> bucket_ranges : array<vec2<u32>>;
+
+@compute @workgroup_size(#WORKGROUP_SIZE)
+// Must run after the scan (counting holds the inclusive prefix sum) and before the sort (which consumes counting)
+fn bucket_ranges_from_scan(
+    @builtin(local_invocation_id) localInvocationId : vec3<u32>,
+    @builtin(workgroup_id) workgroupId : vec3<u32>,
+    @builtin(num_workgroups) numWorkgroups : vec3<u32>,
+) {
+    let total = arrayLength(&counting);
+    let wid: u32 = linear_workgroup_index(workgroupId, numWorkgroups);
+    let gid: u32 = wid * u32(#WORKGROUP_SIZE) + localInvocationId.x;
+
+    if (gid >= total) { return; }
+
+    var start = 0u;
+    if (gid > 0u) { start = counting[gid - 1u]; }
+
+    bucket_ranges[gid] = vec2<u32>(start, counting[gid]);
+}
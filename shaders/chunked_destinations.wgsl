@@ -0,0 +1,22 @@
+// First step of the sorting pass of the chunked counting sort (the sorting id buffer can't be bound at once)
+// Consume the count buffer to get the destination of each value of a chunk
+
+@group(0) @binding(0) var<storage, read> values : array<u32>;
+@group(0) @binding(1) var<storage, read_write> counting : array<atomic<u32>>;
+
+@group(1) @binding(0) var<storage, read_write> destinations : array<u32>;
+
+@compute @workgroup_size(#WORKGROUP_SIZE)
+fn compute_destinations(
+    @builtin(local_invocation_id) localInvocationId : vec3<u32>,
+    @builtin(workgroup_id) workgroupId : vec3<u32>,
+    @builtin(num_workgroups) numWorkgroups : vec3<u32>,
+) {
+    let total = arrayLength(&values);
+    let wid: u32 = linear_workgroup_index(workgroupId, numWorkgroups);
+    let gid: u32 = wid * u32(#WORKGROUP_SIZE) + localInvocationId.x;
+
+    if (gid >= total) { return; }
+
+    destinations[gid] = atomicSub(&counting[values[gid]], 1u) - 1u;
+}
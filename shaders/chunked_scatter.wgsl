@@ -0,0 +1,28 @@
+// Second step of the sorting pass of the chunked counting sort
+// Write the ids of a chunk whose destination falls in the bound window of the sorting id buffer
+
+// Destinations of the chunk (bound read write with the layout of the destinations pass)
+@group(0) @binding(0) var<storage, read_write> destinations : array<u32>;
+@group(1) @binding(0) var<storage, read_write> sorting_idx : array<u32>;
+// Index of the first value of the chunk
+@group(2) @binding(0) var<uniform> chunk_start : u32;
+// Index of the first sorting id of the window
+@group(3) @binding(0) var<uniform> window_start : u32;
+
+@compute @workgroup_size(#WORKGROUP_SIZE)
+fn scatter_window(
+    @builtin(local_invocation_id) localInvocationId : vec3<u32>,
+    @builtin(workgroup_id) workgroupId : vec3<u32>,
+    @builtin(num_workgroups) numWorkgroups : vec3<u32>,
+) {
+    let total = arrayLength(&destinations);
+    let wid: u32 = linear_workgroup_index(workgroupId, numWorkgroups);
+    let gid: u32 = wid * u32(#WORKGROUP_SIZE) + localInvocationId.x;
+
+    if (gid >= total) { return; }
+
+    let destination = destinations[gid];
+    if (destination >= window_start && destination - window_start < arrayLength(&sorting_idx)) {
+        sorting_idx[destination - window_start] = chunk_start + gid;
+    }
+}
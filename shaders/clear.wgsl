@@ -0,0 +1,17 @@
+@group(0) @binding(0) var<storage, read_write> counting : array<u32>;
+
+@compute @workgroup_size(#WORKGROUP_SIZE)
+// Clear the bound range of the count buffer (which may be a sub-range of a bigger buffer) before counting
+fn clear_counts(
+    @builtin(local_invocation_id) localInvocationId : vec3<u32>,
+    @builtin(workgroup_id) workgroupId : vec3<u32>,
+    @builtin(num_workgroups) numWorkgroups : vec3<u32>,
+) {
+    let total = arrayLength(&counting);
+    let wid: u32 = linear_workgroup_index(workgroupId, numWorkgroups);
+    let gid: u32 = wid * u32(#WORKGROUP_SIZE) + localInvocationId.x;
+
+    if (gid >= total) { return; }
+
+    counting[gid] = 0u;
+}
@@ -1,11 +1,125 @@
+// Values are loaded as vec4<u32> when VECTORIZED_LOADS is 1 (ITEMS_PER_INVOCATION multiple of 4 and value count multiple of 4)
+// Values are bound read write when VALUES_READ_WRITE is 1 (they share their buffer with the count or sorting id binding)
+#if VALUES_READ_WRITE == 1
+#if VECTORIZED_LOADS == 1
+@group(0) @binding(0) var<storage, read_write> values : array<vec4<u32>>;
+#else
+@group(0) @binding(0) var<storage, read_write> values : array<u32>;
+#endif
+#else
+#if VECTORIZED_LOADS == 1
+@group(0) @binding(0) var<storage, read> values : array<vec4<u32>>;
+#else
 @group(0) @binding(0) var<storage, read> values : array<u32>;
+#endif
+#endif
 @group(0) @binding(1) var<storage, read_write> counting : array<atomic<u32>>;
 
+#ifdef PRIVATE_HISTOGRAM_SIZE
+// Workgroup histogram of a window of PRIVATE_HISTOGRAM_SIZE buckets starting at private_histogram_start
+// When the whole count buffer fits in it the window always starts at 0, otherwise it is centered on the key of the first element of the workgroup
+// (hybrid path: keys outside of the window are counted directly in global memory)
+var<workgroup> private_histogram : array<atomic<u32>, #PRIVATE_HISTOGRAM_SIZE>;
+var<workgroup> private_histogram_start : u32;
+#endif
+
+fn count_value(value: u32, window_start: u32) {
+#ifdef PRIVATE_HISTOGRAM_SIZE
+    if (value >= window_start && value - window_start < u32(#PRIVATE_HISTOGRAM_SIZE)) {
+        atomicAdd(&private_histogram[value - window_start], 1u);
+        return;
+    }
+#endif
+    atomicAdd(&counting[value], 1u);
+}
+
+fn count_key(key: u32, window_start: u32) {
+#ifdef DIGIT_COUNT
+    // Histograms of every RADIX_BITS digit of the KEY_BITS low bits of the key (least significant first) at once, one after the other in the count buffer
+    let radix = 1u << u32(#RADIX_BITS);
+    let sorted_bits = key & (0xffffffffu >> (32u - u32(#KEY_BITS)));
+    for (var digit = 0u; digit < u32(#DIGIT_COUNT); digit++) {
+        count_value(digit * radix + ((sorted_bits >> (digit * u32(#RADIX_BITS))) & (radix - 1u)), window_start);
+    }
+#else
+    count_value(key, window_start);
+#endif
+}
+
+// Each workgroup handles WORKGROUP_SIZE * ITEMS_PER_INVOCATION consecutive values, loaded with a WORKGROUP_SIZE stride for coalescing
 @compute @workgroup_size(#WORKGROUP_SIZE)
-fn count(@builtin(global_invocation_id) GlobalInvocationID : vec3<u32>) {
+fn count(
+    @builtin(local_invocation_id) LocalInvocationID : vec3<u32>,
+    @builtin(workgroup_id) WorkgroupID : vec3<u32>,
+    @builtin(num_workgroups) NumWorkgroups : vec3<u32>,
+) {
+    let lid: u32 = LocalInvocationID.x;
+    let wid: u32 = linear_workgroup_index(WorkgroupID, NumWorkgroups);
+    let workgroup_size = u32(#WORKGROUP_SIZE);
+    let items_per_invocation = u32(#ITEMS_PER_INVOCATION);
+
+    var window_start = 0u;
+
+#ifdef PRIVATE_HISTOGRAM_SIZE
+    // No early return in this shader as every invocation must reach the workgroup barriers
+    let private_size = u32(#PRIVATE_HISTOGRAM_SIZE);
+
+    for (var i = lid; i < private_size; i += workgroup_size) {
+        atomicStore(&private_histogram[i], 0u);
+    }
+
+    if (lid == 0u) {
+        var start = 0u;
+        let first_index = wid * workgroup_size * items_per_invocation;
+#if VECTORIZED_LOADS == 1
+        if (first_index / 4u < arrayLength(&values)) {
+            let first_value = values[first_index / 4u].x;
+            start = first_value - min(first_value, private_size / 2u);
+        }
+#else
+        if (first_index < arrayLength(&values)) {
+            let first_value = values[first_index];
+            start = first_value - min(first_value, private_size / 2u);
+        }
+#endif
+        private_histogram_start = min(start, arrayLength(&counting) - private_size);
+    }
+    workgroupBarrier();
+
+    window_start = private_histogram_start;
+#endif
+
+#if VECTORIZED_LOADS == 1
     let total = arrayLength(&values);
-    let index: u32 = GlobalInvocationID.x;
-    if (index >= total) { return; }
+    for (var i = 0u; i < items_per_invocation / 4u; i++) {
+        let index = (wid * (items_per_invocation / 4u) + i) * workgroup_size + lid;
+        if (index < total) {
+            let value = values[index];
+            count_key(value.x, window_start);
+            count_key(value.y, window_start);
+            count_key(value.z, window_start);
+            count_key(value.w, window_start);
+        }
+    }
+#else
+    let total = arrayLength(&values);
+    for (var i = 0u; i < items_per_invocation; i++) {
+        let index = (wid * items_per_invocation + i) * workgroup_size + lid;
+        if (index < total) {
+            count_key(values[index], window_start);
+        }
+    }
+#endif
+
+#ifdef PRIVATE_HISTOGRAM_SIZE
+    workgroupBarrier();
 
-    atomicAdd(&counting[values[index]], 1u);
-}
\ No newline at end of file
+    // Flush the workgroup histogram once into the global one
+    for (var i = lid; i < private_size; i += workgroup_size) {
+        let count = atomicLoad(&private_histogram[i]);
+        if (count > 0u) {
+            atomicAdd(&counting[window_start + i], count);
+        }
+    }
+#endif
+}
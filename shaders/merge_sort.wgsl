@@ -0,0 +1,76 @@
+// Merge pass of the merge sort: merge each pair of sorted runs of run_size ids into a run of 2 * run_size ids
+// Expects `fn less(a: u32, b: u32) -> bool` comparing the elements of index a and b to be prepended (its resources are bound at group 2)
+// Each invocation writes ITEMS_PER_INVOCATION consecutive ids of a merged run, starting where the merge path crosses its first output diagonal
+// Ids of the left run come first on ties so the sort is stable
+
+struct MergePass {
+    run_size: u32,
+    // Number of invocations per pair of runs (ceil(2 * run_size / ITEMS_PER_INVOCATION))
+    invocations_per_run_pair: u32,
+}
+
+@group(0) @binding(0) var<storage, read> source_ids : array<u32>;
+@group(0) @binding(1) var<storage, read_write> destination_ids : array<u32>;
+
+@group(1) @binding(0) var<uniform> merge_pass : MergePass;
+
+@compute @workgroup_size(#WORKGROUP_SIZE)
+fn merge_runs(
+    @builtin(local_invocation_id) localInvocationId : vec3<u32>,
+    @builtin(workgroup_id) workgroupId : vec3<u32>,
+    @builtin(num_workgroups) numWorkgroups : vec3<u32>,
+) {
+    let total = arrayLength(&source_ids);
+    let wid: u32 = linear_workgroup_index(workgroupId, numWorkgroups);
+    let gid: u32 = wid * u32(#WORKGROUP_SIZE) + localInvocationId.x;
+
+    let run_size = merge_pass.run_size;
+    let left_start = (gid / merge_pass.invocations_per_run_pair) * 2u * run_size;
+    if (left_start >= total) { return; }
+
+    let right_start = left_start + run_size;
+    let left_len = min(run_size, total - left_start);
+    let right_len = select(0u, min(run_size, total - right_start), right_start < total);
+
+    let diagonal = (gid % merge_pass.invocations_per_run_pair) * u32(#ITEMS_PER_INVOCATION);
+    if (diagonal >= left_len + right_len) { return; }
+
+    // Number of left ids among the first `diagonal` merged ids
+    var low = select(0u, diagonal - right_len, diagonal > right_len);
+    var high = min(diagonal, left_len);
+    while (low < high) {
+        let mid = (low + high) / 2u;
+        if (!less(source_ids[right_start + diagonal - 1u - mid], source_ids[left_start + mid])) {
+            low = mid + 1u;
+        } else {
+            high = mid;
+        }
+    }
+
+    var left = low;
+    var right = diagonal - low;
+    let end = min(diagonal + u32(#ITEMS_PER_INVOCATION), left_len + right_len);
+    for (var output = diagonal; output < end; output++) {
+        if (left < left_len && (right >= right_len || !less(source_ids[right_start + right], source_ids[left_start + left]))) {
+            destination_ids[left_start + output] = source_ids[left_start + left];
+            left++;
+        } else {
+            destination_ids[left_start + output] = source_ids[right_start + right];
+            right++;
+        }
+    }
+}
+
+@compute @workgroup_size(#WORKGROUP_SIZE)
+fn init_ids(
+    @builtin(local_invocation_id) localInvocationId : vec3<u32>,
+    @builtin(workgroup_id) workgroupId : vec3<u32>,
+    @builtin(num_workgroups) numWorkgroups : vec3<u32>,
+) {
+    let wid: u32 = linear_workgroup_index(workgroupId, numWorkgroups);
+    let gid: u32 = wid * u32(#WORKGROUP_SIZE) + localInvocationId.x;
+
+    if (gid >= arrayLength(&destination_ids)) { return; }
+
+    destination_ids[gid] = gid;
+}
@@ -0,0 +1,29 @@
+// Needs morton_common.wgsl to be prepended
+
+#if MORTON_DIMENSION == 2
+@group(0) @binding(0) var<storage, read> positions : array<vec2<f32>>;
+#else
+@group(0) @binding(0) var<storage, read> positions : array<vec4<f32>>;
+#endif
+@group(0) @binding(1) var<storage, read_write> keys : array<u32>;
+
+@group(1) @binding(0) var<uniform> params : MortonParams;
+
+@compute @workgroup_size(#WORKGROUP_SIZE)
+fn compute_morton_keys(
+    @builtin(local_invocation_id) localInvocationId : vec3<u32>,
+    @builtin(workgroup_id) workgroupId : vec3<u32>,
+    @builtin(num_workgroups) numWorkgroups : vec3<u32>,
+) {
+    let total = arrayLength(&keys);
+    let wid: u32 = linear_workgroup_index(workgroupId, numWorkgroups);
+    let gid: u32 = wid * u32(#WORKGROUP_SIZE) + localInvocationId.x;
+
+    if (gid >= total) { return; }
+
+#if MORTON_DIMENSION == 2
+    keys[gid] = morton_code_2d(params, positions[gid]);
+#else
+    keys[gid] = morton_code_3d(params, positions[gid].xyz);
+#endif
+}
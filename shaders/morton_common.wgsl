@@ -0,0 +1,57 @@
+// Morton (Z-order) helpers, prepend this file to your own shader to compute Morton codes
+
+struct MortonParams {
+    bounds_min: vec3<f32>,
+    // Quantization of each axis (at most 10 in 3D and 16 in 2D), the code fits in 3 * bits (or 2 * bits) bits
+    bits_per_axis: u32,
+    bounds_max: vec3<f32>,
+    padding: u32,
+}
+
+// Insert two 0 bits between each of the 10 lower bits of v
+fn morton_expand_bits_3d(v: u32) -> u32 {
+    var x = v & 0x3ffu;
+    x = (x | (x << 16u)) & 0x030000ffu;
+    x = (x | (x << 8u)) & 0x0300f00fu;
+    x = (x | (x << 4u)) & 0x030c30c3u;
+    x = (x | (x << 2u)) & 0x09249249u;
+    return x;
+}
+
+// Insert one 0 bit between each of the 16 lower bits of v
+fn morton_expand_bits_2d(v: u32) -> u32 {
+    var x = v & 0xffffu;
+    x = (x | (x << 8u)) & 0x00ff00ffu;
+    x = (x | (x << 4u)) & 0x0f0f0f0fu;
+    x = (x | (x << 2u)) & 0x33333333u;
+    x = (x | (x << 1u)) & 0x55555555u;
+    return x;
+}
+
+// Morton code of integer coordinates (x is the most significant axis)
+fn morton_encode_3d(coord: vec3<u32>) -> u32 {
+    return (morton_expand_bits_3d(coord.x) << 2u) | (morton_expand_bits_3d(coord.y) << 1u) | morton_expand_bits_3d(coord.z);
+}
+
+fn morton_encode_2d(coord: vec2<u32>) -> u32 {
+    return (morton_expand_bits_2d(coord.x) << 1u) | morton_expand_bits_2d(coord.y);
+}
+
+// Quantize a position into [0, 2^bits_per_axis) on each axis, positions outside the bounds are clamped
+fn morton_quantize_3d(params: MortonParams, position: vec3<f32>) -> vec3<u32> {
+    let extent = max(params.bounds_max - params.bounds_min, vec3<f32>(1e-20));
+    let normalized = clamp((position - params.bounds_min) / extent, vec3<f32>(0.0), vec3<f32>(1.0));
+    let cell_count = 1u << params.bits_per_axis;
+    return min(vec3<u32>(normalized * f32(cell_count)), vec3<u32>(cell_count - 1u));
+}
+
+fn morton_quantize_2d(params: MortonParams, position: vec2<f32>) -> vec2<u32> {
+    let extent = max(params.bounds_max.xy - params.bounds_min.xy, vec2<f32>(1e-20));
+    let normalized = clamp((position - params.bounds_min.xy) / extent, vec2<f32>(0.0), vec2<f32>(1.0));
+    let cell_count = 1u << params.bits_per_axis;
+    return min(vec2<u32>(normalized * f32(cell_count)), vec2<u32>(cell_count - 1u));
+}
+
+fn morton_code_3d(params: MortonParams, position: vec3<f32>) -> u32 { return morton_encode_3d(morton_quantize_3d(params, position)); }
+
+fn morton_code_2d(params: MortonParams, position: vec2<f32>) -> u32 { return morton_encode_2d(morton_quantize_2d(params, position)); }
@@ -0,0 +1,146 @@
+// Scatter pass of the Onesweep radix sort for one digit (the digit histograms are computed and scanned upfront for every digit)
+// Each workgroup handles a tile of WORKGROUP_SIZE keys and finds the offset of its keys in each bucket with a chained scan:
+// it publishes its bucket counts in the tile status buffer then looks back at the status of the previous tiles until one holds an inclusive prefix
+// Tiles are numbered in launch order (atomic counter) so a workgroup only waits for tiles that already started
+// WebGPU doesn't guarantee that a started tile makes progress while others wait for it, so each look back spins for at most MAX_SPIN_COUNT loads
+// in total: once they are spent, the previous tiles that haven't published their status are counted by the waiting workgroup itself
+// Keys are ranked by their index in the tile so each pass (and the whole sort) is stable
+
+struct RadixPass {
+    // Digit (of RADIX_BITS bits) sorted by this pass
+    digit: u32,
+}
+
+@group(0) @binding(0) var<storage, read> keys_in : array<u32>;
+@group(0) @binding(1) var<storage, read> ids_in : array<u32>;
+@group(0) @binding(2) var<storage, read_write> keys_out : array<u32>;
+@group(0) @binding(3) var<storage, read_write> ids_out : array<u32>;
+// Inclusive scan of the digit histograms laid out one after the other (so digit d histogram is offset by d * key count)
+@group(0) @binding(4) var<storage, read> digit_histograms : array<u32>;
+// Tile counter followed by the status of each (tile, bucket): flag in the 2 high bits, count or inclusive prefix in the others
+@group(0) @binding(5) var<storage, read_write> tile_status : array<atomic<u32>>;
+
+@group(1) @binding(0) var<uniform> radix_pass : RadixPass;
+
+const FLAG_AGGREGATE: u32 = 0x40000000u;
+const FLAG_INCLUSIVE: u32 = 0x80000000u;
+const VALUE_MASK: u32 = 0x3fffffffu;
+const MAX_SPIN_COUNT: u32 = 1024u;
+// Only the KEY_BITS low bits of the keys are sorted
+const KEY_MASK: u32 = 0xffffffffu >> (32u - u32(#KEY_BITS));
+
+var<workgroup> tile_index : u32;
+var<workgroup> tile_digits : array<u32, #WORKGROUP_SIZE>;
+var<workgroup> tile_histogram : array<atomic<u32>, #RADIX>;
+// Number of keys of the previous tiles in each bucket
+var<workgroup> tile_offsets : array<u32, #RADIX>;
+
+fn key_digit(key: u32) -> u32 {
+    return ((key & KEY_MASK) >> (radix_pass.digit * u32(#RADIX_BITS))) & (u32(#RADIX) - 1u);
+}
+
+// Fallback of the look back: number of keys of a (full) previous tile in the bucket
+fn tile_bucket_count(tile: u32, bucket: u32) -> u32 {
+    let workgroup_size = u32(#WORKGROUP_SIZE);
+    var count = 0u;
+    for (var i = 0u; i < workgroup_size; i++) {
+        if (key_digit(keys_in[tile * workgroup_size + i]) == bucket) { count++; }
+    }
+    return count;
+}
+
+@compute @workgroup_size(#WORKGROUP_SIZE)
+fn scatter(
+    @builtin(local_invocation_id) localInvocationId : vec3<u32>,
+) {
+    let lid: u32 = localInvocationId.x;
+    let workgroup_size = u32(#WORKGROUP_SIZE);
+    let radix = u32(#RADIX);
+    let total = arrayLength(&keys_in);
+
+    if (lid == 0u) {
+        tile_index = atomicAdd(&tile_status[0], 1u);
+    }
+    for (var bucket = lid; bucket < radix; bucket += workgroup_size) {
+        atomicStore(&tile_histogram[bucket], 0u);
+    }
+    let tile = workgroupUniformLoad(&tile_index);
+
+    // Extra workgroups of a folded dispatch
+    if (tile * workgroup_size >= total) { return; }
+
+    let gid = tile * workgroup_size + lid;
+    let valid = gid < total;
+    var key = 0u;
+    var id = 0u;
+    // Invalid keys get the out of range digit RADIX
+    var digit = radix;
+    if (valid) {
+        key = keys_in[gid];
+#if FIRST_PASS == 1
+        id = gid;
+#else
+        id = ids_in[gid];
+#endif
+        digit = key_digit(key);
+        atomicAdd(&tile_histogram[digit], 1u);
+    }
+    tile_digits[lid] = digit;
+    workgroupBarrier();
+
+    for (var bucket = lid; bucket < radix; bucket += workgroup_size) {
+        let count = atomicLoad(&tile_histogram[bucket]);
+        let status_index = 1u + tile * radix + bucket;
+
+        if (tile == 0u) {
+            atomicStore(&tile_status[status_index], FLAG_INCLUSIVE | count);
+            tile_offsets[bucket] = 0u;
+            continue;
+        }
+
+        atomicStore(&tile_status[status_index], FLAG_AGGREGATE | count);
+
+        var prefix = 0u;
+        var look_back_tile = tile - 1u;
+        // Spin budget of the whole look back (not reset for each previous tile)
+        var spin_count = 0u;
+        loop {
+            let status = atomicLoad(&tile_status[1u + look_back_tile * radix + bucket]);
+            if ((status & (FLAG_AGGREGATE | FLAG_INCLUSIVE)) == 0u) {
+                // Wait for the previous tile to publish its count, or count its keys once the spin budget is spent
+                if (spin_count < MAX_SPIN_COUNT) {
+                    spin_count++;
+                    continue;
+                }
+                prefix += tile_bucket_count(look_back_tile, bucket);
+            } else {
+                prefix += status & VALUE_MASK;
+                if ((status & FLAG_INCLUSIVE) != 0u) { break; }
+            }
+
+            // Tile 0 has no previous tile (it publishes an inclusive prefix right away, unless it has been counted by the fallback)
+            if (look_back_tile == 0u) { break; }
+            look_back_tile -= 1u;
+        }
+
+        atomicStore(&tile_status[status_index], FLAG_INCLUSIVE | (prefix + count));
+        tile_offsets[bucket] = prefix;
+    }
+    workgroupBarrier();
+
+    if (!valid) { return; }
+
+    var rank = 0u;
+    for (var i = 0u; i < lid; i++) {
+        if (tile_digits[i] == digit) { rank++; }
+    }
+
+    var bucket_start = 0u;
+    if (digit > 0u) {
+        bucket_start = digit_histograms[radix_pass.digit * radix + digit - 1u] - radix_pass.digit * total;
+    }
+
+    let destination = bucket_start + tile_offsets[digit] + rank;
+    keys_out[destination] = key;
+    ids_out[destination] = id;
+}
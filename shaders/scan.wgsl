@@ -14,17 +14,15 @@ var<workgroup> workgroup_memory : array<u32, #WORKGROUP_SIZE>;
 
 @compute @workgroup_size(#WORKGROUP_SIZE)
 fn workgroup_scan(
-    @builtin(global_invocation_id) globalInvocationId : vec3<u32>,
     @builtin(local_invocation_id) localInvocationId : vec3<u32>,
-    // @builtin(workgroup_id) workgroupId : vec3<u32>,
-    // @builtin(num_workgroups) numWorkgroups : vec3<u32>,
+    @builtin(workgroup_id) workgroupId : vec3<u32>,
+    @builtin(num_workgroups) numWorkgroups : vec3<u32>,
 ) {
     let total = arrayLength(&values);
 
-    let gid: u32 = globalInvocationId.x;
     let lid: u32 = localInvocationId.x;
-    // let wid: u32 = workgroupId.x;
-    // gid = wid * numWorkgroups.x + lid;
+    let wid: u32 = linear_workgroup_index(workgroupId, numWorkgroups);
+    let gid: u32 = wid * u32(#WORKGROUP_SIZE) + lid;
 
     var workgroup_stride: u32 = 1u;
     for (var i = 0u; i < u32(#SCAN_LEVEL); i++) {
@@ -58,13 +56,14 @@ fn workgroup_scan(
 
 @compute @workgroup_size(#WORKGROUP_SIZE)
 fn workgroup_propagate(
-    @builtin(global_invocation_id) globalInvocationId : vec3<u32>,
+    @builtin(local_invocation_id) localInvocationId : vec3<u32>,
     @builtin(workgroup_id) workgroupId : vec3<u32>,
+    @builtin(num_workgroups) numWorkgroups : vec3<u32>,
 ) {
     let total = arrayLength(&values);
 
-    let gid: u32 = globalInvocationId.x;
-    let wid: u32 = workgroupId.x;
+    let wid: u32 = linear_workgroup_index(workgroupId, numWorkgroups);
+    let gid: u32 = wid * u32(#WORKGROUP_SIZE) + localInvocationId.x;
 
     // Skip the first workgroup
     // TODO: do better by calling only the workgroup needed and shift the offset
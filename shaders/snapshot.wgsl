@@ -0,0 +1,18 @@
+@group(0) @binding(0) var<storage, read_write> counting : array<u32>;
+@group(1) @binding(0) var<storage, read_write> snapshot : array<u32>;
+
+@compute @workgroup_size(#WORKGROUP_SIZE)
+// Copy the bound range of the count buffer into a snapshot buffer (debug mode), so the module doesn't need to own the count buffer
+fn snapshot_counts(
+    @builtin(local_invocation_id) localInvocationId : vec3<u32>,
+    @builtin(workgroup_id) workgroupId : vec3<u32>,
+    @builtin(num_workgroups) numWorkgroups : vec3<u32>,
+) {
+    let total = arrayLength(&counting);
+    let wid: u32 = linear_workgroup_index(workgroupId, numWorkgroups);
+    let gid: u32 = wid * u32(#WORKGROUP_SIZE) + localInvocationId.x;
+
+    if (gid >= total) { return; }
+
+    snapshot[gid] = counting[gid];
+}
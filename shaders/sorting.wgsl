@@ -1,17 +1,57 @@
+// Values are bound read write when VALUES_READ_WRITE is 1 (they share their buffer with the count or sorting id binding)
+#if VALUES_READ_WRITE == 1
+#if VECTORIZED_LOADS == 1
+@group(0) @binding(0) var<storage, read_write> values : array<vec4<u32>>;
+#else
+@group(0) @binding(0) var<storage, read_write> values : array<u32>;
+#endif
+#else
+#if VECTORIZED_LOADS == 1
+@group(0) @binding(0) var<storage, read> values : array<vec4<u32>>;
+#else
 @group(0) @binding(0) var<storage, read> values : array<u32>;
+#endif
+#endif
 @group(0) @binding(1) var<storage, read_write> counting : array<atomic<u32>>;
 
 @group(1) @binding(0) var<storage, read_write> sorting_idx : array<u32>;
 
+fn sort_value(value: u32, index: u32) {
+    let count = atomicSub(&counting[value], 1u);
+    sorting_idx[count-1u] = index;
+}
+
 @compute @workgroup_size(#WORKGROUP_SIZE)
 // counting sort (not stable as done in parallel)
-fn sort (@builtin(global_invocation_id) globalInvocationId : vec3<u32>) {
+// Each workgroup handles WORKGROUP_SIZE * ITEMS_PER_INVOCATION consecutive values (same layout as the counting pass)
+fn sort (
+    @builtin(local_invocation_id) localInvocationId : vec3<u32>,
+    @builtin(workgroup_id) workgroupId : vec3<u32>,
+    @builtin(num_workgroups) numWorkgroups : vec3<u32>,
+) {
     let total = arrayLength(&values);
-    let gid: u32 = globalInvocationId.x;
+    let lid: u32 = localInvocationId.x;
+    let wid: u32 = linear_workgroup_index(workgroupId, numWorkgroups);
+    let workgroup_size = u32(#WORKGROUP_SIZE);
+    let items_per_invocation = u32(#ITEMS_PER_INVOCATION);
 
-    if (gid >= total) { return; }
-
-    let value = values[gid];
-    let count = atomicSub(&counting[value], 1u);
-    sorting_idx[count-1u] = gid;
-}
\ No newline at end of file
+#if VECTORIZED_LOADS == 1
+    for (var i = 0u; i < items_per_invocation / 4u; i++) {
+        let index = (wid * (items_per_invocation / 4u) + i) * workgroup_size + lid;
+        if (index < total) {
+            let value = values[index];
+            sort_value(value.x, 4u * index);
+            sort_value(value.y, 4u * index + 1u);
+            sort_value(value.z, 4u * index + 2u);
+            sort_value(value.w, 4u * index + 3u);
+        }
+    }
+#else
+    for (var i = 0u; i < items_per_invocation; i++) {
+        let index = (wid * items_per_invocation + i) * workgroup_size + lid;
+        if (index < total) {
+            sort_value(values[index], index);
+        }
+    }
+#endif
+}
@@ -0,0 +1,21 @@
+// Needs spatial_hash_grid_common.wgsl to be prepended
+
+@group(0) @binding(0) var<storage, read> positions : array<vec4<f32>>;
+@group(0) @binding(1) var<storage, read_write> cell_keys : array<u32>;
+
+@group(1) @binding(0) var<uniform> grid : SpatialHashGridParams;
+
+@compute @workgroup_size(#WORKGROUP_SIZE)
+fn compute_cell_keys(
+    @builtin(local_invocation_id) localInvocationId : vec3<u32>,
+    @builtin(workgroup_id) workgroupId : vec3<u32>,
+    @builtin(num_workgroups) numWorkgroups : vec3<u32>,
+) {
+    let total = arrayLength(&cell_keys);
+    let wid: u32 = linear_workgroup_index(workgroupId, numWorkgroups);
+    let gid: u32 = wid * u32(#WORKGROUP_SIZE) + localInvocationId.x;
+
+    if (gid >= total) { return; }
+
+    cell_keys[gid] = spatial_hash_grid_key(grid, spatial_hash_grid_cell(grid, positions[gid].xyz));
+}
@@ -0,0 +1,52 @@
+// Shared spatial hash grid helpers, prepend this file to your own shader to look up neighbour cells
+//
+// Usage (with `cell_ranges` bound to `cell_range_buffer()` and `sorted_ids` to `sorting_id_buffer()`):
+//     let cell = spatial_hash_grid_cell(grid, position);
+//     for (var n = 0u; n < SPATIAL_HASH_GRID_NEIGHBOUR_COUNT; n++) {
+//         let key = spatial_hash_grid_neighbour_key(grid, cell, n);
+//         if (key == SPATIAL_HASH_GRID_INVALID_KEY) { continue; }
+//         let range = cell_ranges[key];
+//         for (var i = range.x; i < range.y; i++) { let particle = sorted_ids[i]; }
+//     }
+//
+// With a hashed grid several neighbour cells can share the same key, so the same particle may be visited more than once
+
+struct SpatialHashGridParams {
+    origin: vec3<f32>,
+    cell_size: f32,
+    cell_count: vec3<u32>,
+    // 0 for a bounded grid of cell_count cells, otherwise the number of buckets of the hash table
+    hash_table_size: u32,
+}
+
+const SPATIAL_HASH_GRID_INVALID_KEY: u32 = 0xffffffffu;
+const SPATIAL_HASH_GRID_NEIGHBOUR_COUNT: u32 = 27u;
+
+// Integer coordinates of the cell containing the position (clamped inside a bounded grid)
+fn spatial_hash_grid_cell(grid: SpatialHashGridParams, position: vec3<f32>) -> vec3<i32> {
+    let cell = vec3<i32>(floor((position - grid.origin) / grid.cell_size));
+    if (grid.hash_table_size == 0u) {
+        return clamp(cell, vec3<i32>(0), vec3<i32>(grid.cell_count) - 1);
+    }
+    return cell;
+}
+
+// Key of a cell (index in the cell ranges), SPATIAL_HASH_GRID_INVALID_KEY for cells outside a bounded grid
+fn spatial_hash_grid_key(grid: SpatialHashGridParams, cell: vec3<i32>) -> u32 {
+    if (grid.hash_table_size == 0u) {
+        if (any(cell < vec3<i32>(0)) || any(cell >= vec3<i32>(grid.cell_count))) {
+            return SPATIAL_HASH_GRID_INVALID_KEY;
+        }
+        let coord = vec3<u32>(cell);
+        return coord.x + grid.cell_count.x * (coord.y + grid.cell_count.y * coord.z);
+    }
+
+    let coord = bitcast<vec3<u32>>(cell);
+    return ((coord.x * 73856093u) ^ (coord.y * 19349663u) ^ (coord.z * 83492791u)) % grid.hash_table_size;
+}
+
+// Key of the neighbour_index-th (in [0, 27)) cell of the 3x3x3 block centered on cell
+fn spatial_hash_grid_neighbour_key(grid: SpatialHashGridParams, cell: vec3<i32>, neighbour_index: u32) -> u32 {
+    let offset = vec3<i32>(vec3<u32>(neighbour_index % 3u, (neighbour_index / 3u) % 3u, neighbour_index / 9u)) - 1;
+    return spatial_hash_grid_key(grid, cell + offset);
+}
@@ -0,0 +1,78 @@
+// Check that the sorting ids are a permutation of [0, n) and that the keys are non-decreasing along them
+// The occurrences of each id are counted in the seen buffer (cleared beforehand) then duplicated ids, out of range ids and keys smaller than the previous one are violations
+// The ok flag is written by a single invocation once every id has been checked
+
+struct VerifyResult {
+    // 1 when the ids are a permutation sorting the keys
+    ok: atomic<u32>,
+    // Smallest index (in the sorting id buffer) of a violation, NO_VIOLATION if there is none
+    first_violation: atomic<u32>,
+    // Number of extra occurrences of the ids found more than once
+    duplicate_count: atomic<u32>,
+}
+
+@group(0) @binding(0) var<storage, read> keys : array<u32>;
+@group(0) @binding(1) var<storage, read> sorting_ids : array<u32>;
+@group(0) @binding(2) var<storage, read_write> seen : array<atomic<u32>>;
+@group(0) @binding(3) var<storage, read_write> result : VerifyResult;
+
+const NO_VIOLATION: u32 = 0xffffffffu;
+
+@compute @workgroup_size(1)
+fn init_result() {
+    atomicStore(&result.ok, 0u);
+    atomicStore(&result.first_violation, NO_VIOLATION);
+    atomicStore(&result.duplicate_count, 0u);
+}
+
+// Count the occurrences of each id
+@compute @workgroup_size(#WORKGROUP_SIZE)
+fn count_ids(
+    @builtin(local_invocation_id) localInvocationId : vec3<u32>,
+    @builtin(workgroup_id) workgroupId : vec3<u32>,
+    @builtin(num_workgroups) numWorkgroups : vec3<u32>,
+) {
+    let total = arrayLength(&keys);
+    let wid: u32 = linear_workgroup_index(workgroupId, numWorkgroups);
+    let gid: u32 = wid * u32(#WORKGROUP_SIZE) + localInvocationId.x;
+
+    if (gid >= total) { return; }
+
+    let id = sorting_ids[gid];
+    if (id < total && atomicAdd(&seen[id], 1u) > 0u) {
+        atomicAdd(&result.duplicate_count, 1u);
+    }
+}
+
+// Every occurrence of a duplicated id is a violation, so the first violation doesn't depend on the order of the atomic operations
+@compute @workgroup_size(#WORKGROUP_SIZE)
+fn verify(
+    @builtin(local_invocation_id) localInvocationId : vec3<u32>,
+    @builtin(workgroup_id) workgroupId : vec3<u32>,
+    @builtin(num_workgroups) numWorkgroups : vec3<u32>,
+) {
+    let total = arrayLength(&keys);
+    let wid: u32 = linear_workgroup_index(workgroupId, numWorkgroups);
+    let gid: u32 = wid * u32(#WORKGROUP_SIZE) + localInvocationId.x;
+
+    if (gid >= total) { return; }
+
+    let id = sorting_ids[gid];
+    if (id >= total || atomicLoad(&seen[id]) > 1u) {
+        atomicMin(&result.first_violation, gid);
+        return;
+    }
+
+    if (gid > 0u) {
+        let previous_id = sorting_ids[gid - 1u];
+        if (previous_id < total && keys[previous_id] > keys[id]) {
+            atomicMin(&result.first_violation, gid);
+        }
+    }
+}
+
+@compute @workgroup_size(1)
+fn finalize_result() {
+    let ok = atomicLoad(&result.first_violation) == NO_VIOLATION && atomicLoad(&result.duplicate_count) == 0u;
+    atomicStore(&result.ok, select(0u, 1u, ok));
+}
@@ -0,0 +1,7 @@
+// Workgroup helpers shared by the shaders of the crate, prepended to their source (see `with_common_wgsl`)
+
+// Linear index of the current workgroup
+// Dispatches with more than max_compute_workgroups_per_dimension workgroups are folded in 3D (see `fold_workgroup_count`), the extra workgroups are skipped by bounds checks
+fn linear_workgroup_index(workgroup_id: vec3<u32>, num_workgroups: vec3<u32>) -> u32 {
+    return workgroup_id.x + num_workgroups.x * (workgroup_id.y + num_workgroups.y * workgroup_id.z);
+}
@@ -0,0 +1,307 @@
+use std::{ops::Range, sync::Arc};
+
+use oxyde::{
+    wgpu::{self, util::DeviceExt},
+    wgpu_utils::{binding_builder, buffers},
+};
+
+use crate::{
+    create_clearing_pipeline, fold_workgroup_count, scan::ScanPipelines, validate_storage_binding, CountingSortDescriptor, CountingSortingError,
+    ErrorScope, WorkgroupSize,
+};
+
+// Independent array of a `GpuBatchedCountingSortModule`: the values [start, end) of the value buffer, with keys in [0, key_range)
+// Keys aren't checked: a key out of the key range is clamped to the last bucket (key_range - 1) so it never reaches the histogram of another entry
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BatchedSortEntry {
+    pub values: Range<u32>,
+    pub key_range: u32,
+}
+
+impl BatchedSortEntry {
+    pub fn new(values: Range<u32>, key_range: u32) -> Self { Self { values, key_range } }
+}
+
+// Entry as read by the shaders (see `BatchEntry` in batched.wgsl)
+#[repr(C)]
+#[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+struct GpuBatchEntry {
+    value_start: u32,
+    value_end: u32,
+    count_offset: u32,
+    key_range: u32,
+    values_before: u32,
+}
+
+// Counting sort of many small independent arrays of the same value buffer (e.g. one per emitter) in a single count, scan and sort sequence
+// instead of a `GpuCountingSortModule::dispatch_work` per array:
+// 1. The histogram of each entry is counted at its count offset in a shared count buffer (entries are laid out in the given order)
+// 2. The whole count buffer is scanned at once
+// 3. The ids of each entry are written into its own range of the sorting id buffer (ids are indices in the value buffer)
+//
+// Values out of every entry are ignored (their sorting ids are left untouched)
+// Keys aren't checked: a key out of the key range of its entry is sorted as `key_range - 1` (mixed with the keys of the last bucket),
+// so keys that may be out of range should be checked beforehand
+// As for `GpuCountingSortModule`, the sort isn't stable
+pub struct GpuBatchedCountingSortModule {
+    workgroup_size: u32,
+    max_workgroups_per_dimension: u32,
+    value_size: u32,
+    count_size: u32,
+
+    entries: Vec<BatchedSortEntry>,
+    count_offsets: Vec<u32>,
+
+    count_buffer: wgpu::Buffer,
+    sorting_id_buffer: wgpu::Buffer,
+    // Kept alive for the bind group
+    _entry_buffer: wgpu::Buffer,
+
+    bind_group: wgpu::BindGroup,
+    count_buffer_bind_group: wgpu::BindGroup,
+
+    clearing_pipeline: Arc<wgpu::ComputePipeline>,
+    counting_pipeline: Arc<wgpu::ComputePipeline>,
+    scan_pipelines: ScanPipelines,
+    sorting_pipeline: Arc<wgpu::ComputePipeline>,
+}
+
+impl GpuBatchedCountingSortModule {
+    // Entries shouldn't overlap, errors with `InvalidBatchEntry` otherwise
+    // Out-of-range keys aren't reported, they are sorted as `key_range - 1` of their entry
+    pub fn new(
+        device: &wgpu::Device,
+        values_buffer: &wgpu::Buffer,
+        entries: &[BatchedSortEntry],
+        workgroup_size: impl Into<WorkgroupSize>,
+    ) -> Result<Self, CountingSortingError> {
+        Self::from_descriptor(device, &CountingSortDescriptor::default().with_workgroup_size(workgroup_size), values_buffer, entries)
+    }
+
+    // Uses the label, workgroup size, output usages (of the count and sorting id buffers) and pipeline cache of the descriptor,
+    // the other options are rejected with `UnsupportedOption`
+    // Out-of-range keys aren't reported, they are sorted as `key_range - 1` of their entry
+    pub fn from_descriptor(
+        device: &wgpu::Device,
+        descriptor: &CountingSortDescriptor,
+        values_buffer: &wgpu::Buffer,
+        entries: &[BatchedSortEntry],
+    ) -> Result<Self, CountingSortingError> {
+        let limits = device.limits();
+        let workgroup_size = descriptor.validate(&limits)?;
+        descriptor.validate_base_options("batched counting sort")?;
+        let size_of_u32 = std::mem::size_of::<u32>() as wgpu::BufferAddress;
+
+        let values_size = validate_storage_binding(&limits, &values_buffer.as_entire_buffer_binding(), "Values buffer")?;
+        let value_size = (values_size / size_of_u32) as u32;
+
+        if entries.is_empty() {
+            return Err(CountingSortingError::EmptyInput("Batch entries"));
+        }
+
+        for (index, entry) in entries.iter().enumerate() {
+            if entry.key_range == 0 {
+                return Err(CountingSortingError::InvalidBatchEntry(index, "empty key range"));
+            }
+            if entry.values.start > entry.values.end || entry.values.end > value_size {
+                return Err(CountingSortingError::InvalidBatchEntry(index, "values out of the value buffer"));
+            }
+        }
+
+        // Empty entries have no value so they can't overlap another entry
+        let mut sorted_entries = (0..entries.len()).filter(|&index| !entries[index].values.is_empty()).collect::<Vec<_>>();
+        sorted_entries.sort_by_key(|&index| entries[index].values.start);
+        if let Some(window) = sorted_entries.windows(2).find(|window| entries[window[0]].values.end > entries[window[1]].values.start) {
+            return Err(CountingSortingError::InvalidBatchEntry(window[1], "values overlapping another entry"));
+        }
+
+        // Histograms and sorted values are laid out in the order of the entries
+        let mut count_size = 0u64;
+        let mut value_count = 0u32;
+        let mut gpu_entries = entries
+            .iter()
+            .map(|entry| {
+                let gpu_entry = GpuBatchEntry {
+                    value_start: entry.values.start,
+                    value_end: entry.values.end,
+                    count_offset: count_size as u32,
+                    key_range: entry.key_range,
+                    values_before: value_count,
+                };
+                count_size += entry.key_range as u64;
+                value_count += entry.values.len() as u32;
+                gpu_entry
+            })
+            .collect::<Vec<_>>();
+        let count_offsets = gpu_entries.iter().map(|gpu_entry| gpu_entry.count_offset).collect::<Vec<_>>();
+
+        let count_buffer_size = count_size * size_of_u32;
+        if count_buffer_size > limits.max_storage_buffer_binding_size as wgpu::BufferAddress {
+            return Err(CountingSortingError::DeviceLimitExceeded(
+                count_buffer_size,
+                limits.max_storage_buffer_binding_size as wgpu::BufferAddress,
+                "max_storage_buffer_binding_size",
+            ));
+        }
+        let count_size = count_size as u32;
+
+        // The shaders look the entry of each value up by value_start, empty entries are left out so they never hide another one
+        // (their histogram is still laid out in the count buffer), an empty entry is kept when all of them are as a binding can't be empty
+        gpu_entries = sorted_entries.iter().map(|&index| gpu_entries[index]).collect();
+        if gpu_entries.is_empty() {
+            gpu_entries.push(bytemuck::Zeroable::zeroed());
+        }
+
+        let error_scope = ErrorScope::push(device);
+
+        let count_buffer = buffers::create_buffer_for_size(
+            device,
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | descriptor.output_usages,
+            Some(&descriptor.label("batched count buffer")),
+            count_buffer_size,
+        );
+
+        let sorting_id_buffer = buffers::create_buffer_for_size(
+            device,
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | descriptor.output_usages,
+            Some(&descriptor.label("batched sorting id buffer")),
+            values_size,
+        );
+
+        let entry_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&descriptor.label("batched entry buffer")),
+            contents: bytemuck::cast_slice(&gpu_entries),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+
+        // init bind groups (the count buffer layout is the one of the clearing and scan pipelines)
+        let pipeline_cache = descriptor.pipeline_cache_or_private(device, Some("batched"));
+
+        let storage_binding_type = |read_only| wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        };
+
+        let bind_group_layout_with_desc = pipeline_cache.get_or_create_bind_group_layout(device, "batched", || {
+            binding_builder::BindGroupLayoutBuilder::new()
+                .add_binding_compute(storage_binding_type(true))
+                .add_binding_compute(storage_binding_type(false))
+                .add_binding_compute(storage_binding_type(true))
+                .add_binding_compute(storage_binding_type(false))
+        });
+
+        let bind_group = binding_builder::BindGroupBuilder::new(&bind_group_layout_with_desc)
+            .resource(values_buffer.as_entire_binding())
+            .resource(count_buffer.as_entire_binding())
+            .resource(entry_buffer.as_entire_binding())
+            .resource(sorting_id_buffer.as_entire_binding())
+            .create(device, Some(&descriptor.label("batched bind_group")));
+
+        let count_buffer_bind_group = binding_builder::BindGroupBuilder::new(pipeline_cache.storage_buffer_bind_group_layout())
+            .resource(count_buffer.as_entire_binding())
+            .create(device, Some(&descriptor.label("batched count_buffer_bind_group")));
+
+        // Pipelines
+        let clearing_pipeline = create_clearing_pipeline(device, &pipeline_cache, workgroup_size)?;
+        let scan_pipelines = ScanPipelines::new(device, &pipeline_cache, count_size, workgroup_size)?;
+        let create_pipeline = |entry_point| {
+            pipeline_cache.get_or_create(
+                device,
+                "batched",
+                include_str!("../shaders/batched.wgsl"),
+                entry_point,
+                &[("WORKGROUP_SIZE", workgroup_size)],
+                &[&bind_group_layout_with_desc.layout],
+            )
+        };
+        let counting_pipeline = create_pipeline("count_batched")?;
+        let sorting_pipeline = create_pipeline("sort_batched")?;
+
+        error_scope.pop()?;
+
+        Ok(Self {
+            workgroup_size,
+            max_workgroups_per_dimension: limits.max_compute_workgroups_per_dimension,
+            value_size,
+            count_size,
+
+            entries: entries.to_vec(),
+            count_offsets,
+
+            count_buffer,
+            sorting_id_buffer,
+            _entry_buffer: entry_buffer,
+
+            bind_group,
+            count_buffer_bind_group,
+
+            clearing_pipeline,
+            counting_pipeline,
+            scan_pipelines,
+            sorting_pipeline,
+        })
+    }
+
+    pub fn dispatch_work(&self, encoder: &mut wgpu::CommandEncoder) {
+        log::trace!(
+            "[GpuBatchedCountingSortModule] {} entries (for value buffer of {} and counting buffer of {})",
+            self.entries.len(),
+            self.value_size,
+            self.count_size
+        );
+
+        let [value_x, value_y, value_z] = fold_workgroup_count(self.value_size.div_ceil(self.workgroup_size), self.max_workgroups_per_dimension);
+        encoder.push_debug_group("Batched Counting Sort");
+
+        {
+            let count_pass = &mut encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Batched Counting Pass"),
+                timestamp_writes: None,
+            });
+
+            count_pass.set_pipeline(&self.clearing_pipeline);
+            count_pass.set_bind_group(0, &self.count_buffer_bind_group, &[]);
+            let [x, y, z] = fold_workgroup_count(self.count_size.div_ceil(self.workgroup_size), self.max_workgroups_per_dimension);
+            count_pass.dispatch_workgroups(x, y, z);
+
+            count_pass.set_pipeline(&self.counting_pipeline);
+            count_pass.set_bind_group(0, &self.bind_group, &[]);
+            count_pass.dispatch_workgroups(value_x, value_y, value_z);
+        }
+
+        self.scan_pipelines
+            .dispatch(encoder, &self.count_buffer_bind_group, self.count_size, self.workgroup_size, self.max_workgroups_per_dimension, None);
+
+        {
+            let sort_pass = &mut encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Batched Sort Pass"),
+                timestamp_writes: None,
+            });
+
+            sort_pass.set_pipeline(&self.sorting_pipeline);
+            sort_pass.set_bind_group(0, &self.bind_group, &[]);
+            sort_pass.dispatch_workgroups(value_x, value_y, value_z);
+        }
+        encoder.pop_debug_group();
+    }
+
+    // Ids of the values of each entry in sorted order, written into the range of the entry
+    pub fn sorting_id_buffer(&self) -> &wgpu::Buffer { &self.sorting_id_buffer }
+
+    // Histograms of the entries laid out at their count offset, after `dispatch_work` each bucket holds
+    // its start offset plus the value count of the entries before (in the order of the entries)
+    pub fn count_buffer(&self) -> &wgpu::Buffer { &self.count_buffer }
+
+    // Offset of the histogram of each entry in the count buffer
+    pub fn count_offsets(&self) -> &[u32] { &self.count_offsets }
+
+    pub fn entries(&self) -> &[BatchedSortEntry] { &self.entries }
+
+    pub fn workgroup_size(&self) -> u32 { self.workgroup_size }
+
+    pub fn value_size(&self) -> u32 { self.value_size }
+
+    // Sum of the key ranges of the entries
+    pub fn count_size(&self) -> u32 { self.count_size }
+}
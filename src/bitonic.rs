@@ -0,0 +1,244 @@
+use std::sync::Arc;
+
+use oxyde::{
+    wgpu::{self, util::DeviceExt},
+    wgpu_utils::{binding_builder, buffers},
+};
+
+use crate::{fold_workgroup_count, validate_storage_binding, CountingSortDescriptor, CountingSortingError, ErrorScope, WorkgroupSize};
+
+// Merge step bigger than a block, run in global memory for each compare distance then in workgroup memory for the last ones
+struct MergeStep {
+    // One per compare distance from merge_size / 2 down to the block size
+    global_step_bind_groups: Vec<wgpu::BindGroup>,
+    local_merge_bind_group: wgpu::BindGroup,
+    // Kept alive for the bind groups
+    _step_buffers: Vec<wgpu::Buffer>,
+}
+
+// Bitonic sort of a buffer of u32 keys with the same conventions as `GpuCountingSortModule` (ids of the values in sorted order in a sorting id buffer)
+// Keys can use the whole u32 range, which suits small arrays (lights, UI quads, ...) with huge key ranges where counting sort is a poor fit
+// (key, id) pairs are padded to a power of two, blocks of 2 * workgroup_size pairs are sorted in workgroup memory then merged:
+// log2(padded size / block size) merges, each made of a global step per compare distance bigger than a block and a final merge in workgroup memory
+// Pairs are compared by key then by id so the sort is stable
+pub struct GpuBitonicSortModule {
+    workgroup_size: u32,
+    max_workgroups_per_dimension: u32,
+    value_size: u32,
+    padded_size: u32,
+
+    sorting_id_buffer: wgpu::Buffer,
+    // Kept alive for the bind group
+    _pair_buffer: wgpu::Buffer,
+
+    bind_group: wgpu::BindGroup,
+    merge_steps: Vec<MergeStep>,
+
+    init_pairs_pipeline: Arc<wgpu::ComputePipeline>,
+    local_sort_pipeline: Arc<wgpu::ComputePipeline>,
+    local_merge_pipeline: Arc<wgpu::ComputePipeline>,
+    global_merge_step_pipeline: Arc<wgpu::ComputePipeline>,
+    write_ids_pipeline: Arc<wgpu::ComputePipeline>,
+}
+
+impl GpuBitonicSortModule {
+    pub fn new(device: &wgpu::Device, values_buffer: &wgpu::Buffer, workgroup_size: impl Into<WorkgroupSize>) -> Result<Self, CountingSortingError> {
+        Self::from_descriptor(device, &CountingSortDescriptor::default().with_workgroup_size(workgroup_size), values_buffer)
+    }
+
+    // Uses the label, workgroup size, output usages (of the sorting id buffer) and pipeline cache of the descriptor,
+    // the other options are rejected with `UnsupportedOption`
+    pub fn from_descriptor(
+        device: &wgpu::Device,
+        descriptor: &CountingSortDescriptor,
+        values_buffer: &wgpu::Buffer,
+    ) -> Result<Self, CountingSortingError> {
+        let limits = device.limits();
+        let workgroup_size = descriptor.validate(&limits)?;
+        descriptor.validate_base_options("bitonic sort")?;
+        let size_of_u32 = std::mem::size_of::<u32>() as wgpu::BufferAddress;
+        let size_of_pair = 2 * size_of_u32;
+
+        let value_size = (validate_storage_binding(&limits, &values_buffer.as_entire_buffer_binding(), "Values buffer")? / size_of_u32) as u32;
+
+        // Blocks of (key, id) pairs sorted in workgroup memory
+        let block_size = 2 * workgroup_size;
+        let block_storage_size = block_size * size_of_pair as u32;
+        if block_storage_size > limits.max_compute_workgroup_storage_size {
+            return Err(CountingSortingError::WorkgroupSizeExceedsLimit(
+                block_storage_size,
+                limits.max_compute_workgroup_storage_size,
+                "max_compute_workgroup_storage_size",
+            ));
+        }
+
+        let padded_size = value_size.next_power_of_two().max(block_size);
+        let pair_buffer_size = padded_size as wgpu::BufferAddress * size_of_pair;
+        if pair_buffer_size > limits.max_storage_buffer_binding_size as wgpu::BufferAddress {
+            return Err(CountingSortingError::DeviceLimitExceeded(
+                pair_buffer_size,
+                limits.max_storage_buffer_binding_size as wgpu::BufferAddress,
+                "max_storage_buffer_binding_size",
+            ));
+        }
+
+        let error_scope = ErrorScope::push(device);
+
+        let sorting_id_buffer = buffers::create_buffer_for_size(
+            device,
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | descriptor.output_usages,
+            Some(&descriptor.label("bitonic sorting id buffer")),
+            value_size as wgpu::BufferAddress * size_of_u32,
+        );
+
+        let pair_buffer =
+            buffers::create_buffer_for_size(device, wgpu::BufferUsages::STORAGE, Some(&descriptor.label("bitonic pair buffer")), pair_buffer_size);
+
+        // init bind groups
+        let pipeline_cache = descriptor.pipeline_cache_or_private(device, Some("bitonic"));
+
+        let storage_binding_type = |read_only| wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        };
+
+        let bind_group_layout_with_desc = pipeline_cache.get_or_create_bind_group_layout(device, "bitonic", || {
+            binding_builder::BindGroupLayoutBuilder::new()
+                .add_binding_compute(storage_binding_type(true))
+                .add_binding_compute(storage_binding_type(false))
+                .add_binding_compute(storage_binding_type(false))
+        });
+        let step_bind_group_layout_with_desc = pipeline_cache.uniform_bind_group_layout();
+
+        let bind_group = binding_builder::BindGroupBuilder::new(&bind_group_layout_with_desc)
+            .resource(values_buffer.as_entire_binding())
+            .resource(pair_buffer.as_entire_binding())
+            .resource(sorting_id_buffer.as_entire_binding())
+            .create(device, Some(&descriptor.label("bitonic bind_group")));
+
+        let merge_steps = std::iter::successors(Some(2 * block_size), |merge_size| Some(merge_size * 2))
+            .take_while(|&merge_size| merge_size <= padded_size)
+            .map(|merge_size| {
+                // (merge_size, compare_distance) of each step, the last one is the merge in workgroup memory
+                let steps = std::iter::successors(Some(merge_size / 2), |compare_distance| Some(compare_distance / 2))
+                    .take_while(|&compare_distance| compare_distance >= block_size)
+                    .chain(std::iter::once(workgroup_size))
+                    .map(|compare_distance| {
+                        let step_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                            label: Some(&descriptor.label("bitonic step buffer")),
+                            contents: bytemuck::cast_slice(&[merge_size, compare_distance]),
+                            usage: wgpu::BufferUsages::UNIFORM,
+                        });
+                        let step_bind_group = binding_builder::BindGroupBuilder::new(step_bind_group_layout_with_desc)
+                            .resource(step_buffer.as_entire_binding())
+                            .create(
+                                device,
+                                Some(&descriptor.label(&format!("bitonic step_bind_group ({}, {})", merge_size, compare_distance))),
+                            );
+                        (step_buffer, step_bind_group)
+                    })
+                    .collect::<Vec<_>>();
+
+                let (step_buffers, mut global_step_bind_groups): (Vec<_>, Vec<_>) = steps.into_iter().unzip();
+                let local_merge_bind_group = global_step_bind_groups.pop().unwrap();
+                MergeStep {
+                    global_step_bind_groups,
+                    local_merge_bind_group,
+                    _step_buffers: step_buffers,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // Pipelines
+        let shader_defines = [("WORKGROUP_SIZE", workgroup_size), ("BLOCK_SIZE", block_size)];
+        let create_pipeline = |entry_point, bind_group_layouts: &[&wgpu::BindGroupLayout]| {
+            pipeline_cache.get_or_create(device, "bitonic", include_str!("../shaders/bitonic.wgsl"), entry_point, &shader_defines, bind_group_layouts)
+        };
+
+        let init_pairs_pipeline = create_pipeline("init_pairs", &[&bind_group_layout_with_desc.layout])?;
+        let local_sort_pipeline = create_pipeline("local_sort", &[&bind_group_layout_with_desc.layout])?;
+        let local_merge_pipeline = create_pipeline("local_merge", &[&bind_group_layout_with_desc.layout, &step_bind_group_layout_with_desc.layout])?;
+        let global_merge_step_pipeline =
+            create_pipeline("global_merge_step", &[&bind_group_layout_with_desc.layout, &step_bind_group_layout_with_desc.layout])?;
+        let write_ids_pipeline = create_pipeline("write_ids", &[&bind_group_layout_with_desc.layout])?;
+
+        error_scope.pop()?;
+
+        Ok(Self {
+            workgroup_size,
+            max_workgroups_per_dimension: limits.max_compute_workgroups_per_dimension,
+            value_size,
+            padded_size,
+
+            sorting_id_buffer,
+            _pair_buffer: pair_buffer,
+
+            bind_group,
+            merge_steps,
+
+            init_pairs_pipeline,
+            local_sort_pipeline,
+            local_merge_pipeline,
+            global_merge_step_pipeline,
+            write_ids_pipeline,
+        })
+    }
+
+    pub fn dispatch_work(&self, encoder: &mut wgpu::CommandEncoder) {
+        log::trace!(
+            "[GpuBitonicSortModule] {} merge steps of {} padded values (for value buffer of {})",
+            self.merge_steps.len(),
+            self.padded_size,
+            self.value_size
+        );
+
+        // Every pipeline but the init and write ones handles 2 pairs per invocation
+        let block_count = self.padded_size / (2 * self.workgroup_size);
+        let [block_x, block_y, block_z] = fold_workgroup_count(block_count, self.max_workgroups_per_dimension);
+
+        encoder.push_debug_group("Bitonic Sort");
+
+        {
+            let sort_pass = &mut encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Bitonic Sort Pass"),
+                timestamp_writes: None,
+            });
+
+            sort_pass.set_bind_group(0, &self.bind_group, &[]);
+
+            sort_pass.set_pipeline(&self.init_pairs_pipeline);
+            let [x, y, z] = fold_workgroup_count(self.padded_size / self.workgroup_size, self.max_workgroups_per_dimension);
+            sort_pass.dispatch_workgroups(x, y, z);
+
+            sort_pass.set_pipeline(&self.local_sort_pipeline);
+            sort_pass.dispatch_workgroups(block_x, block_y, block_z);
+
+            for merge_step in self.merge_steps.iter() {
+                sort_pass.set_pipeline(&self.global_merge_step_pipeline);
+                for step_bind_group in merge_step.global_step_bind_groups.iter() {
+                    sort_pass.set_bind_group(1, step_bind_group, &[]);
+                    sort_pass.dispatch_workgroups(block_x, block_y, block_z);
+                }
+
+                sort_pass.set_pipeline(&self.local_merge_pipeline);
+                sort_pass.set_bind_group(1, &merge_step.local_merge_bind_group, &[]);
+                sort_pass.dispatch_workgroups(block_x, block_y, block_z);
+            }
+
+            sort_pass.set_pipeline(&self.write_ids_pipeline);
+            let [x, y, z] = fold_workgroup_count(self.value_size.div_ceil(self.workgroup_size), self.max_workgroups_per_dimension);
+            sort_pass.dispatch_workgroups(x, y, z);
+        }
+        encoder.pop_debug_group();
+    }
+
+    pub fn sorting_id_buffer(&self) -> &wgpu::Buffer { &self.sorting_id_buffer }
+
+    pub fn workgroup_size(&self) -> u32 { self.workgroup_size }
+
+    pub fn value_size(&self) -> u32 { self.value_size }
+
+    // Power of two (at least 2 * workgroup_size) the values are padded to
+    pub fn padded_size(&self) -> u32 { self.padded_size }
+}
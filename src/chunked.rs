@@ -0,0 +1,349 @@
+use std::sync::Arc;
+
+use oxyde::{
+    wgpu::{self, util::DeviceExt},
+    wgpu_utils::{binding_builder, buffers},
+};
+
+use crate::{
+    create_clearing_pipeline, create_counting_pipeline, fold_workgroup_count, scan::ScanPipelines, validate_storage_binding, CountingSortDescriptor,
+    CountingSortingError, ErrorScope, WorkgroupSize,
+};
+
+// Values [start, start + len) of the value buffer (and of the sorting id buffer as windows use the same partition)
+struct Chunk {
+    start: u32,
+    len: u32,
+    counting_bind_group: wgpu::BindGroup,
+    destination_bind_group: wgpu::BindGroup,
+    // Window of the sorting id buffer with the same range as the chunk
+    sorting_id_window_bind_group: wgpu::BindGroup,
+    // Start of the chunk (and of its window), bound as chunk start or window start of the scatter
+    start_bind_group: wgpu::BindGroup,
+    // Kept alive for start_bind_group
+    _start_buffer: wgpu::Buffer,
+}
+
+// Upper bound of chunks as the scatter step reads the destinations of every chunk once per window (chunk count² dispatches)
+const MAX_CHUNK_COUNT: u32 = 64;
+
+// Counting sort of a value buffer bigger than max_storage_buffer_binding_size
+// The value buffer (and the sorting id buffer) are processed in chunks that can be bound:
+// 1. Counting the values of each chunk into the same count buffer
+// 2. Scanning the count buffer once (it should fit in a single binding)
+// 3. Computing the destination of each value chunk by chunk (consuming the count buffer like the regular sort step)
+// 4. Scattering the ids of each chunk into each window of the sorting id buffer
+//    (chunk count ^ 2 dispatches each reading the destinations of a chunk, the chunk count is capped to MAX_CHUNK_COUNT)
+//
+// As for `GpuCountingSortModule`, after `dispatch_work` the count buffer holds the start offset of each bucket
+pub struct GpuChunkedCountingSortModule {
+    workgroup_size: u32,
+    max_workgroups_per_dimension: u32,
+    value_size: u32,
+    count_size: u32,
+    chunk_size: u32,
+
+    sorting_id_buffer: wgpu::Buffer,
+    // Kept alive for the destination and scatter bind groups
+    _destination_buffer: wgpu::Buffer,
+
+    chunks: Vec<Chunk>,
+    count_buffer_bind_group: wgpu::BindGroup,
+
+    clearing_pipeline: Arc<wgpu::ComputePipeline>,
+    counting_pipeline: Arc<wgpu::ComputePipeline>,
+    scan_pipelines: ScanPipelines,
+    destination_pipeline: Arc<wgpu::ComputePipeline>,
+    scatter_pipeline: Arc<wgpu::ComputePipeline>,
+}
+
+// Largest chunk (in values) that can be bound at an aligned offset
+fn max_chunk_size(limits: &wgpu::Limits) -> u32 {
+    let alignment = limits.min_storage_buffer_offset_alignment;
+    (limits.max_storage_buffer_binding_size / alignment * alignment) / std::mem::size_of::<u32>() as u32
+}
+
+impl GpuChunkedCountingSortModule {
+    pub fn new(
+        device: &wgpu::Device,
+        values_buffer: &wgpu::Buffer,
+        count_buffer: &wgpu::Buffer,
+        workgroup_size: impl Into<WorkgroupSize>,
+    ) -> Result<Self, CountingSortingError> {
+        Self::from_descriptor(device, &CountingSortDescriptor::default().with_workgroup_size(workgroup_size), values_buffer, count_buffer, None)
+    }
+
+    // chunk_size (in values) should fit in max_storage_buffer_binding_size and be a multiple of min_storage_buffer_offset_alignment / 4
+    pub fn new_with_chunk_size(
+        device: &wgpu::Device,
+        values_buffer: &wgpu::Buffer,
+        count_buffer: &wgpu::Buffer,
+        workgroup_size: impl Into<WorkgroupSize>,
+        chunk_size: u32,
+    ) -> Result<Self, CountingSortingError> {
+        Self::from_descriptor(
+            device,
+            &CountingSortDescriptor::default().with_workgroup_size(workgroup_size),
+            values_buffer,
+            count_buffer,
+            Some(chunk_size),
+        )
+    }
+
+    // Same construction path as `GpuCountingSortModule::from_descriptor`: the label, workgroup size, output usages and pipeline cache
+    // of the descriptor are used (the chunks are loaded one value per invocation, the other options are rejected with `UnsupportedOption`)
+    // The chunk size defaults to the largest bindable one when None
+    pub fn from_descriptor(
+        device: &wgpu::Device,
+        descriptor: &CountingSortDescriptor,
+        values_buffer: &wgpu::Buffer,
+        count_buffer: &wgpu::Buffer,
+        chunk_size: Option<u32>,
+    ) -> Result<Self, CountingSortingError> {
+        let limits = device.limits();
+        let workgroup_size = descriptor.validate(&limits)?;
+        descriptor.validate_base_options("chunked counting sort")?;
+        let chunk_size = chunk_size.unwrap_or_else(|| max_chunk_size(&limits));
+        let size_of_u32 = std::mem::size_of::<u32>() as wgpu::BufferAddress;
+
+        // Only the count buffer is bound whole (the values are bound chunk by chunk)
+        let count_binding_size = validate_storage_binding(&limits, &count_buffer.as_entire_buffer_binding(), "Count buffer")?;
+
+        if !values_buffer.usage().contains(wgpu::BufferUsages::STORAGE) {
+            return Err(CountingSortingError::MissingBufferUsage(wgpu::BufferUsages::STORAGE, "Values buffer"));
+        }
+
+        if std::ptr::eq(values_buffer, count_buffer) {
+            return Err(CountingSortingError::OverlappingBindings("Values buffer", "Count buffer"));
+        }
+
+        let chunk_size_in_bytes = chunk_size as wgpu::BufferAddress * size_of_u32;
+        if chunk_size == 0
+            || chunk_size > max_chunk_size(&limits)
+            || !chunk_size_in_bytes.is_multiple_of(limits.min_storage_buffer_offset_alignment as wgpu::BufferAddress)
+        {
+            return Err(CountingSortingError::InvalidChunkSize(chunk_size, max_chunk_size(&limits)));
+        }
+
+        // Values are indexed with u32 and the sorting ids and destinations hold one u32 per value
+        let value_size = u32::try_from(values_buffer.size() / size_of_u32)
+            .map_err(|_| CountingSortingError::DeviceLimitExceeded(values_buffer.size() / size_of_u32, u32::MAX as u64, "Chunked value count"))?;
+        let output_buffer_size = value_size as wgpu::BufferAddress * size_of_u32;
+        if output_buffer_size > limits.max_buffer_size {
+            return Err(CountingSortingError::DeviceLimitExceeded(output_buffer_size, limits.max_buffer_size, "max_buffer_size"));
+        }
+        let count_size = (count_binding_size / size_of_u32) as u32;
+
+        let chunk_count = value_size.div_ceil(chunk_size);
+        if chunk_count > MAX_CHUNK_COUNT {
+            return Err(CountingSortingError::DeviceLimitExceeded(chunk_count as u64, MAX_CHUNK_COUNT as u64, "Chunked chunk count"));
+        }
+
+        let error_scope = ErrorScope::push(device);
+        let sorting_id_buffer = buffers::create_buffer_for_size(
+            device,
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | descriptor.output_usages,
+            Some(&descriptor.label("chunked sorting id buffer")),
+            output_buffer_size,
+        );
+
+        let destination_buffer = buffers::create_buffer_for_size(
+            device,
+            wgpu::BufferUsages::STORAGE,
+            Some(&descriptor.label("chunked destination buffer")),
+            output_buffer_size,
+        );
+
+        // init bind groups (the layouts are the ones of the cache shared with the counting and scan pipelines)
+        let pipeline_cache = descriptor.pipeline_cache_or_private(device, Some("chunked"));
+        let single_read_write_storage_buffer_bind_group_layout_with_desc = pipeline_cache.storage_buffer_bind_group_layout();
+        let read_write_bind_group_layout_with_desc = pipeline_cache.read_write_bind_group_layout();
+
+        let uniform_bind_group_layout_with_desc = pipeline_cache.uniform_bind_group_layout();
+
+        let chunk_binding = |buffer, start: u32, len: u32| wgpu::BufferBinding {
+            buffer,
+            offset: start as wgpu::BufferAddress * size_of_u32,
+            size: wgpu::BufferSize::new(len as wgpu::BufferAddress * size_of_u32),
+        };
+
+        let chunk_ranges = (0..value_size)
+            .step_by(chunk_size as usize)
+            .map(|start| (start, chunk_size.min(value_size - start)))
+            .collect::<Vec<_>>();
+
+        let chunks = chunk_ranges
+            .iter()
+            .map(|&(start, len)| {
+                let chunk_index = start / chunk_size;
+                let start_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some(&descriptor.label(&format!("chunked start buffer (chunk {})", chunk_index))),
+                    contents: bytemuck::bytes_of(&start),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                });
+
+                Chunk {
+                    start,
+                    len,
+                    counting_bind_group: binding_builder::BindGroupBuilder::new(read_write_bind_group_layout_with_desc)
+                        .resource(wgpu::BindingResource::Buffer(chunk_binding(values_buffer, start, len)))
+                        .resource(count_buffer.as_entire_binding())
+                        .create(device, Some(&descriptor.label(&format!("chunked counting_bind_group (chunk {})", chunk_index)))),
+                    destination_bind_group: binding_builder::BindGroupBuilder::new(single_read_write_storage_buffer_bind_group_layout_with_desc)
+                        .resource(wgpu::BindingResource::Buffer(chunk_binding(&destination_buffer, start, len)))
+                        .create(device, Some(&descriptor.label(&format!("chunked destination_bind_group (chunk {})", chunk_index)))),
+                    sorting_id_window_bind_group: binding_builder::BindGroupBuilder::new(single_read_write_storage_buffer_bind_group_layout_with_desc)
+                        .resource(wgpu::BindingResource::Buffer(chunk_binding(&sorting_id_buffer, start, len)))
+                        .create(device, Some(&descriptor.label(&format!("chunked sorting_id_window_bind_group (chunk {})", chunk_index)))),
+                    start_bind_group: binding_builder::BindGroupBuilder::new(uniform_bind_group_layout_with_desc)
+                        .resource(start_buffer.as_entire_binding())
+                        .create(device, Some(&descriptor.label(&format!("chunked start_bind_group (chunk {})", chunk_index)))),
+                    _start_buffer: start_buffer,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let count_buffer_bind_group = binding_builder::BindGroupBuilder::new(single_read_write_storage_buffer_bind_group_layout_with_desc)
+            .resource(count_buffer.as_entire_binding())
+            .create(device, Some(&descriptor.label("chunked count_buffer_bind_group")));
+
+        // Pipelines
+        let clearing_pipeline = create_clearing_pipeline(device, &pipeline_cache, workgroup_size)?;
+        let counting_pipeline = create_counting_pipeline(device, &pipeline_cache, chunk_size, count_size, workgroup_size, 1)?;
+
+        let scan_pipelines = ScanPipelines::new(device, &pipeline_cache, count_size, workgroup_size)?;
+
+        let destination_pipeline = pipeline_cache.get_or_create(
+            device,
+            "chunked_destinations",
+            include_str!("../shaders/chunked_destinations.wgsl"),
+            "compute_destinations",
+            &[("WORKGROUP_SIZE", workgroup_size)],
+            &[
+                &read_write_bind_group_layout_with_desc.layout,
+                &single_read_write_storage_buffer_bind_group_layout_with_desc.layout,
+            ],
+        )?;
+
+        let scatter_pipeline = pipeline_cache.get_or_create(
+            device,
+            "chunked_scatter",
+            include_str!("../shaders/chunked_scatter.wgsl"),
+            "scatter_window",
+            &[("WORKGROUP_SIZE", workgroup_size)],
+            &[
+                &single_read_write_storage_buffer_bind_group_layout_with_desc.layout,
+                &single_read_write_storage_buffer_bind_group_layout_with_desc.layout,
+                &uniform_bind_group_layout_with_desc.layout,
+                &uniform_bind_group_layout_with_desc.layout,
+            ],
+        )?;
+
+        error_scope.pop()?;
+
+        Ok(Self {
+            workgroup_size,
+            max_workgroups_per_dimension: limits.max_compute_workgroups_per_dimension,
+            value_size,
+            count_size,
+            chunk_size,
+
+            sorting_id_buffer,
+            _destination_buffer: destination_buffer,
+
+            chunks,
+            count_buffer_bind_group,
+
+            clearing_pipeline,
+            counting_pipeline,
+            scan_pipelines,
+            destination_pipeline,
+            scatter_pipeline,
+        })
+    }
+
+    pub fn dispatch_work(&self, encoder: &mut wgpu::CommandEncoder) {
+        log::trace!(
+            "[GpuChunkedCountingSortModule] {} chunks of {} values (for value buffer of {} and counting buffer of {})",
+            self.chunks.len(),
+            self.chunk_size,
+            self.value_size,
+            self.count_size
+        );
+
+        encoder.push_debug_group("Chunked Counting Sort");
+
+        {
+            let count_pass = &mut encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Chunked Counting Pass"),
+                timestamp_writes: None,
+            });
+
+            count_pass.set_pipeline(&self.clearing_pipeline);
+            count_pass.set_bind_group(0, &self.count_buffer_bind_group, &[]);
+            let [x, y, z] = fold_workgroup_count(self.count_size.div_ceil(self.workgroup_size), self.max_workgroups_per_dimension);
+            count_pass.dispatch_workgroups(x, y, z);
+
+            count_pass.set_pipeline(&self.counting_pipeline);
+            for chunk in self.chunks.iter() {
+                count_pass.set_bind_group(0, &chunk.counting_bind_group, &[]);
+                let [x, y, z] = fold_workgroup_count(chunk.len.div_ceil(self.workgroup_size), self.max_workgroups_per_dimension);
+                count_pass.dispatch_workgroups(x, y, z);
+            }
+        }
+
+        self.scan_pipelines
+            .dispatch(encoder, &self.count_buffer_bind_group, self.count_size, self.workgroup_size, self.max_workgroups_per_dimension, None);
+
+        {
+            let destination_pass = &mut encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Chunked Destinations Pass"),
+                timestamp_writes: None,
+            });
+
+            destination_pass.set_pipeline(&self.destination_pipeline);
+            for chunk in self.chunks.iter() {
+                destination_pass.set_bind_group(0, &chunk.counting_bind_group, &[]);
+                destination_pass.set_bind_group(1, &chunk.destination_bind_group, &[]);
+                let [x, y, z] = fold_workgroup_count(chunk.len.div_ceil(self.workgroup_size), self.max_workgroups_per_dimension);
+                destination_pass.dispatch_workgroups(x, y, z);
+            }
+        }
+
+        {
+            let scatter_pass = &mut encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Chunked Scatter Pass"),
+                timestamp_writes: None,
+            });
+
+            scatter_pass.set_pipeline(&self.scatter_pipeline);
+            for chunk in self.chunks.iter() {
+                scatter_pass.set_bind_group(0, &chunk.destination_bind_group, &[]);
+                scatter_pass.set_bind_group(2, &chunk.start_bind_group, &[]);
+                let [x, y, z] = fold_workgroup_count(chunk.len.div_ceil(self.workgroup_size), self.max_workgroups_per_dimension);
+                // Windows use the same partition as the chunks
+                for window in self.chunks.iter() {
+                    scatter_pass.set_bind_group(1, &window.sorting_id_window_bind_group, &[]);
+                    scatter_pass.set_bind_group(3, &window.start_bind_group, &[]);
+                    scatter_pass.dispatch_workgroups(x, y, z);
+                }
+            }
+        }
+        encoder.pop_debug_group();
+    }
+
+    pub fn sorting_id_buffer(&self) -> &wgpu::Buffer { &self.sorting_id_buffer }
+
+    pub fn chunk_count(&self) -> u32 { self.chunks.len() as u32 }
+
+    // [start, start + len) value ranges of the chunks (the sorting id buffer windows use the same ranges)
+    pub fn chunk_ranges(&self) -> impl Iterator<Item = std::ops::Range<u32>> + '_ {
+        self.chunks.iter().map(|chunk| chunk.start..chunk.start + chunk.len)
+    }
+
+    pub fn workgroup_size(&self) -> u32 { self.workgroup_size }
+
+    pub fn value_size(&self) -> u32 { self.value_size }
+
+    pub fn count_size(&self) -> u32 { self.count_size }
+}
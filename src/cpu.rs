@@ -0,0 +1,135 @@
+// CPU implementation of the counting sort, used as a fallback without compute support and as the oracle of the GPU results
+// Histogram, scan and sort are multi-threaded with the "rayon" feature, their results don't depend on the number of threads
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+use crate::{CountingSortBackend, CountingSortOutput, CountingSortingError};
+
+fn check_key_range(keys: &[u32], key_range: u32) -> Result<(), CountingSortingError> {
+    match keys.iter().find(|&&key| key >= key_range) {
+        Some(&key) => Err(CountingSortingError::KeyOutOfRange(key, key_range)),
+        None => Ok(()),
+    }
+}
+
+// One chunk per thread
+fn chunk_size(len: usize) -> usize {
+    #[cfg(feature = "rayon")]
+    let thread_count = rayon::current_num_threads();
+    #[cfg(not(feature = "rayon"))]
+    let thread_count = 1;
+    len.div_ceil(thread_count).max(1)
+}
+
+// Map each (chunk index, chunk) of keys
+fn map_chunks<T: Send>(keys: &[u32], f: impl Fn(usize, &[u32]) -> T + Sync + Send) -> Vec<T> {
+    #[cfg(feature = "rayon")]
+    let chunks = keys.par_chunks(chunk_size(keys.len()));
+    #[cfg(not(feature = "rayon"))]
+    let chunks = keys.chunks(chunk_size(keys.len()));
+    chunks.enumerate().map(|(index, chunk)| f(index, chunk)).collect()
+}
+
+fn chunk_histogram(keys: &[u32], key_range: u32) -> Vec<u32> {
+    let mut histogram = vec![0u32; key_range as usize];
+    for &key in keys {
+        histogram[key as usize] += 1;
+    }
+    histogram
+}
+
+fn sum_histograms(histograms: &[Vec<u32>], key_range: u32) -> Vec<u32> {
+    histograms.iter().fold(vec![0u32; key_range as usize], |mut sum, histogram| {
+        sum.iter_mut().zip(histogram).for_each(|(sum, count)| *sum += count);
+        sum
+    })
+}
+
+// Number of keys in each bucket (the count buffer after the counting pass)
+pub fn histogram(keys: &[u32], key_range: u32) -> Result<Vec<u32>, CountingSortingError> {
+    check_key_range(keys, key_range)?;
+    Ok(sum_histograms(&map_chunks(keys, |_, chunk| chunk_histogram(chunk, key_range)), key_range))
+}
+
+// Inclusive prefix sum, in place (the count buffer after the scan passes)
+pub fn inclusive_scan(values: &mut [u32]) {
+    #[cfg(feature = "rayon")]
+    {
+        let chunk_size = chunk_size(values.len());
+        values.par_chunks_mut(chunk_size).for_each(inclusive_scan_serial);
+
+        // Propagate the total of the previous chunks
+        let chunk_offsets = values
+            .chunks(chunk_size)
+            .scan(0u32, |offset, chunk| {
+                let chunk_offset = *offset;
+                *offset += chunk.last().copied().unwrap_or(0);
+                Some(chunk_offset)
+            })
+            .collect::<Vec<u32>>();
+        values
+            .par_chunks_mut(chunk_size)
+            .zip(chunk_offsets)
+            .for_each(|(chunk, offset)| chunk.iter_mut().for_each(|value| *value += offset));
+    }
+    #[cfg(not(feature = "rayon"))]
+    inclusive_scan_serial(values);
+}
+
+fn inclusive_scan_serial(values: &mut [u32]) {
+    for i in 1..values.len() {
+        values[i] += values[i - 1];
+    }
+}
+
+// Counting sort of keys in [0, key_range)
+// Within a bucket, ids are in decreasing order, as if the GPU sort pass had run sequentially (the GPU order isn't deterministic)
+pub fn counting_sort(keys: &[u32], key_range: u32) -> Result<CountingSortOutput, CountingSortingError> {
+    check_key_range(keys, key_range)?;
+
+    let chunk_histograms = map_chunks(keys, |_, chunk| chunk_histogram(chunk, key_range));
+    let mut scan = sum_histograms(&chunk_histograms, key_range);
+    inclusive_scan(&mut scan);
+
+    // Each chunk writes below the ids of the next chunks in its buckets, starting from the bucket end for the last one
+    let mut bucket_starts = scan.clone();
+    let mut chunk_ends = vec![Vec::new(); chunk_histograms.len()];
+    for (chunk_end, chunk_histogram) in chunk_ends.iter_mut().zip(&chunk_histograms).rev() {
+        *chunk_end = bucket_starts.clone();
+        bucket_starts.iter_mut().zip(chunk_histogram).for_each(|(start, count)| *start -= count);
+    }
+
+    let chunk_size = chunk_size(keys.len());
+    let destinations = map_chunks(keys, |chunk_index, chunk| {
+        let mut ends = chunk_ends[chunk_index].clone();
+        chunk
+            .iter()
+            .enumerate()
+            .map(|(i, &key)| {
+                ends[key as usize] -= 1;
+                (ends[key as usize], (chunk_index * chunk_size + i) as u32)
+            })
+            .collect::<Vec<_>>()
+    });
+
+    let mut sorting_ids = vec![0u32; keys.len()];
+    for (destination, id) in destinations.into_iter().flatten() {
+        sorting_ids[destination as usize] = id;
+    }
+
+    let bucket_ranges = bucket_starts.iter().zip(&scan).map(|(&start, &end)| [start, end]).collect();
+
+    Ok(CountingSortOutput {
+        sorting_ids,
+        bucket_starts,
+        bucket_ranges,
+    })
+}
+
+// `CountingSortBackend` running on the CPU (see `counting_sort`)
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CpuCountingSortBackend;
+
+impl CountingSortBackend for CpuCountingSortBackend {
+    fn counting_sort(&self, keys: &[u32], key_range: u32) -> Result<CountingSortOutput, CountingSortingError> { counting_sort(keys, key_range) }
+}
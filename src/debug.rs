@@ -0,0 +1,167 @@
+use std::{
+    future::Future,
+    io::Write,
+    path::Path,
+    sync::Arc,
+};
+
+use oxyde::{
+    wgpu,
+    wgpu_utils::{binding_builder, buffers},
+};
+
+use crate::{fold_workgroup_count, host, CountingSortDescriptor, CountingSortingError, SortPipelineCache};
+
+// Count buffer as it was after a stage of the counting sort, see `GpuCountingSortModule::read_debug_snapshots`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CountSnapshot {
+    // "count", "scan level i" or "propagate level i"
+    pub stage: String,
+    pub counts: Vec<u32>,
+}
+
+impl CountSnapshot {
+    // Raw little endian u32 counts
+    pub fn write_binary(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        std::fs::write(path, self.counts.iter().flat_map(|count| count.to_le_bytes()).collect::<Vec<u8>>())
+    }
+
+    // A "bucket,count" line per bucket
+    pub fn write_csv(&self, path: impl AsRef<Path>) -> std::io::Result<()> {
+        let mut writer = std::io::BufWriter::new(std::fs::File::create(path)?);
+        writeln!(writer, "bucket,count")?;
+        for (bucket, count) in self.counts.iter().enumerate() {
+            writeln!(writer, "{},{}", bucket, count)?;
+        }
+        writer.flush()
+    }
+}
+
+// Write each snapshot as "<index>_<stage>.bin" and "<index>_<stage>.csv" (e.g. "01_scan_level_0.csv") into directory, which is created if needed
+pub fn dump_count_snapshots(snapshots: &[CountSnapshot], directory: impl AsRef<Path>) -> std::io::Result<()> {
+    let directory = directory.as_ref();
+    std::fs::create_dir_all(directory)?;
+
+    for (index, snapshot) in snapshots.iter().enumerate() {
+        let file_stem = format!("{:02}_{}", index, snapshot.stage.replace(' ', "_"));
+        snapshot.write_binary(directory.join(format!("{}.bin", file_stem)))?;
+        snapshot.write_csv(directory.join(format!("{}.csv", file_stem)))?;
+    }
+    Ok(())
+}
+
+// Copies of the count buffer after the count pass and after each scan and propagate level (debug mode)
+// The copies are done by a compute pass reading the count bind group, so the count buffer can be provided by the caller
+pub(crate) struct CountSnapshots {
+    buffers: Vec<wgpu::Buffer>,
+    bind_groups: Vec<wgpu::BindGroup>,
+    pipeline: Arc<wgpu::ComputePipeline>,
+}
+
+impl CountSnapshots {
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        pipeline_cache: &SortPipelineCache,
+        descriptor: &CountingSortDescriptor,
+        workgroup_size: u32,
+        count_size: u32,
+        snapshot_count: u32,
+    ) -> Result<Self, CountingSortingError> {
+        let pipeline = pipeline_cache.get_or_create(
+            device,
+            "snapshot",
+            include_str!("../shaders/snapshot.wgsl"),
+            "snapshot_counts",
+            &[("WORKGROUP_SIZE", workgroup_size)],
+            &[&pipeline_cache.storage_buffer_bind_group_layout().layout, &pipeline_cache.storage_buffer_bind_group_layout().layout],
+        )?;
+
+        let mut count_snapshots = Self {
+            buffers: Vec::new(),
+            bind_groups: Vec::new(),
+            pipeline,
+        };
+        count_snapshots.create_buffers(device, pipeline_cache, descriptor, count_size, snapshot_count);
+        Ok(count_snapshots)
+    }
+
+    pub(crate) fn snapshot_count(&self) -> u32 { self.buffers.len() as u32 }
+
+    // Recreate the snapshot buffers (e.g. when the count size or the scan level count changes)
+    pub(crate) fn create_buffers(
+        &mut self,
+        device: &wgpu::Device,
+        pipeline_cache: &SortPipelineCache,
+        descriptor: &CountingSortDescriptor,
+        count_size: u32,
+        snapshot_count: u32,
+    ) {
+        self.buffers = (0..snapshot_count)
+            .map(|snapshot| {
+                buffers::create_buffer_for_size(
+                    device,
+                    wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+                    Some(&descriptor.label(&format!("count snapshot buffer {}", snapshot))),
+                    count_size as wgpu::BufferAddress * std::mem::size_of::<u32>() as wgpu::BufferAddress,
+                )
+            })
+            .collect();
+
+        self.bind_groups = self
+            .buffers
+            .iter()
+            .enumerate()
+            .map(|(snapshot, buffer)| {
+                binding_builder::BindGroupBuilder::new(pipeline_cache.storage_buffer_bind_group_layout())
+                    .resource(buffer.as_entire_binding())
+                    .create(device, Some(&descriptor.label(&format!("count_snapshot_bind_group {}", snapshot))))
+            })
+            .collect();
+    }
+
+    pub(crate) fn buffers(&self) -> &[wgpu::Buffer] { &self.buffers }
+
+    // Copy the count range bound by count_buffer_bind_group into the given snapshot buffer, in its own compute pass
+    pub(crate) fn record(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        snapshot: usize,
+        count_buffer_bind_group: &wgpu::BindGroup,
+        count_size: u32,
+        workgroup_size: u32,
+        max_workgroups_per_dimension: u32,
+    ) {
+        let snapshot_pass = &mut encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Count Snapshot Pass"),
+            timestamp_writes: None,
+        });
+
+        snapshot_pass.set_pipeline(&self.pipeline);
+        snapshot_pass.set_bind_group(0, count_buffer_bind_group, &[]);
+        snapshot_pass.set_bind_group(1, &self.bind_groups[snapshot], &[]);
+        let [x, y, z] = fold_workgroup_count(count_size.div_ceil(workgroup_size), max_workgroups_per_dimension);
+        snapshot_pass.dispatch_workgroups(x, y, z);
+    }
+
+    // Snapshots named by stage, in dispatch order
+    pub(crate) fn read<'a>(
+        &self,
+        device: &'a wgpu::Device,
+        queue: &wgpu::Queue,
+        stages: Vec<String>,
+    ) -> impl Future<Output = Result<Vec<CountSnapshot>, CountingSortingError>> + 'a {
+        let reads = self
+            .buffers
+            .iter()
+            .map(|buffer| host::read_buffer::<u32>(device, queue, buffer, 0, buffer.size()))
+            .collect::<Vec<_>>();
+
+        async move {
+            let mut snapshots = Vec::with_capacity(reads.len());
+            for (stage, read) in stages.into_iter().zip(reads) {
+                snapshots.push(CountSnapshot { stage, counts: read.await? });
+            }
+            Ok(snapshots)
+        }
+    }
+}
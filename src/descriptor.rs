@@ -0,0 +1,157 @@
+use std::sync::Arc;
+
+use oxyde::wgpu;
+
+use crate::{CountingSortingError, GpuCountingSortModule, SortPipelineCache, WorkgroupSize};
+
+pub(crate) fn prefixed_label(prefix: Option<&str>, name: &str) -> String {
+    match prefix {
+        Some(prefix) => format!("{} {}", prefix, name),
+        None => name.to_owned(),
+    }
+}
+
+// Configuration of a `GpuCountingSortModule`
+// Clone it to build sibling modules sharing the same configuration (e.g. `GpuCountingSortModule::descriptor`)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CountingSortDescriptor {
+    // Prefix of the labels of the buffers and bind groups created by the module, and of the private pipeline cache
+    // (pipelines of a shared `pipeline_cache` keep the label of that cache)
+    pub label: Option<String>,
+    pub workgroup_size: WorkgroupSize,
+    // Values handled by each invocation of the counting and sorting passes
+    pub items_per_invocation: u32,
+    // Output the [start, end) range of each bucket (see `GpuCountingSortModule::bucket_range_buffer`)
+    pub bucket_ranges: bool,
+    // Record the GPU duration of each stage (see `GpuCountingSortModule::read_stage_durations`), needs the TIMESTAMP_QUERY feature
+    pub timestamps: bool,
+    // Copy the count buffer after each stage (see `GpuCountingSortModule::read_debug_snapshots`)
+    pub debug_snapshots: bool,
+    // Usages added to the output buffers created by the module (sorting ids, bucket ranges and allocated count buffer), e.g. VERTEX or INDIRECT
+    // (MAP_READ can't be combined with STORAGE, read the outputs back with `read_sorting_ids` or `read_bucket_ranges`)
+    pub output_usages: wgpu::BufferUsages,
+    // Pipelines shared with the other modules built with the same cache (a private cache is created when None)
+    pub pipeline_cache: Option<Arc<SortPipelineCache>>,
+}
+
+impl Default for CountingSortDescriptor {
+    fn default() -> Self {
+        Self {
+            label: None,
+            workgroup_size: WorkgroupSize::Auto,
+            items_per_invocation: 1,
+            bucket_ranges: false,
+            timestamps: false,
+            debug_snapshots: false,
+            output_usages: wgpu::BufferUsages::empty(),
+            pipeline_cache: None,
+        }
+    }
+}
+
+impl CountingSortDescriptor {
+    pub fn with_label(mut self, label: impl Into<String>) -> Self {
+        self.label = Some(label.into());
+        self
+    }
+
+    pub fn with_workgroup_size(mut self, workgroup_size: impl Into<WorkgroupSize>) -> Self {
+        self.workgroup_size = workgroup_size.into();
+        self
+    }
+
+    pub fn with_items_per_invocation(mut self, items_per_invocation: u32) -> Self {
+        self.items_per_invocation = items_per_invocation;
+        self
+    }
+
+    pub fn with_bucket_ranges(mut self, bucket_ranges: bool) -> Self {
+        self.bucket_ranges = bucket_ranges;
+        self
+    }
+
+    pub fn with_timestamps(mut self, timestamps: bool) -> Self {
+        self.timestamps = timestamps;
+        self
+    }
+
+    pub fn with_debug_snapshots(mut self, debug_snapshots: bool) -> Self {
+        self.debug_snapshots = debug_snapshots;
+        self
+    }
+
+    pub fn with_output_usages(mut self, output_usages: wgpu::BufferUsages) -> Self {
+        self.output_usages = output_usages;
+        self
+    }
+
+    pub fn with_pipeline_cache(mut self, pipeline_cache: Arc<SortPipelineCache>) -> Self {
+        self.pipeline_cache = Some(pipeline_cache);
+        self
+    }
+
+    // Check the configuration against the device limits and return the resolved workgroup size
+    pub fn validate(&self, limits: &wgpu::Limits) -> Result<u32, CountingSortingError> {
+        if self.items_per_invocation == 0 {
+            return Err(CountingSortingError::InvalidItemsPerInvocation(self.items_per_invocation));
+        }
+
+        self.workgroup_size.resolve(limits)
+    }
+
+    // Reject the options only honoured by `GpuCountingSortModule` for the other modules built from a descriptor (e.g. "chunked counting sort")
+    // which only use its label, workgroup size, output usages and pipeline cache
+    pub(crate) fn validate_base_options(&self, module_name: &'static str) -> Result<(), CountingSortingError> {
+        let unsupported_options = [
+            ("items_per_invocation", self.items_per_invocation != 1),
+            ("bucket_ranges", self.bucket_ranges),
+            ("timestamps", self.timestamps),
+            ("debug_snapshots", self.debug_snapshots),
+        ];
+        match unsupported_options.into_iter().find(|&(_, is_set)| is_set) {
+            Some((option_name, _)) => Err(CountingSortingError::UnsupportedOption(module_name, option_name)),
+            None => Ok(()),
+        }
+    }
+
+    // Label of a resource created by the module, prefixed by the descriptor label
+    pub fn label(&self, name: &str) -> String { prefixed_label(self.label.as_deref(), name) }
+
+    // The shared pipeline cache, or a private one labelled with the label of the module (e.g. "chunked", None for the counting sort)
+    pub(crate) fn pipeline_cache_or_private(&self, device: &wgpu::Device, module_name: Option<&str>) -> Arc<SortPipelineCache> {
+        self.pipeline_cache.clone().unwrap_or_else(|| {
+            let label = module_name.map(|module_name| self.label(module_name)).or_else(|| self.label.clone());
+            Arc::new(SortPipelineCache::new(device, label.as_deref()))
+        })
+    }
+
+    pub fn build(
+        &self,
+        device: &wgpu::Device,
+        values_buffer: &wgpu::Buffer,
+        count_buffer: &wgpu::Buffer,
+    ) -> Result<GpuCountingSortModule, CountingSortingError> {
+        self.build_with_bindings(device, values_buffer.as_entire_buffer_binding(), count_buffer.as_entire_buffer_binding(), None)
+    }
+
+    // The module allocates its own count buffer of count_size buckets (see `GpuCountingSortModule::count_buffer`)
+    pub fn build_with_count_size(
+        &self,
+        device: &wgpu::Device,
+        values_buffer: &wgpu::Buffer,
+        count_size: u32,
+    ) -> Result<GpuCountingSortModule, CountingSortingError> {
+        GpuCountingSortModule::from_descriptor_with_count_size(device, self, values_buffer.as_entire_buffer_binding(), count_size, None)
+    }
+
+    // See `GpuCountingSortModule::new_with_bindings`
+    pub fn build_with_bindings(
+        &self,
+        device: &wgpu::Device,
+        values_binding: wgpu::BufferBinding,
+        count_binding: wgpu::BufferBinding,
+        sorting_id_binding: Option<wgpu::BufferBinding>,
+    ) -> Result<GpuCountingSortModule, CountingSortingError> {
+        GpuCountingSortModule::from_descriptor(device, self, values_binding, count_binding, sorting_id_binding)
+    }
+}
@@ -0,0 +1,64 @@
+use oxyde::wgpu;
+
+use crate::CountingSortingError;
+
+// Capture the validation and out of memory errors raised while creating GPU objects
+// so they are returned as `CountingSortingError` instead of going through `on_uncaptured_error`
+// The scopes are popped (and their errors dropped) if the guard is dropped on an early return
+//
+// Only native backends resolve the scopes right away: on WebGPU popping a scope needs the browser event loop,
+// which can't run while blocking, so no scope is pushed and the errors go to the uncaptured error handler of the device
+#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+pub(crate) struct ErrorScope<'a> {
+    device: &'a wgpu::Device,
+    popped: bool,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<'a> ErrorScope<'a> {
+    pub(crate) fn push(device: &'a wgpu::Device) -> Self {
+        device.push_error_scope(wgpu::ErrorFilter::OutOfMemory);
+        device.push_error_scope(wgpu::ErrorFilter::Validation);
+        Self { device, popped: false }
+    }
+
+    pub(crate) fn pop(mut self) -> Result<(), CountingSortingError> {
+        self.popped = true;
+        let validation_error = pollster::block_on(self.device.pop_error_scope());
+        let out_of_memory_error = pollster::block_on(self.device.pop_error_scope());
+        match validation_error.or(out_of_memory_error) {
+            Some(error) => Err(error.into()),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl<'a> ErrorScope<'a> {
+    pub(crate) fn push(device: &'a wgpu::Device) -> Self { Self { device, popped: false } }
+
+    pub(crate) fn pop(mut self) -> Result<(), CountingSortingError> {
+        self.popped = true;
+        Ok(())
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for ErrorScope<'_> {
+    fn drop(&mut self) {
+        if !self.popped {
+            // The scopes are popped as soon as pop_error_scope is called, their errors are dropped without waiting for them
+            drop(self.device.pop_error_scope());
+            drop(self.device.pop_error_scope());
+        }
+    }
+}
+
+impl From<wgpu::Error> for CountingSortingError {
+    fn from(error: wgpu::Error) -> Self {
+        match error {
+            wgpu::Error::Validation { description, .. } => CountingSortingError::PipelineValidation(description),
+            wgpu::Error::OutOfMemory { .. } => CountingSortingError::OutOfMemory(error.to_string()),
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use std::{
+    future::Future,
+    marker::PhantomData,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+use oxyde::wgpu;
+#[cfg(not(target_arch = "wasm32"))]
+use oxyde::wgpu::util::DeviceExt;
+
+use crate::CountingSortingError;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::{CountingSortDescriptor, GpuCountingSortModule, WorkgroupSize};
+
+// Result of the map_async callback of a staging buffer, with the waker of the task awaiting it
+#[derive(Default)]
+struct MapState {
+    result: Option<Result<(), wgpu::BufferAsyncError>>,
+    waker: Option<Waker>,
+}
+
+// Future resolved once the staging buffer of a readback is mapped (see `read_buffer`), woken by the map_async callback
+// Native backends only call the callback while the device is polled (see `block_on_readback`), on WebGPU the browser calls it
+pub(crate) struct BufferRead<T> {
+    staging_buffer: wgpu::Buffer,
+    state: Arc<Mutex<MapState>>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T: bytemuck::Pod> Future for BufferRead<T> {
+    type Output = Result<Vec<T>, CountingSortingError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.state.lock().unwrap();
+        match state.result.take() {
+            Some(Ok(())) => {
+                drop(state);
+                let values = bytemuck::cast_slice(&self.staging_buffer.slice(..).get_mapped_range()).to_vec();
+                self.staging_buffer.unmap();
+                Poll::Ready(Ok(values))
+            }
+            Some(Err(err)) => Poll::Ready(Err(CountingSortingError::BufferMapping(err.to_string()))),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+// Copy a range of a buffer (which needs COPY_SRC) into a staging buffer and read it back once the copy is done
+// The copy is submitted right away, the returned future only waits for the mapping (the readback path of every `read_*` method of the modules)
+// As for any wgpu mapping, on native backends the future only resolves while the device is polled (e.g. `device.poll` in the render loop
+// or a polling thread, see `block_on_readback`)
+pub fn read_buffer<'a, T: bytemuck::Pod>(
+    device: &'a wgpu::Device,
+    queue: &wgpu::Queue,
+    buffer: &wgpu::Buffer,
+    offset: wgpu::BufferAddress,
+    size: wgpu::BufferAddress,
+) -> impl Future<Output = Result<Vec<T>, CountingSortingError>> + 'a {
+    let staging_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("readback staging buffer"),
+        size,
+        usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("readback encoder") });
+    encoder.copy_buffer_to_buffer(buffer, offset, &staging_buffer, 0, size);
+    queue.submit(Some(encoder.finish()));
+
+    let state = Arc::new(Mutex::new(MapState::default()));
+    let callback_state = state.clone();
+    staging_buffer.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+        let mut state = callback_state.lock().unwrap();
+        state.result = Some(result);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    });
+
+    BufferRead {
+        staging_buffer,
+        state,
+        _marker: PhantomData,
+    }
+}
+
+// Block the current thread on a readback future (native only), polling the device until the staging buffers it waits for are mapped
+// The thread sleeps in `Maintain::Wait` until the submitted work is done instead of spinning
+#[cfg(not(target_arch = "wasm32"))]
+pub fn block_on_readback<F: Future>(device: &wgpu::Device, future: F) -> F::Output {
+    let mut future = std::pin::pin!(future);
+    let mut cx = Context::from_waker(Waker::noop());
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        device.poll(wgpu::Maintain::Wait);
+    }
+}
+
+// Sort keys in [0, key_range) from the host and return the ids of the keys in sorted order (blocking, native only)
+// Uploads the keys, sorts them with a `GpuCountingSortModule` and reads the sorting ids back, mostly meant for tools and tests
+#[cfg(not(target_arch = "wasm32"))]
+pub fn sort_ids(device: &wgpu::Device, queue: &wgpu::Queue, keys: &[u32], key_range: u32) -> Result<Vec<u32>, CountingSortingError> {
+    if let Some(&key) = keys.iter().find(|&&key| key >= key_range) {
+        return Err(CountingSortingError::KeyOutOfRange(key, key_range));
+    }
+
+    if keys.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let keys_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("sort_ids keys buffer"),
+        contents: bytemuck::cast_slice(keys),
+        usage: wgpu::BufferUsages::STORAGE,
+    });
+
+    let counting_sort_module = GpuCountingSortModule::new_with_count_size(device, &keys_buffer, key_range, WorkgroupSize::Auto)?;
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("sort_ids encoder") });
+    counting_sort_module.dispatch_work(&mut encoder);
+    queue.submit(Some(encoder.finish()));
+
+    block_on_readback(device, counting_sort_module.read_sorting_ids(device, queue))
+}
+
+// Results of a counting sort of host keys, as read back from `GpuCountingSortModule` after `dispatch_work`
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CountingSortOutput {
+    pub sorting_ids: Vec<u32>,
+    // The count buffer after the sort (see `GpuCountingSortModule::count_buffer`)
+    pub bucket_starts: Vec<u32>,
+    // See `GpuCountingSortModule::bucket_range_buffer`
+    pub bucket_ranges: Vec<[u32; 2]>,
+}
+
+// Counting sort of host keys in [0, key_range), so the GPU module and its CPU reference (`cpu::CpuCountingSortBackend`) are interchangeable
+// Only the order of the ids within a bucket may differ between backends (the GPU sort isn't stable)
+pub trait CountingSortBackend {
+    fn counting_sort(&self, keys: &[u32], key_range: u32) -> Result<CountingSortOutput, CountingSortingError>;
+}
+
+// `CountingSortBackend` uploading the keys and sorting them with a `GpuCountingSortModule` (blocking, native only)
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Clone, Copy, Debug)]
+pub struct GpuCountingSortBackend<'a> {
+    pub device: &'a wgpu::Device,
+    pub queue: &'a wgpu::Queue,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<'a> GpuCountingSortBackend<'a> {
+    pub fn new(device: &'a wgpu::Device, queue: &'a wgpu::Queue) -> Self { Self { device, queue } }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl CountingSortBackend for GpuCountingSortBackend<'_> {
+    fn counting_sort(&self, keys: &[u32], key_range: u32) -> Result<CountingSortOutput, CountingSortingError> {
+        if let Some(&key) = keys.iter().find(|&&key| key >= key_range) {
+            return Err(CountingSortingError::KeyOutOfRange(key, key_range));
+        }
+
+        if keys.is_empty() {
+            return Ok(CountingSortOutput {
+                sorting_ids: Vec::new(),
+                bucket_starts: vec![0; key_range as usize],
+                bucket_ranges: vec![[0, 0]; key_range as usize],
+            });
+        }
+
+        let keys_buffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("counting sort backend keys buffer"),
+            contents: bytemuck::cast_slice(keys),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+
+        let counting_sort_module = CountingSortDescriptor::default()
+            .with_label("counting sort backend")
+            .with_bucket_ranges(true)
+            .build_with_count_size(self.device, &keys_buffer, key_range)?;
+
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("counting sort backend encoder") });
+        counting_sort_module.dispatch_work(&mut encoder);
+        self.queue.submit(Some(encoder.finish()));
+
+        block_on_readback(self.device, async {
+            Ok(CountingSortOutput {
+                sorting_ids: counting_sort_module.read_sorting_ids(self.device, self.queue).await?,
+                bucket_starts: counting_sort_module.read_bucket_starts(self.device, self.queue).await?,
+                bucket_ranges: counting_sort_module.read_bucket_ranges(self.device, self.queue).await?,
+            })
+        })
+    }
+}
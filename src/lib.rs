@@ -1,11 +1,53 @@
-use log;
+use std::{future::Future, sync::Arc};
 
 use oxyde::{
     anyhow::Result,
     wgpu,
-    wgpu_utils::{binding_builder, buffers, ShaderComposer}
+    wgpu_utils::{binding_builder, buffers}
 };
 
+pub mod batched;
+mod bitonic;
+pub mod chunked;
+pub mod cpu;
+mod debug;
+mod descriptor;
+mod error_scope;
+mod host;
+mod merge_sort;
+mod morton;
+mod onesweep;
+mod pipeline_cache;
+mod scan;
+mod sorter;
+pub mod spatial_hash_grid;
+mod timestamps;
+mod verify;
+mod workgroup_size;
+
+use debug::CountSnapshots;
+use error_scope::ErrorScope;
+use scan::{AfterLevelPass, LevelPasses, ScanPipelines};
+use timestamps::StageTimestamps;
+
+pub use batched::{BatchedSortEntry, GpuBatchedCountingSortModule};
+pub use bitonic::GpuBitonicSortModule;
+pub use chunked::GpuChunkedCountingSortModule;
+pub use debug::{dump_count_snapshots, CountSnapshot};
+pub use descriptor::CountingSortDescriptor;
+#[cfg(not(target_arch = "wasm32"))]
+pub use host::{block_on_readback, sort_ids, GpuCountingSortBackend};
+pub use host::{read_buffer, CountingSortBackend, CountingSortOutput};
+pub use merge_sort::{GpuMergeSortModule, MergeSortComparator};
+pub use morton::{GpuMortonKeyModule, MortonDimension, MortonParams, MORTON_WGSL};
+pub use onesweep::GpuOnesweepRadixSortModule;
+pub use pipeline_cache::SortPipelineCache;
+pub use sorter::{GpuSorter, SorterRequirements};
+pub use spatial_hash_grid::{GpuSpatialHashGrid, SpatialHashGridLayout, SpatialHashGridParams, SPATIAL_HASH_GRID_WGSL};
+pub use timestamps::StageDuration;
+pub use verify::{GpuSortVerifyModule, SortVerification};
+pub use workgroup_size::WorkgroupSize;
+
 // Structure that handle the counting and sorting of a buffer of u32
 // The counting sorting is done in place and the sorting id are stored in a separate buffer
 // The counting sort is done in 3 steps:
@@ -17,28 +59,77 @@ use oxyde::{
 //
 // The Scan part is done using the Kogge-Stone method at the workgroup level
 // then using the strategy of "scan then propagate" by doing a second scan on the bigger values of each previous workgroup then propagating those values to get the final scan
+//
+// After `dispatch_work` the count buffer holds the start offset of each bucket (as the sort step decrements it down to the bucket start)
+// Use `with_bucket_ranges` to get an explicit buffer of [start, end) ranges per bucket instead of relying on this
 pub struct GpuCountingSortModule {
+    // Configuration the module has been built with (with its workgroup size resolved)
+    descriptor: CountingSortDescriptor,
     workgroup_size: u32,
+    max_workgroups_per_dimension: u32,
     value_size: u32,
+    // Offset in bytes of the values binding in its buffer (see `read_sorted_keys`)
+    values_offset: wgpu::BufferAddress,
     count_size: u32,
+    // The values are bound read write as they share their buffer with the count or sorting id binding
+    values_read_write: bool,
 
-    sorting_id_buffer: wgpu::Buffer,
+    // None when the count buffer is provided by the caller (see `new_with_count_size` to let the module allocate it)
+    count_buffer: Option<wgpu::Buffer>,
+    // None when the sorting ids are written into a binding provided by the caller
+    sorting_id_buffer: Option<wgpu::Buffer>,
 
     counting_bind_group: wgpu::BindGroup,
     sorting_bind_group: wgpu::BindGroup,
     count_buffer_bind_group: wgpu::BindGroup,
 
-    counting_pipeline: wgpu::ComputePipeline,
+    // Owner of the bind group layouts and pipelines (possibly shared with other modules)
+    pipeline_cache: Arc<SortPipelineCache>,
+
+    clearing_pipeline: Arc<wgpu::ComputePipeline>,
+    counting_pipeline: Arc<wgpu::ComputePipeline>,
+    scan_pipelines: ScanPipelines,
+    sorting_pipeline: Arc<wgpu::ComputePipeline>,
+
+    bucket_ranges: Option<BucketRanges>,
+    timestamps: Option<StageTimestamps>,
+    debug_snapshots: Option<CountSnapshots>,
+}
 
-    workgroup_scan_pipelines: Vec<wgpu::ComputePipeline>,
-    workgroup_propagate_pipelines: Vec<wgpu::ComputePipeline>,
-    sorting_pipeline: wgpu::ComputePipeline,
+// Optional output of the [start, end) range (as a vec2<u32>) of each bucket in the sorting id buffer
+struct BucketRanges {
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pipeline: Arc<wgpu::ComputePipeline>,
 }
 
 #[derive(Debug)]
 pub enum CountingSortingError {
     MissingBufferUsage(wgpu::BufferUsages, &'static str),
     ToManyScanThenPropagateLevels(u32, u32, u32),
+    MisalignedBufferOffset(wgpu::BufferAddress, u32, &'static str),
+    InvalidBufferRange(wgpu::BufferAddress, wgpu::BufferAddress, wgpu::BufferAddress, &'static str),
+    OverlappingBindings(&'static str, &'static str),
+    BufferTooSmall(wgpu::BufferAddress, wgpu::BufferAddress, &'static str),
+    EmptyInput(&'static str),
+    InvalidGridCellCount(SpatialHashGridLayout),
+    InvalidMortonBitsPerAxis(u32, u32),
+    InvalidItemsPerInvocation(u32),
+    InvalidKeyBits(u32),
+    InvalidBatchEntry(usize, &'static str),
+    InvalidChunkSize(u32, u32),
+    UnsupportedOption(&'static str, &'static str),
+    InvalidWorkgroupSize(u32),
+    WorkgroupSizeExceedsLimit(u32, u32, &'static str),
+    DeviceLimitExceeded(u64, u64, &'static str),
+    ShaderComposition(&'static str, String),
+    PipelineValidation(String),
+    OutOfMemory(String),
+    MissingCountBuffer,
+    KeyOutOfRange(u32, u32),
+    BufferMapping(String),
+    MissingOutputBuffer(&'static str),
+    MissingDeviceFeature(wgpu::Features),
 }
 
 impl std::fmt::Display for CountingSortingError {
@@ -55,30 +146,321 @@ impl std::fmt::Display for CountingSortingError {
                     scan_then_propagate_levels
                 )
             }
+            CountingSortingError::MisalignedBufferOffset(offset, alignment, buffer_name) => write!(
+                f,
+                "Offset {} of {} isn't a multiple of min_storage_buffer_offset_alignment ({})",
+                offset, buffer_name, alignment
+            ),
+            CountingSortingError::InvalidBufferRange(offset, size, buffer_size, buffer_name) => write!(
+                f,
+                "Invalid range (offset {}, size {}) for {} of size {}, the range should be non empty, a multiple of 4 bytes and fit in the buffer",
+                offset, size, buffer_name, buffer_size
+            ),
+            CountingSortingError::OverlappingBindings(buffer_name, other_buffer_name) =>
+                write!(f, "{} and {} overlap in the same buffer", buffer_name, other_buffer_name),
+            CountingSortingError::BufferTooSmall(size, required_size, buffer_name) =>
+                write!(f, "{} is too small ({} bytes while at least {} bytes are required)", buffer_name, size, required_size),
+            CountingSortingError::EmptyInput(input_name) => write!(f, "{} should not be empty", input_name),
+            CountingSortingError::InvalidGridCellCount(layout) =>
+                write!(f, "Invalid spatial hash grid layout {:?}, the cell count should be non zero and fit in a u32", layout),
+            CountingSortingError::InvalidMortonBitsPerAxis(bits_per_axis, max_bits_per_axis) =>
+                write!(f, "Invalid Morton bits per axis {}, it should be in [1, {}]", bits_per_axis, max_bits_per_axis),
+            CountingSortingError::InvalidItemsPerInvocation(items_per_invocation) =>
+                write!(f, "Invalid items per invocation {}, it should be at least 1", items_per_invocation),
+            CountingSortingError::InvalidKeyBits(key_bits) => write!(f, "Invalid key bits {}, it should be in [1, 32]", key_bits),
+            CountingSortingError::InvalidBatchEntry(index, reason) => write!(f, "Invalid batch entry {}: {}", index, reason),
+            CountingSortingError::InvalidChunkSize(chunk_size, max_chunk_size) => write!(
+                f,
+                "Invalid chunk size {}, it should be at most {} values and its size in bytes a multiple of min_storage_buffer_offset_alignment",
+                chunk_size, max_chunk_size
+            ),
+            CountingSortingError::UnsupportedOption(module_name, option_name) =>
+                write!(f, "The {} doesn't support the {} option of the descriptor", module_name, option_name),
+            CountingSortingError::InvalidWorkgroupSize(workgroup_size) =>
+                write!(f, "Invalid workgroup size {}, it should be a power of two greater than 1", workgroup_size),
+            CountingSortingError::WorkgroupSizeExceedsLimit(size, limit, limit_name) =>
+                write!(f, "Workgroup size requires {} while the device limit {} is {}", size, limit_name, limit),
+            CountingSortingError::DeviceLimitExceeded(size, limit, limit_name) =>
+                write!(f, "Requested {} exceeds the device limit {} ({})", size, limit_name, limit),
+            CountingSortingError::ShaderComposition(shader_name, error) =>
+                write!(f, "Unable to compose the {} shader: {}", shader_name, error),
+            CountingSortingError::PipelineValidation(description) =>
+                write!(f, "Validation error while creating the sorting pipelines: {}", description),
+            CountingSortingError::OutOfMemory(error) =>
+                write!(f, "Out of memory while creating the sorting resources: {}", error),
+            CountingSortingError::MissingCountBuffer =>
+                write!(f, "No count buffer given while the module doesn't own one"),
+            CountingSortingError::KeyOutOfRange(key, key_range) =>
+                write!(f, "Key {} is out of the key range [0, {})", key, key_range),
+            CountingSortingError::BufferMapping(error) =>
+                write!(f, "Unable to map a buffer for reading: {}", error),
+            CountingSortingError::MissingOutputBuffer(buffer_name) =>
+                write!(f, "The module has no {} buffer to read back", buffer_name),
+            CountingSortingError::MissingDeviceFeature(features) => write!(f, "Missing device features {:?}", features),
         }
     }
 }
 
 impl std::error::Error for CountingSortingError {}
 
-//This function is used to compute the number of scan then propagate levels required to scan the count buffer for a given size and workgroup size
-fn scan_then_propagate_level_count(size: u32, workgroup_size: u32) -> u32 {
-    let mut count = 1;
-    let mut temp_size = size / workgroup_size;
-    while temp_size > 0 {
-        count += 1;
-        temp_size /= workgroup_size;
+// WGSL helpers shared by the shaders of the crate (e.g. `linear_workgroup_index`)
+const WORKGROUP_WGSL: &str = include_str!("../shaders/workgroup_common.wgsl");
+
+// Shader source with the shared WGSL helpers prepended
+pub(crate) fn with_common_wgsl(shader_source: &str) -> String { format!("{}\n{}", WORKGROUP_WGSL, shader_source) }
+
+// Fold a workgroup count into a 3D dispatch size when it exceeds max_compute_workgroups_per_dimension
+// Shaders linearize the workgroup id back with `linear_workgroup_index`, the extra workgroups are skipped by their bounds checks
+pub(crate) fn fold_workgroup_count(workgroup_count: u32, max_workgroups_per_dimension: u32) -> [u32; 3] {
+    let x = workgroup_count.min(max_workgroups_per_dimension);
+    let y = workgroup_count.div_ceil(x.max(1)).min(max_workgroups_per_dimension);
+    let z = workgroup_count.div_ceil((x * y).max(1));
+    [x, y, z]
+}
+
+// Upper bound of buckets per invocation of the workgroup histogram to keep its clearing and flushing cheap
+const PRIVATE_HISTOGRAM_MAX_BUCKETS_PER_INVOCATION: u32 = 8;
+
+// Size of the workgroup (privatized) histogram used by the counting pass, None when it would be useless
+// The whole count buffer is privatized when it fits, otherwise a window of buckets is (hybrid path)
+fn private_histogram_size(limits: &wgpu::Limits, count_size: u32, workgroup_size: u32) -> Option<u32> {
+    let max_size = (limits.max_compute_workgroup_storage_size / std::mem::size_of::<u32>() as u32)
+        .saturating_sub(1) // private_histogram_start
+        .min(workgroup_size * PRIVATE_HISTOGRAM_MAX_BUCKETS_PER_INVOCATION);
+    let size = count_size.min(max_size);
+    (size > 1).then_some(size)
+}
+
+// How the counting and sorting passes load the values, which specializes their pipelines
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct ValueLoading {
+    value_size: u32,
+    workgroup_size: u32,
+    items_per_invocation: u32,
+    // The values share their buffer with a read write binding (see `values_share_buffer`)
+    values_read_write: bool,
+}
+
+impl ValueLoading {
+    fn shader_defines(&self) -> [(&'static str, u32); 4] {
+        // vec4<u32> loads need the whole value range to be made of vec4
+        let vectorized_loads = self.items_per_invocation.is_multiple_of(4) && self.value_size.is_multiple_of(4);
+        [
+            ("WORKGROUP_SIZE", self.workgroup_size),
+            ("ITEMS_PER_INVOCATION", self.items_per_invocation),
+            ("VECTORIZED_LOADS", vectorized_loads as u32),
+            ("VALUES_READ_WRITE", self.values_read_write as u32),
+        ]
+    }
+
+    // Layout of the values followed by the count buffer
+    fn bind_group_layout<'a>(&self, pipeline_cache: &'a SortPipelineCache) -> &'a binding_builder::BindGroupLayoutWithDesc {
+        if self.values_read_write {
+            pipeline_cache.read_write_pair_bind_group_layout()
+        } else {
+            pipeline_cache.read_write_bind_group_layout()
+        }
+    }
+}
+
+// Clear the count range bound as a single storage buffer (it may be a sub-range of a buffer the module doesn't own)
+pub(crate) fn create_clearing_pipeline(
+    device: &wgpu::Device,
+    pipeline_cache: &SortPipelineCache,
+    workgroup_size: u32,
+) -> Result<Arc<wgpu::ComputePipeline>, CountingSortingError> {
+    pipeline_cache.get_or_create(
+        device,
+        "clear",
+        include_str!("../shaders/clear.wgsl"),
+        "clear_counts",
+        &[("WORKGROUP_SIZE", workgroup_size)],
+        &[&pipeline_cache.storage_buffer_bind_group_layout().layout],
+    )
+}
+
+pub(crate) fn create_counting_pipeline(
+    device: &wgpu::Device,
+    pipeline_cache: &SortPipelineCache,
+    value_size: u32,
+    count_size: u32,
+    workgroup_size: u32,
+    items_per_invocation: u32,
+) -> Result<Arc<wgpu::ComputePipeline>, CountingSortingError> {
+    create_histogram_pipeline(device, pipeline_cache, value_size, count_size, workgroup_size, items_per_invocation, &[])
+}
+
+// Counting pipeline with extra shader defines, e.g. DIGIT_COUNT and RADIX_BITS to count every digit of the keys into consecutive histograms
+pub(crate) fn create_histogram_pipeline(
+    device: &wgpu::Device,
+    pipeline_cache: &SortPipelineCache,
+    value_size: u32,
+    count_size: u32,
+    workgroup_size: u32,
+    items_per_invocation: u32,
+    extra_shader_defines: &[(&'static str, u32)],
+) -> Result<Arc<wgpu::ComputePipeline>, CountingSortingError> {
+    let value_loading = ValueLoading {
+        value_size,
+        workgroup_size,
+        items_per_invocation,
+        values_read_write: false,
+    };
+    create_value_counting_pipeline(device, pipeline_cache, value_loading, count_size, extra_shader_defines)
+}
+
+// Counting pipeline loading the values as described by value_loading (e.g. read write values sharing their buffer)
+fn create_value_counting_pipeline(
+    device: &wgpu::Device,
+    pipeline_cache: &SortPipelineCache,
+    value_loading: ValueLoading,
+    count_size: u32,
+    extra_shader_defines: &[(&'static str, u32)],
+) -> Result<Arc<wgpu::ComputePipeline>, CountingSortingError> {
+    let mut shader_defines = value_loading.shader_defines().to_vec();
+    shader_defines.extend_from_slice(extra_shader_defines);
+
+    // Counting into a workgroup histogram first avoids serializing global atomics on skewed distributions
+    if let Some(private_histogram_size) = private_histogram_size(&device.limits(), count_size, value_loading.workgroup_size) {
+        shader_defines.push(("PRIVATE_HISTOGRAM_SIZE", private_histogram_size));
     }
-    count
+
+    pipeline_cache.get_or_create(
+        device,
+        "counting",
+        include_str!("../shaders/counting.wgsl"),
+        "count",
+        &shader_defines,
+        &[&value_loading.bind_group_layout(pipeline_cache).layout],
+    )
+}
+
+fn create_sorting_pipeline(
+    device: &wgpu::Device,
+    pipeline_cache: &SortPipelineCache,
+    value_loading: ValueLoading,
+) -> Result<Arc<wgpu::ComputePipeline>, CountingSortingError> {
+    pipeline_cache.get_or_create(
+        device,
+        "sorting",
+        include_str!("../shaders/sorting.wgsl"),
+        "sort",
+        &value_loading.shader_defines(),
+        &[
+            &value_loading.bind_group_layout(pipeline_cache).layout,
+            &pipeline_cache.storage_buffer_bind_group_layout().layout,
+        ],
+    )
 }
 
-fn workgroup_size_per_level(size: u32, workgroup_size: u32, level: u32) -> Vec<u32> {
-    std::iter::successors(
-        Some(size),
-        |&x| Some((x + workgroup_size - 1) / workgroup_size))
-    .take((level+1) as usize)
-    .skip(1)
-    .collect()
+fn create_bucket_ranges_pipeline(
+    device: &wgpu::Device,
+    pipeline_cache: &SortPipelineCache,
+    workgroup_size: u32,
+) -> Result<Arc<wgpu::ComputePipeline>, CountingSortingError> {
+    pipeline_cache.get_or_create(
+        device,
+        "bucket_ranges",
+        include_str!("../shaders/bucket_ranges.wgsl"),
+        "bucket_ranges_from_scan",
+        &[("WORKGROUP_SIZE", workgroup_size)],
+        &[
+            &pipeline_cache.storage_buffer_bind_group_layout().layout,
+            &pipeline_cache.storage_buffer_bind_group_layout().layout,
+        ],
+    )
+}
+
+// Size in bytes of the range covered by a binding (the remaining of the buffer when no size is given)
+fn binding_size(binding: &wgpu::BufferBinding) -> wgpu::BufferAddress {
+    binding
+        .size
+        .map_or(binding.buffer.size().saturating_sub(binding.offset), wgpu::BufferSize::get)
+}
+
+// Check that a binding can be bound as a storage buffer and return the size of its range in bytes
+pub(crate) fn validate_storage_binding(
+    limits: &wgpu::Limits,
+    binding: &wgpu::BufferBinding,
+    buffer_name: &'static str,
+) -> Result<wgpu::BufferAddress, CountingSortingError> {
+    if !binding.buffer.usage().contains(wgpu::BufferUsages::STORAGE) {
+        return Err(CountingSortingError::MissingBufferUsage(wgpu::BufferUsages::STORAGE, buffer_name));
+    }
+
+    if !binding.offset.is_multiple_of(limits.min_storage_buffer_offset_alignment as wgpu::BufferAddress) {
+        return Err(CountingSortingError::MisalignedBufferOffset(
+            binding.offset,
+            limits.min_storage_buffer_offset_alignment,
+            buffer_name,
+        ));
+    }
+
+    let size = binding_size(binding);
+    let size_of_u32 = std::mem::size_of::<u32>() as wgpu::BufferAddress;
+    if size == 0 || !size.is_multiple_of(size_of_u32) || binding.offset + size > binding.buffer.size() {
+        return Err(CountingSortingError::InvalidBufferRange(binding.offset, size, binding.buffer.size(), buffer_name));
+    }
+
+    if size > limits.max_storage_buffer_binding_size as wgpu::BufferAddress {
+        return Err(CountingSortingError::DeviceLimitExceeded(
+            size,
+            limits.max_storage_buffer_binding_size as wgpu::BufferAddress,
+            "max_storage_buffer_binding_size",
+        ));
+    }
+
+    Ok(size)
+}
+
+// Whether two bindings overlap in the same buffer
+fn bindings_overlap(binding: &wgpu::BufferBinding, other_binding: &wgpu::BufferBinding) -> bool {
+    std::ptr::eq(binding.buffer, other_binding.buffer)
+        && binding.offset < other_binding.offset + binding_size(other_binding)
+        && other_binding.offset < binding.offset + binding_size(binding)
+}
+
+// Whether the values are in the buffer of the count or sorting id binding
+// The values are then bound read write, as a buffer can't be both read only and read write storage in the same dispatch (even at disjoint ranges)
+fn values_share_buffer(
+    values_binding: &wgpu::BufferBinding,
+    count_binding: &wgpu::BufferBinding,
+    sorting_id_binding: Option<&wgpu::BufferBinding>,
+) -> bool {
+    std::ptr::eq(values_binding.buffer, count_binding.buffer)
+        || sorting_id_binding.is_some_and(|sorting_id_binding| std::ptr::eq(values_binding.buffer, sorting_id_binding.buffer))
+}
+
+// Check the bindings given to a module and return the size in bytes of the values and count ranges
+// Bindings may share a buffer (e.g. sub-ranges of an arena buffer) as long as their ranges don't overlap
+fn validate_bindings(
+    limits: &wgpu::Limits,
+    values_binding: &wgpu::BufferBinding,
+    count_binding: &wgpu::BufferBinding,
+    sorting_id_binding: Option<&wgpu::BufferBinding>,
+) -> Result<(wgpu::BufferAddress, wgpu::BufferAddress), CountingSortingError> {
+    let values_binding_size = validate_storage_binding(limits, values_binding, "Values buffer")?;
+    let count_binding_size = validate_storage_binding(limits, count_binding, "Count buffer")?;
+
+    if bindings_overlap(values_binding, count_binding) {
+        return Err(CountingSortingError::OverlappingBindings("Values buffer", "Count buffer"));
+    }
+
+    if let Some(sorting_id_binding) = sorting_id_binding {
+        let sorting_id_binding_size = validate_storage_binding(limits, sorting_id_binding, "Sorting id buffer")?;
+        if sorting_id_binding_size < values_binding_size {
+            return Err(CountingSortingError::BufferTooSmall(sorting_id_binding_size, values_binding_size, "Sorting id buffer"));
+        }
+
+        if bindings_overlap(values_binding, sorting_id_binding) {
+            return Err(CountingSortingError::OverlappingBindings("Values buffer", "Sorting id buffer"));
+        }
+        if bindings_overlap(count_binding, sorting_id_binding) {
+            return Err(CountingSortingError::OverlappingBindings("Count buffer", "Sorting id buffer"));
+        }
+    }
+
+    Ok((values_binding_size, count_binding_size))
 }
 
 impl GpuCountingSortModule {
@@ -86,238 +468,668 @@ impl GpuCountingSortModule {
         device: &wgpu::Device,
         values_buffer: &wgpu::Buffer,
         count_buffer: &wgpu::Buffer,
-        workgroup_size: u32,
+        workgroup_size: impl Into<WorkgroupSize>,
     ) -> Result<Self, CountingSortingError> {
-        if !count_buffer.usage().contains(wgpu::BufferUsages::COPY_DST) {
-            return Err(CountingSortingError::MissingBufferUsage(wgpu::BufferUsages::COPY_DST, "Count buffer"));
-        }
-
-        if !values_buffer.usage().contains(wgpu::BufferUsages::STORAGE) {
-            return Err(CountingSortingError::MissingBufferUsage(wgpu::BufferUsages::STORAGE, "Values buffer"));
-        }
-
-        if !count_buffer.usage().contains(wgpu::BufferUsages::STORAGE) {
-            return Err(CountingSortingError::MissingBufferUsage(wgpu::BufferUsages::STORAGE, "Count buffer"));
-        }
-
-        let count_size: u32 = (count_buffer.size() / std::mem::size_of::<u32>() as u64) as _;
-        let value_size = (values_buffer.size() / std::mem::size_of::<u32>() as u64) as _;
+        CountingSortDescriptor::default().with_workgroup_size(workgroup_size).build(device, values_buffer, count_buffer)
+    }
 
-        let scan_then_propagate_level_count = scan_then_propagate_level_count(count_size, workgroup_size);
+    // Same as `new` but values, count and (optionally) sorting ids are sub-ranges of bigger buffers
+    // Offsets should be multiples of `min_storage_buffer_offset_alignment` and sizes multiples of 4 bytes
+    // When no sorting id binding is given, the module allocates its own sorting id buffer
+    pub fn new_with_bindings(
+        device: &wgpu::Device,
+        values_binding: wgpu::BufferBinding,
+        count_binding: wgpu::BufferBinding,
+        sorting_id_binding: Option<wgpu::BufferBinding>,
+        workgroup_size: impl Into<WorkgroupSize>,
+    ) -> Result<Self, CountingSortingError> {
+        CountingSortDescriptor::default()
+            .with_workgroup_size(workgroup_size)
+            .build_with_bindings(device, values_binding, count_binding, sorting_id_binding)
+    }
 
-        if scan_then_propagate_level_count > 4 {
-            return Err(CountingSortingError::ToManyScanThenPropagateLevels(count_size, workgroup_size, scan_then_propagate_level_count));
-        }
+    // Same as `new` but the module allocates (and owns) a count buffer of count_size buckets (see `count_buffer`)
+    pub fn new_with_count_size(
+        device: &wgpu::Device,
+        values_buffer: &wgpu::Buffer,
+        count_size: u32,
+        workgroup_size: impl Into<WorkgroupSize>,
+    ) -> Result<Self, CountingSortingError> {
+        CountingSortDescriptor::default()
+            .with_workgroup_size(workgroup_size)
+            .build_with_count_size(device, values_buffer, count_size)
+    }
 
-        let sorting_id_buffer = buffers::create_buffer_for_size(
+    // See `CountingSortDescriptor::build_with_count_size`
+    pub fn from_descriptor_with_count_size(
+        device: &wgpu::Device,
+        descriptor: &CountingSortDescriptor,
+        values_binding: wgpu::BufferBinding,
+        count_size: u32,
+        sorting_id_binding: Option<wgpu::BufferBinding>,
+    ) -> Result<Self, CountingSortingError> {
+        let error_scope = ErrorScope::push(device);
+        let count_buffer = buffers::create_buffer_for_size(
             device,
-            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
-            Some("sorting id buffer"),
-            values_buffer.size(),
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST | descriptor.output_usages,
+            Some(&descriptor.label("count buffer")),
+            count_size as wgpu::BufferAddress * std::mem::size_of::<u32>() as wgpu::BufferAddress,
         );
+        error_scope.pop()?;
 
-        // init bind groups
-        let single_read_write_storage_buffer_bind_group_layout_with_desc = binding_builder::BindGroupLayoutBuilder::new()
-            .add_binding_compute(wgpu::BindingType::Buffer {
-                ty: wgpu::BufferBindingType::Storage { read_only: false },
-                has_dynamic_offset: false,
-                min_binding_size: None,
-            })
-            .create(device, None);
-
-        let read_write_bind_group_layout_with_desc = binding_builder::BindGroupLayoutBuilder::new()
-            .add_binding_compute(wgpu::BindingType::Buffer {
-                ty: wgpu::BufferBindingType::Storage { read_only: true },
-                has_dynamic_offset: false,
-                min_binding_size: None,
-            })
-            .add_binding_compute(wgpu::BindingType::Buffer {
-                ty: wgpu::BufferBindingType::Storage { read_only: false },
-                has_dynamic_offset: false,
-                min_binding_size: None,
-            })
-            .create(device, None);
-
-        let counting_bind_group = binding_builder::BindGroupBuilder::new(&read_write_bind_group_layout_with_desc)
-            .resource(values_buffer.as_entire_binding())
-            .resource(count_buffer.as_entire_binding())
-            .create(device, Some("counting_bind_group"));
-
-        let sorting_bind_group = binding_builder::BindGroupBuilder::new(&single_read_write_storage_buffer_bind_group_layout_with_desc)
-            .resource(sorting_id_buffer.as_entire_binding())
-            .create(device, Some("sorting_bind_group"));
-
-        let count_buffer_bind_group = binding_builder::BindGroupBuilder::new(&single_read_write_storage_buffer_bind_group_layout_with_desc)
-            .resource(count_buffer.as_entire_binding())
-            .create(device, Some("count_buffer_bind_group"));
+        let mut counting_sort_module =
+            Self::from_descriptor(device, descriptor, values_binding, count_buffer.as_entire_buffer_binding(), sorting_id_binding)?;
+        counting_sort_module.count_buffer = Some(count_buffer);
+        Ok(counting_sort_module)
+    }
 
-        // Pipelines
-        let counting_shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("counting shader"),
-            source: wgpu::ShaderSource::Naga(std::borrow::Cow::Owned(
-                ShaderComposer::new(include_str!("../shaders/counting.wgsl"), Some("counting"))
-                    .with_shader_define("WORKGROUP_SIZE", workgroup_size.into())
-                    .build()
-                    .unwrap(),
-            )),
-        });
+    // See `CountingSortDescriptor::build_with_bindings`
+    pub fn from_descriptor(
+        device: &wgpu::Device,
+        descriptor: &CountingSortDescriptor,
+        values_binding: wgpu::BufferBinding,
+        count_binding: wgpu::BufferBinding,
+        sorting_id_binding: Option<wgpu::BufferBinding>,
+    ) -> Result<Self, CountingSortingError> {
+        let limits = device.limits();
+        let workgroup_size = descriptor.validate(&limits)?;
+        let descriptor = CountingSortDescriptor {
+            workgroup_size: WorkgroupSize::Fixed(workgroup_size),
+            ..descriptor.clone()
+        };
 
-        let mut scan_shader_composer =
-            ShaderComposer::new(include_str!("../shaders/scan.wgsl"), Some("scan")).with_shader_define("WORKGROUP_SIZE", workgroup_size.into());
+        let (values_binding_size, count_binding_size) = validate_bindings(&limits, &values_binding, &count_binding, sorting_id_binding.as_ref())?;
 
-        
-        let mut workgroup_scan_pipelines = Vec::with_capacity(scan_then_propagate_level_count as usize);
-        let mut workgroup_propagate_pipelines = Vec::with_capacity((scan_then_propagate_level_count-1) as usize);
+        let count_size: u32 = (count_binding_size / std::mem::size_of::<u32>() as u64) as _;
+        let value_loading = ValueLoading {
+            value_size: (values_binding_size / std::mem::size_of::<u32>() as u64) as _,
+            workgroup_size,
+            items_per_invocation: descriptor.items_per_invocation,
+            values_read_write: values_share_buffer(&values_binding, &count_binding, sorting_id_binding.as_ref()),
+        };
 
-        for scan_then_propagate_level in 0..scan_then_propagate_level_count {
-            // Unable to use push_constant as it's not available in wgpu yet so we have to use a shader define for the scan level and recompile the shader for each level
-            // Otherwise we could have used a uniform buffer to pass the scan level but this force use to submit the queue for each scan level
-            scan_shader_composer.add_shader_define("SCAN_LEVEL", scan_then_propagate_level.into());
+        let values_offset = values_binding.offset;
 
-            let scan_shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-                label: Some("scan shader"),
-                source: wgpu::ShaderSource::Naga(std::borrow::Cow::Owned(scan_shader_composer.build_ref().unwrap())),
-            });
+        let error_scope = ErrorScope::push(device);
+        let sorting_id_buffer = match sorting_id_binding {
+            Some(_) => None,
+            None => Some(buffers::create_buffer_for_size(
+                device,
+                wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | descriptor.output_usages,
+                Some(&descriptor.label("sorting id buffer")),
+                values_binding_size,
+            )),
+        };
+        let sorting_id_binding = sorting_id_binding.unwrap_or_else(|| sorting_id_buffer.as_ref().unwrap().as_entire_buffer_binding());
+        let pipeline_cache = descriptor.pipeline_cache_or_private(device, None);
 
-            workgroup_scan_pipelines.push(device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-                label: Some(format!("workgroup scan pipeline (level {})", scan_then_propagate_level).as_str()),
-                layout: Some(&device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                    label: Some(format!("workgroup scan pipeline layout (level {})", scan_then_propagate_level).as_str()),
-                    bind_group_layouts: &[&single_read_write_storage_buffer_bind_group_layout_with_desc.layout],
-                    push_constant_ranges: &[],
-                })),
-                module: &scan_shader_module,
-                entry_point: "workgroup_scan",
-            }));
-
-            if scan_then_propagate_level < scan_then_propagate_level_count - 1 {
-                workgroup_propagate_pipelines.push(device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-                    label: Some(format!("workgroup propagate pipeline (level {})", scan_then_propagate_level).as_str()),
-                    layout: Some(&device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                        label: Some(format!("workgroup propagate pipeline layout (level {})", scan_then_propagate_level).as_str()),
-                        bind_group_layouts: &[&single_read_write_storage_buffer_bind_group_layout_with_desc.layout],
-                        push_constant_ranges: &[],
-                    })),
-                    module: &scan_shader_module,
-                    entry_point: "workgroup_propagate",
-                }));
-            }
-        }
+        // init bind groups
+        let counting_bind_group = binding_builder::BindGroupBuilder::new(value_loading.bind_group_layout(&pipeline_cache))
+            .resource(wgpu::BindingResource::Buffer(values_binding))
+            .resource(wgpu::BindingResource::Buffer(count_binding.clone()))
+            .create(device, Some(&descriptor.label("counting_bind_group")));
 
-        let sorting_shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("counting shader"),
-            source: wgpu::ShaderSource::Naga(std::borrow::Cow::Owned(
-                ShaderComposer::new(include_str!("../shaders/sorting.wgsl"), Some("sorting"))
-                    .with_shader_define("WORKGROUP_SIZE", workgroup_size.into())
-                    .build()
-                    .unwrap(),
-            )),
-        });
+        let sorting_bind_group = binding_builder::BindGroupBuilder::new(pipeline_cache.storage_buffer_bind_group_layout())
+            .resource(wgpu::BindingResource::Buffer(sorting_id_binding))
+            .create(device, Some(&descriptor.label("sorting_bind_group")));
 
-        let counting_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: Some("counting pipeline"),
-            layout: Some(&device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("counting pipeline layout"),
-                bind_group_layouts: &[&read_write_bind_group_layout_with_desc.layout],
-                push_constant_ranges: &[],
-            })),
-            module: &counting_shader_module,
-            entry_point: "count",
-        });
+        let count_buffer_bind_group = binding_builder::BindGroupBuilder::new(pipeline_cache.storage_buffer_bind_group_layout())
+            .resource(wgpu::BindingResource::Buffer(count_binding))
+            .create(device, Some(&descriptor.label("count_buffer_bind_group")));
 
-        let sorting_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
-            label: Some("sorting pipeline"),
-            layout: Some(&device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("sorting pipeline layout"),
-                bind_group_layouts: &[
-                    &read_write_bind_group_layout_with_desc.layout,
-                    &single_read_write_storage_buffer_bind_group_layout_with_desc.layout,
-                ],
-                push_constant_ranges: &[],
-            })),
-            module: &sorting_shader_module,
-            entry_point: "sort",
-        });
+        // Pipelines
+        let clearing_pipeline = create_clearing_pipeline(device, &pipeline_cache, workgroup_size)?;
 
-        Ok(Self {
+        let counting_pipeline = create_value_counting_pipeline(device, &pipeline_cache, value_loading, count_size, &[])?;
+        let scan_pipelines = ScanPipelines::new(device, &pipeline_cache, count_size, workgroup_size)?;
+        let sorting_pipeline = create_sorting_pipeline(device, &pipeline_cache, value_loading)?;
+        error_scope.pop()?;
+
+        let (bucket_ranges, timestamps, debug_snapshots) = (descriptor.bucket_ranges, descriptor.timestamps, descriptor.debug_snapshots);
+        let counting_sort_module = Self {
+            descriptor,
             workgroup_size,
-            value_size,
+            max_workgroups_per_dimension: limits.max_compute_workgroups_per_dimension,
+            value_size: value_loading.value_size,
+            values_offset,
             count_size,
+            values_read_write: value_loading.values_read_write,
 
+            count_buffer: None,
             sorting_id_buffer,
 
             counting_bind_group,
             sorting_bind_group,
             count_buffer_bind_group,
 
+            pipeline_cache,
+
+            clearing_pipeline,
             counting_pipeline,
-            workgroup_scan_pipelines,
-            workgroup_propagate_pipelines,
+            scan_pipelines,
             sorting_pipeline,
-        })
+
+            bucket_ranges: None,
+            timestamps: None,
+            debug_snapshots: None,
+        };
+
+        let counting_sort_module = if bucket_ranges {
+            counting_sort_module.with_bucket_ranges(device)?
+        } else {
+            counting_sort_module
+        };
+
+        let counting_sort_module = if timestamps {
+            counting_sort_module.with_timestamps(device)?
+        } else {
+            counting_sort_module
+        };
+
+        if debug_snapshots {
+            counting_sort_module.with_debug_snapshots(device)
+        } else {
+            Ok(counting_sort_module)
+        }
+    }
+
+    fn value_loading(&self) -> ValueLoading {
+        ValueLoading {
+            value_size: self.value_size,
+            workgroup_size: self.workgroup_size,
+            items_per_invocation: self.descriptor.items_per_invocation,
+            values_read_write: self.values_read_write,
+        }
+    }
+
+    // Make each invocation of the counting and sorting passes handle several values (1 by default)
+    // Large buffers then need fewer workgroups, values are loaded as vec4<u32> when items_per_invocation and the value count are multiples of 4
+    pub fn with_items_per_invocation(mut self, device: &wgpu::Device, items_per_invocation: u32) -> Result<Self, CountingSortingError> {
+        if items_per_invocation == 0 {
+            return Err(CountingSortingError::InvalidItemsPerInvocation(items_per_invocation));
+        }
+
+        let error_scope = ErrorScope::push(device);
+        self.descriptor.items_per_invocation = items_per_invocation;
+        self.counting_pipeline = create_value_counting_pipeline(device, &self.pipeline_cache, self.value_loading(), self.count_size, &[])?;
+        self.sorting_pipeline = create_sorting_pipeline(device, &self.pipeline_cache, self.value_loading())?;
+        error_scope.pop()?;
+        Ok(self)
+    }
+
+    // Enable the output of the [start, end) range of each bucket (see `bucket_range_buffer`)
+    // The ranges are computed right after the scan so they don't depend on how the sort step consumes the count buffer
+    pub fn with_bucket_ranges(mut self, device: &wgpu::Device) -> Result<Self, CountingSortingError> {
+        let error_scope = ErrorScope::push(device);
+        let buffer = buffers::create_buffer_for_size(
+            device,
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | self.descriptor.output_usages,
+            Some(&self.descriptor.label("bucket range buffer")),
+            self.count_size as wgpu::BufferAddress * std::mem::size_of::<[u32; 2]>() as wgpu::BufferAddress,
+        );
+
+        let bind_group = binding_builder::BindGroupBuilder::new(self.pipeline_cache.storage_buffer_bind_group_layout())
+            .resource(buffer.as_entire_binding())
+            .create(device, Some(&self.descriptor.label("bucket_ranges_bind_group")));
+
+        let pipeline = create_bucket_ranges_pipeline(device, &self.pipeline_cache, self.workgroup_size)?;
+
+        error_scope.pop()?;
+
+        self.descriptor.bucket_ranges = true;
+        self.bucket_ranges = Some(BucketRanges { buffer, bind_group, pipeline });
+        Ok(self)
+    }
+
+    // Record the begin and end timestamps of each stage (see `read_stage_durations`), to tune the workgroup size per GPU
+    // Each scan and propagate level then runs in its own compute pass, errors with `MissingDeviceFeature` without TIMESTAMP_QUERY
+    pub fn with_timestamps(mut self, device: &wgpu::Device) -> Result<Self, CountingSortingError> {
+        // count, scatter, bucket ranges and every scan and propagate level the module can be rebound to
+        let stage_capacity = 3 + 2 * scan::MAX_SCAN_THEN_PROPAGATE_LEVELS - 1;
+
+        let error_scope = ErrorScope::push(device);
+        let timestamps = StageTimestamps::new(device, &self.descriptor.label("timestamp"), stage_capacity)?;
+        error_scope.pop()?;
+
+        self.descriptor.timestamps = true;
+        self.timestamps = Some(timestamps);
+        Ok(self)
+    }
+
+    // Debug mode: copy the count buffer after the count pass and after each scan and propagate level (see `read_debug_snapshots`)
+    // to tell whether counting, scanning or propagating went wrong, each scan and propagate level then runs in its own compute pass
+    pub fn with_debug_snapshots(mut self, device: &wgpu::Device) -> Result<Self, CountingSortingError> {
+        let error_scope = ErrorScope::push(device);
+        let debug_snapshots = CountSnapshots::new(
+            device,
+            &self.pipeline_cache,
+            &self.descriptor,
+            self.workgroup_size,
+            self.count_size,
+            2 * self.scan_pipelines.level_count(),
+        )?;
+        error_scope.pop()?;
+
+        self.descriptor.debug_snapshots = true;
+        self.debug_snapshots = Some(debug_snapshots);
+        Ok(self)
     }
 }
 
 impl GpuCountingSortModule {
-    // TODO: find a way to store some kind of reference to the buffer to avoid the need to pass it as an argument
-    pub fn dispatch_work(&self, encoder: &mut wgpu::CommandEncoder, count_buffer: &wgpu::Buffer) {
-        log::trace!("[GpuCountingSortModule] workgroups of size {} (for value buffer of {} and counting buffer or {})", self.workgroup_size, self.value_size, self.count_size);
+    // Point the module to other values and count buffers without rebuilding it
+    // Only the bind groups (and the owned sorting id and bucket range buffers) are recreated,
+    // pipelines are rebuilt only when their specialization changes (vectorized loads, workgroup histogram size or scan level count)
+    // The module doesn't own a count buffer anymore after a rebind
+    pub fn rebind(&mut self, device: &wgpu::Device, values_buffer: &wgpu::Buffer, count_buffer: &wgpu::Buffer) -> Result<(), CountingSortingError> {
+        self.rebind_bindings(device, values_buffer.as_entire_buffer_binding(), count_buffer.as_entire_buffer_binding(), None)
+    }
+
+    // Sort only the first value_size values of the values buffer (e.g. the live part of a particle pool)
+    // When no count buffer is given, the one owned by the module is kept
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        values_buffer: &wgpu::Buffer,
+        count_buffer: Option<&wgpu::Buffer>,
+        value_size: u32,
+    ) -> Result<(), CountingSortingError> {
+        let values_binding = wgpu::BufferBinding {
+            buffer: values_buffer,
+            offset: 0,
+            size: wgpu::BufferSize::new(value_size as wgpu::BufferAddress * std::mem::size_of::<u32>() as wgpu::BufferAddress),
+        };
 
-        let value_workgroup_size_x = (self.value_size + self.workgroup_size - 1) / self.workgroup_size;
+        match count_buffer {
+            Some(count_buffer) => self.rebind_bindings(device, values_binding, count_buffer.as_entire_buffer_binding(), None),
+            None => {
+                let count_buffer = self.count_buffer.take().ok_or(CountingSortingError::MissingCountBuffer)?;
+                let result = self.update_bindings(device, values_binding, count_buffer.as_entire_buffer_binding(), None);
+                self.count_buffer = Some(count_buffer);
+                result
+            }
+        }
+    }
+
+    // Same as `rebind` with sub-ranges (see `new_with_bindings`)
+    pub fn rebind_bindings(
+        &mut self,
+        device: &wgpu::Device,
+        values_binding: wgpu::BufferBinding,
+        count_binding: wgpu::BufferBinding,
+        sorting_id_binding: Option<wgpu::BufferBinding>,
+    ) -> Result<(), CountingSortingError> {
+        self.update_bindings(device, values_binding, count_binding, sorting_id_binding)?;
+        self.count_buffer = None;
+        Ok(())
+    }
+
+    fn update_bindings(
+        &mut self,
+        device: &wgpu::Device,
+        values_binding: wgpu::BufferBinding,
+        count_binding: wgpu::BufferBinding,
+        sorting_id_binding: Option<wgpu::BufferBinding>,
+    ) -> Result<(), CountingSortingError> {
+        let limits = device.limits();
+        let (values_binding_size, count_binding_size) = validate_bindings(&limits, &values_binding, &count_binding, sorting_id_binding.as_ref())?;
+
+        let size_of_u32 = std::mem::size_of::<u32>() as wgpu::BufferAddress;
+        let value_loading = ValueLoading {
+            value_size: (values_binding_size / size_of_u32) as u32,
+            values_read_write: values_share_buffer(&values_binding, &count_binding, sorting_id_binding.as_ref()),
+            ..self.value_loading()
+        };
+        let count_size = (count_binding_size / size_of_u32) as u32;
+        let values_offset = values_binding.offset;
+
+        let error_scope = ErrorScope::push(device);
+
+        // Pipelines are built first so that the module is left untouched if one of them fails
+        let counting_defines_changed = self.value_loading().shader_defines() != value_loading.shader_defines();
+        let private_histogram_changed =
+            private_histogram_size(&limits, self.count_size, self.workgroup_size) != private_histogram_size(&limits, count_size, self.workgroup_size);
+
+        let counting_pipeline = if counting_defines_changed || private_histogram_changed {
+            Some(create_value_counting_pipeline(device, &self.pipeline_cache, value_loading, count_size, &[])?)
+        } else {
+            None
+        };
+
+        let sorting_pipeline = if counting_defines_changed {
+            Some(create_sorting_pipeline(device, &self.pipeline_cache, value_loading)?)
+        } else {
+            None
+        };
+
+        let scan_pipelines = if self.scan_pipelines.level_count() != scan::scan_then_propagate_level_count(count_size, self.workgroup_size) {
+            Some(ScanPipelines::new(device, &self.pipeline_cache, count_size, self.workgroup_size)?)
+        } else {
+            None
+        };
+
+        // The owned sorting id buffer is kept when it has the right size
+        let owns_sorting_ids = sorting_id_binding.is_none();
+        let new_sorting_id_buffer = if owns_sorting_ids && self.sorting_id_buffer.as_ref().is_none_or(|buffer| buffer.size() != values_binding_size) {
+            Some(buffers::create_buffer_for_size(
+                device,
+                wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | self.descriptor.output_usages,
+                Some(&self.descriptor.label("sorting id buffer")),
+                values_binding_size,
+            ))
+        } else {
+            None
+        };
+        let sorting_id_binding = sorting_id_binding.unwrap_or_else(|| {
+            new_sorting_id_buffer.as_ref().or(self.sorting_id_buffer.as_ref()).unwrap().as_entire_buffer_binding()
+        });
+
+        self.counting_bind_group = binding_builder::BindGroupBuilder::new(value_loading.bind_group_layout(&self.pipeline_cache))
+            .resource(wgpu::BindingResource::Buffer(values_binding))
+            .resource(wgpu::BindingResource::Buffer(count_binding.clone()))
+            .create(device, Some(&self.descriptor.label("counting_bind_group")));
+
+        self.sorting_bind_group = binding_builder::BindGroupBuilder::new(self.pipeline_cache.storage_buffer_bind_group_layout())
+            .resource(wgpu::BindingResource::Buffer(sorting_id_binding))
+            .create(device, Some(&self.descriptor.label("sorting_bind_group")));
+
+        self.count_buffer_bind_group = binding_builder::BindGroupBuilder::new(self.pipeline_cache.storage_buffer_bind_group_layout())
+            .resource(wgpu::BindingResource::Buffer(count_binding))
+            .create(device, Some(&self.descriptor.label("count_buffer_bind_group")));
+
+        if let Some(bucket_ranges) = &mut self.bucket_ranges {
+            if count_size != self.count_size {
+                bucket_ranges.buffer = buffers::create_buffer_for_size(
+                    device,
+                    wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | self.descriptor.output_usages,
+                    Some(&self.descriptor.label("bucket range buffer")),
+                    count_size as wgpu::BufferAddress * std::mem::size_of::<[u32; 2]>() as wgpu::BufferAddress,
+                );
+
+                bucket_ranges.bind_group = binding_builder::BindGroupBuilder::new(self.pipeline_cache.storage_buffer_bind_group_layout())
+                    .resource(bucket_ranges.buffer.as_entire_binding())
+                    .create(device, Some(&self.descriptor.label("bucket_ranges_bind_group")));
+            }
+        }
+
+        if let Some(debug_snapshots) = &mut self.debug_snapshots {
+            let snapshot_count = 2 * scan::scan_then_propagate_level_count(count_size, self.workgroup_size);
+            if count_size != self.count_size || snapshot_count != debug_snapshots.snapshot_count() {
+                debug_snapshots.create_buffers(device, &self.pipeline_cache, &self.descriptor, count_size, snapshot_count);
+            }
+        }
+
+        if let Some(counting_pipeline) = counting_pipeline {
+            self.counting_pipeline = counting_pipeline;
+        }
+        if let Some(sorting_pipeline) = sorting_pipeline {
+            self.sorting_pipeline = sorting_pipeline;
+        }
+        if let Some(scan_pipelines) = scan_pipelines {
+            self.scan_pipelines = scan_pipelines;
+        }
+        if !owns_sorting_ids {
+            self.sorting_id_buffer = None;
+        } else if new_sorting_id_buffer.is_some() {
+            self.sorting_id_buffer = new_sorting_id_buffer;
+        }
+        self.value_size = value_loading.value_size;
+        self.values_offset = values_offset;
+        self.count_size = count_size;
+        self.values_read_write = value_loading.values_read_write;
+
+        error_scope.pop()
+    }
+}
+
+impl GpuCountingSortModule {
+    // Only the bound range of the count buffer is cleared (by a compute pass, so the module doesn't need to own the buffer)
+    pub fn dispatch_work(&self, encoder: &mut wgpu::CommandEncoder) {
+        log::trace!("[GpuCountingSortModule] workgroups of size {} with {} items per invocation (for value buffer of {} and counting buffer or {})", self.workgroup_size, self.descriptor.items_per_invocation, self.value_size, self.count_size);
+
+        let value_workgroup_count = self.value_size.div_ceil(self.workgroup_size * self.descriptor.items_per_invocation);
         encoder.push_debug_group("Counting Sort");
-        encoder.clear_buffer(count_buffer, 0, None);
 
         {
             let count_pass = &mut encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
                 label: Some("Compting Pass"),
-                timestamp_writes: None,
+                timestamp_writes: self.timestamps.as_ref().map(|timestamps| timestamps.pass_writes(0)),
             });
 
+            count_pass.set_pipeline(&self.clearing_pipeline);
+            count_pass.set_bind_group(0, &self.count_buffer_bind_group, &[]);
+            let [x, y, z] = fold_workgroup_count(self.count_size.div_ceil(self.workgroup_size), self.max_workgroups_per_dimension);
+            count_pass.dispatch_workgroups(x, y, z);
+
             count_pass.set_pipeline(&self.counting_pipeline);
             count_pass.set_bind_group(0, &self.counting_bind_group, &[]);
-            count_pass.dispatch_workgroups(value_workgroup_size_x, 1, 1);
+            let [x, y, z] = fold_workgroup_count(value_workgroup_count, self.max_workgroups_per_dimension);
+            count_pass.dispatch_workgroups(x, y, z);
         }
 
-        {
-            let scan_pass = &mut encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
-                label: Some("Scan Pass"),
-                timestamp_writes: None,
-            });
-
-            scan_pass.set_bind_group(0, &self.count_buffer_bind_group, &[]);
-            
-            let scan_workgroup_sizes = workgroup_size_per_level(self.count_size, self.workgroup_size, self.workgroup_scan_pipelines.len() as u32);
-            
-            for (workgroup_scan_pipeline, workgroup_size_x) in self.workgroup_scan_pipelines.iter().zip(scan_workgroup_sizes.iter()) {
-                scan_pass.push_debug_group(format!("Scan ({} workgroups)", workgroup_size_x).as_str());
-                log::trace!("[GpuCountingSortModule] Dispatching Scan ({} workgroups)", workgroup_size_x);
-                scan_pass.set_pipeline(workgroup_scan_pipeline);
-                scan_pass.dispatch_workgroups(*workgroup_size_x, 1, 1);
-                scan_pass.pop_debug_group();
+        // Snapshots are taken in the order of `debug_snapshot_stage_names`
+        let record_snapshot = |encoder: &mut wgpu::CommandEncoder, snapshot: usize| {
+            if let Some(debug_snapshots) = &self.debug_snapshots {
+                debug_snapshots.record(
+                    encoder,
+                    snapshot,
+                    &self.count_buffer_bind_group,
+                    self.count_size,
+                    self.workgroup_size,
+                    self.max_workgroups_per_dimension,
+                );
             }
+        };
+        record_snapshot(encoder, 0);
+        let record_scan_snapshot = |encoder: &mut wgpu::CommandEncoder, scan_stage: usize| record_snapshot(encoder, 1 + scan_stage);
 
-            for (workgroup_propagate_pipeline, workgroup_size_x) in self.workgroup_propagate_pipelines.iter().rev().zip(scan_workgroup_sizes.iter().rev().skip(1)) {
-                scan_pass.push_debug_group(format!("Propagate ({} workgroups)", workgroup_size_x).as_str());
-                log::trace!("[GpuCountingSortModule] Dispatching Propagate ({} workgroups)", workgroup_size_x);
-                scan_pass.set_pipeline(workgroup_propagate_pipeline);
-                scan_pass.dispatch_workgroups(*workgroup_size_x, 1, 1);
-                scan_pass.pop_debug_group();
-            }
+        // Stages are timed in the order of `stage_names`
+        let mut stage = 1;
+        let level_passes = (self.timestamps.is_some() || self.debug_snapshots.is_some()).then(|| LevelPasses {
+            timestamps: self.timestamps.as_ref().map(|timestamps| (timestamps, stage)),
+            after_pass: self.debug_snapshots.as_ref().map(|_| &record_scan_snapshot as &AfterLevelPass),
+        });
+        self.scan_pipelines.dispatch(
+            encoder,
+            &self.count_buffer_bind_group,
+            self.count_size,
+            self.workgroup_size,
+            self.max_workgroups_per_dimension,
+            level_passes,
+        );
+        stage += 2 * self.scan_pipelines.level_count() - 1;
+
+        if let Some(bucket_ranges) = &self.bucket_ranges {
+            let bucket_ranges_pass = &mut encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Bucket Ranges Pass"),
+                timestamp_writes: self.timestamps.as_ref().map(|timestamps| timestamps.pass_writes(stage)),
+            });
+            stage += 1;
+
+            bucket_ranges_pass.set_pipeline(&bucket_ranges.pipeline);
+            bucket_ranges_pass.set_bind_group(0, &self.count_buffer_bind_group, &[]);
+            bucket_ranges_pass.set_bind_group(1, &bucket_ranges.bind_group, &[]);
+            let [x, y, z] = fold_workgroup_count(self.count_size.div_ceil(self.workgroup_size), self.max_workgroups_per_dimension);
+            bucket_ranges_pass.dispatch_workgroups(x, y, z);
         }
 
         {
             let sort_pass = &mut encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
                 label: Some("Sort Pass"),
-                timestamp_writes: None,
+                timestamp_writes: self.timestamps.as_ref().map(|timestamps| timestamps.pass_writes(stage)),
             });
 
             sort_pass.set_pipeline(&self.sorting_pipeline);
             sort_pass.set_bind_group(0, &self.counting_bind_group, &[]);
             sort_pass.set_bind_group(1, &self.sorting_bind_group, &[]);
-            sort_pass.dispatch_workgroups(value_workgroup_size_x, 1, 1);
+            let [x, y, z] = fold_workgroup_count(value_workgroup_count, self.max_workgroups_per_dimension);
+            sort_pass.dispatch_workgroups(x, y, z);
+        }
+
+        if let Some(timestamps) = &self.timestamps {
+            timestamps.resolve(encoder, stage + 1);
         }
         encoder.pop_debug_group();
     }
 
-    pub fn sorting_id_buffer(&self) -> &wgpu::Buffer { &self.sorting_id_buffer }
+    // Stages timed when the module has been created `with_timestamps`, in dispatch order:
+    // "count", "scan level i" for each level, "propagate level i" from the last level but one down to 0, "bucket ranges" (if any) and "scatter"
+    pub fn stage_names(&self) -> Vec<String> {
+        let mut stage_names = vec!["count".to_owned()];
+        stage_names.extend(self.scan_pipelines.stage_names(self.count_size, self.workgroup_size));
+        if self.bucket_ranges.is_some() {
+            stage_names.push("bucket ranges".to_owned());
+        }
+        stage_names.push("scatter".to_owned());
+        stage_names
+    }
+
+    // Stages after which the count buffer is copied in debug mode, in dispatch order:
+    // "count", "scan level i" for each level and "propagate level i" from the last level but one down to 0
+    pub fn debug_snapshot_stage_names(&self) -> Vec<String> {
+        let mut stage_names = vec!["count".to_owned()];
+        stage_names.extend(self.scan_pipelines.stage_names(self.count_size, self.workgroup_size));
+        stage_names
+    }
+
+    // Buffers (STORAGE | COPY_SRC) of the debug snapshots in the order of `debug_snapshot_stage_names`
+    // None unless the module has been created `with_debug_snapshots`
+    pub fn debug_snapshot_buffers(&self) -> Option<&[wgpu::Buffer]> { self.debug_snapshots.as_ref().map(CountSnapshots::buffers) }
+
+    // None when the count buffer is provided by the caller
+    // After `dispatch_work` it holds the start offset of each bucket
+    pub fn count_buffer(&self) -> Option<&wgpu::Buffer> { self.count_buffer.as_ref() }
+
+    // None when the sorting ids are written into a binding given to `new_with_bindings`
+    pub fn sorting_id_buffer(&self) -> Option<&wgpu::Buffer> { self.sorting_id_buffer.as_ref() }
+
+    // Buffer of `vec2<u32>` holding the [start, end) range of each bucket in the sorting id buffer
+    // Elements with key k are `sorting_ids[start..end]` with (start, end) = bucket_ranges[k]
+    // None unless the module has been created `with_bucket_ranges`
+    pub fn bucket_range_buffer(&self) -> Option<&wgpu::Buffer> { self.bucket_ranges.as_ref().map(|bucket_ranges| &bucket_ranges.buffer) }
+
+    // Configuration of the module, to build sibling modules with `CountingSortDescriptor::build`
+    pub fn descriptor(&self) -> &CountingSortDescriptor { &self.descriptor }
+
+    // Resolved workgroup size (see `WorkgroupSize::Auto`)
+    pub fn workgroup_size(&self) -> u32 { self.workgroup_size }
+
+    pub fn value_size(&self) -> u32 { self.value_size }
+
+    pub fn count_size(&self) -> u32 { self.count_size }
+}
+
+// Readback of the results, to await after submitting the `dispatch_work` commands
+// On native backends the futures are only woken while the device is polled (`device.poll`, wgpu's convention for mappings), so an application
+// awaiting them keeps polling the device (e.g. once per frame) and blocking callers use `block_on_readback`, on WebGPU the browser wakes them
+impl GpuCountingSortModule {
+    // Errors with `MissingOutputBuffer` when the sorting ids are written into a binding given to `new_with_bindings`
+    pub fn read_sorting_ids<'a>(
+        &self,
+        device: &'a wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> impl Future<Output = Result<Vec<u32>, CountingSortingError>> + 'a {
+        let read = self
+            .sorting_id_buffer
+            .as_ref()
+            .map(|buffer| host::read_buffer(device, queue, buffer, 0, self.value_size as wgpu::BufferAddress * 4))
+            .ok_or(CountingSortingError::MissingOutputBuffer("sorting id"));
+        async move { read?.await }
+    }
+
+    // Start offset of each bucket, errors with `MissingOutputBuffer` when the count buffer is provided by the caller
+    pub fn read_bucket_starts<'a>(
+        &self,
+        device: &'a wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> impl Future<Output = Result<Vec<u32>, CountingSortingError>> + 'a {
+        let read = self
+            .count_buffer
+            .as_ref()
+            .map(|buffer| host::read_buffer(device, queue, buffer, 0, self.count_size as wgpu::BufferAddress * 4))
+            .ok_or(CountingSortingError::MissingOutputBuffer("count"));
+        async move { read?.await }
+    }
+
+    // Keys in sorted order, gathered from the values binding of the module in values_buffer (which needs COPY_SRC)
+    pub fn read_sorted_keys<'a>(
+        &self,
+        device: &'a wgpu::Device,
+        queue: &wgpu::Queue,
+        values_buffer: &wgpu::Buffer,
+    ) -> impl Future<Output = Result<Vec<u32>, CountingSortingError>> + 'a {
+        let keys_read = host::read_buffer::<u32>(device, queue, values_buffer, self.values_offset, self.value_size as wgpu::BufferAddress * 4);
+        let sorting_ids_read = self.read_sorting_ids(device, queue);
+        async move {
+            let keys = keys_read.await?;
+            Ok(sorting_ids_read.await?.iter().map(|&id| keys[id as usize]).collect())
+        }
+    }
+
+    // GPU duration of each stage of the last submitted `dispatch_work` (see `stage_names`)
+    // Errors with `MissingOutputBuffer` unless the module has been created `with_timestamps`
+    pub fn read_stage_durations<'a>(
+        &self,
+        device: &'a wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> impl Future<Output = Result<Vec<StageDuration>, CountingSortingError>> + 'a {
+        let read = self
+            .timestamps
+            .as_ref()
+            .map(|timestamps| timestamps.read_durations(device, queue, self.stage_names()))
+            .ok_or(CountingSortingError::MissingOutputBuffer("timestamp"));
+        async move { read?.await }
+    }
+
+    // Count buffer after each stage of the last submitted `dispatch_work` (see `debug_snapshot_stage_names`), to dump with `dump_count_snapshots`
+    // Errors with `MissingOutputBuffer` unless the module has been created `with_debug_snapshots`
+    pub fn read_debug_snapshots<'a>(
+        &self,
+        device: &'a wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> impl Future<Output = Result<Vec<CountSnapshot>, CountingSortingError>> + 'a {
+        let read = self
+            .debug_snapshots
+            .as_ref()
+            .map(|debug_snapshots| debug_snapshots.read(device, queue, self.debug_snapshot_stage_names()))
+            .ok_or(CountingSortingError::MissingOutputBuffer("debug snapshot"));
+        async move { read?.await }
+    }
+
+    // [start, end) range of each bucket, errors with `MissingOutputBuffer` unless the module has been created `with_bucket_ranges`
+    pub fn read_bucket_ranges<'a>(
+        &self,
+        device: &'a wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> impl Future<Output = Result<Vec<[u32; 2]>, CountingSortingError>> + 'a {
+        let read = self
+            .bucket_ranges
+            .as_ref()
+            .map(|bucket_ranges| host::read_buffer(device, queue, &bucket_ranges.buffer, 0, bucket_ranges.buffer.size()))
+            .ok_or(CountingSortingError::MissingOutputBuffer("bucket range"));
+        async move { read?.await }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fold_workgroup_count;
+
+    #[test]
+    fn fold_workgroup_count_covers_every_workgroup() {
+        assert_eq!(fold_workgroup_count(0, 65535), [0, 0, 0]);
+        assert_eq!(fold_workgroup_count(1000, 65535), [1000, 1, 1]);
+        assert_eq!(fold_workgroup_count(65535, 65535), [65535, 1, 1]);
+        assert_eq!(fold_workgroup_count(65536, 65535), [65535, 2, 1]);
+        assert_eq!(fold_workgroup_count(20, 4), [4, 4, 2]);
+
+        for (workgroup_count, max_workgroups_per_dimension) in [(131072, 65535), (1 << 20, 1024), (999_999, 100), (999, 10)] {
+            let [x, y, z] = fold_workgroup_count(workgroup_count, max_workgroups_per_dimension);
+            assert!(x <= max_workgroups_per_dimension && y <= max_workgroups_per_dimension && z <= max_workgroups_per_dimension);
+            // Every linear index is dispatched and less than a row of extra workgroups is wasted
+            let dispatched = x as u64 * y as u64 * z as u64;
+            assert!(dispatched >= workgroup_count as u64);
+            assert!(dispatched - (workgroup_count as u64) < x as u64 * y as u64);
+        }
+    }
 }
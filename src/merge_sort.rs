@@ -0,0 +1,211 @@
+use std::sync::Arc;
+
+use oxyde::{
+    wgpu::{self, util::DeviceExt},
+    wgpu_utils::{binding_builder, buffers},
+};
+
+use crate::{fold_workgroup_count, CountingSortDescriptor, CountingSortingError, ErrorScope, WorkgroupSize};
+
+// Ids written by each invocation of a merge pass
+const MERGE_ITEMS_PER_INVOCATION: u32 = 8;
+
+// Comparator of `GpuMergeSortModule`: WGSL source defining `fn less(a: u32, b: u32) -> bool` on element indices
+// Its resources (e.g. the records to compare) are declared at @group(2) and bound with `with_resources`
+pub struct MergeSortComparator<'a> {
+    source: &'a str,
+    resources: Option<(&'a wgpu::BindGroupLayout, wgpu::BindGroup)>,
+}
+
+impl<'a> MergeSortComparator<'a> {
+    pub fn new(source: &'a str) -> Self { Self { source, resources: None } }
+
+    // The module keeps the bind group, rebuild the module to compare other resources
+    pub fn with_resources(mut self, bind_group_layout: &'a wgpu::BindGroupLayout, bind_group: wgpu::BindGroup) -> Self {
+        self.resources = Some((bind_group_layout, bind_group));
+        self
+    }
+}
+
+// Merge pass of pairs of sorted runs
+struct MergePass {
+    invocation_count: u32,
+    bind_group: wgpu::BindGroup,
+    // Kept alive for bind_group
+    _buffer: wgpu::Buffer,
+}
+
+// Merge sort of element indices by a custom comparator, for orders that don't reduce to an integer key (e.g. lexicographic on floats with an epsilon)
+// Ids start as [0, element_count) and runs of 1, 2, 4, ... ids are merged pass after pass (log2(element_count) passes) between two id buffers
+// Each merge is split in tiles of consecutive outputs located with a binary search along the merge path, so every invocation does the same work
+// Ties keep the order of the ids so the sort is stable
+pub struct GpuMergeSortModule {
+    workgroup_size: u32,
+    max_workgroups_per_dimension: u32,
+    element_count: u32,
+
+    // The sorted ids end up in id_buffers[merge_passes.len() % 2]
+    id_buffers: [wgpu::Buffer; 2],
+    // bind_groups[i] writes into id_buffers[i] (reading the other one)
+    bind_groups: [wgpu::BindGroup; 2],
+    comparator_bind_group: Option<wgpu::BindGroup>,
+    merge_passes: Vec<MergePass>,
+
+    init_ids_pipeline: Arc<wgpu::ComputePipeline>,
+    merge_runs_pipeline: Arc<wgpu::ComputePipeline>,
+}
+
+impl GpuMergeSortModule {
+    pub fn new(
+        device: &wgpu::Device,
+        element_count: u32,
+        comparator: MergeSortComparator,
+        workgroup_size: impl Into<WorkgroupSize>,
+    ) -> Result<Self, CountingSortingError> {
+        Self::from_descriptor(device, &CountingSortDescriptor::default().with_workgroup_size(workgroup_size), element_count, comparator)
+    }
+
+    // Uses the label, workgroup size, output usages (of the id buffers) and pipeline cache of the descriptor,
+    // the other options are rejected with `UnsupportedOption`
+    pub fn from_descriptor(
+        device: &wgpu::Device,
+        descriptor: &CountingSortDescriptor,
+        element_count: u32,
+        comparator: MergeSortComparator,
+    ) -> Result<Self, CountingSortingError> {
+        let limits = device.limits();
+        let workgroup_size = descriptor.validate(&limits)?;
+        descriptor.validate_base_options("merge sort")?;
+        let size_of_u32 = std::mem::size_of::<u32>() as wgpu::BufferAddress;
+
+        if element_count == 0 {
+            return Err(CountingSortingError::EmptyInput("Merge sort elements"));
+        }
+
+        let id_buffer_size = element_count as wgpu::BufferAddress * size_of_u32;
+        if id_buffer_size > limits.max_storage_buffer_binding_size as wgpu::BufferAddress {
+            return Err(CountingSortingError::DeviceLimitExceeded(
+                id_buffer_size,
+                limits.max_storage_buffer_binding_size as wgpu::BufferAddress,
+                "max_storage_buffer_binding_size",
+            ));
+        }
+
+        let error_scope = ErrorScope::push(device);
+
+        let id_buffers = [0, 1].map(|index| {
+            buffers::create_buffer_for_size(
+                device,
+                wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | descriptor.output_usages,
+                Some(&descriptor.label(&format!("merge sort id buffer {}", index))),
+                id_buffer_size,
+            )
+        });
+
+        // init bind groups (the layout of the ids is the read write one of the counting sort)
+        let pipeline_cache = descriptor.pipeline_cache_or_private(device, Some("merge sort"));
+        let read_write_bind_group_layout_with_desc = pipeline_cache.read_write_bind_group_layout();
+        let merge_pass_bind_group_layout_with_desc = pipeline_cache.uniform_bind_group_layout();
+
+        let bind_groups = [(1, 0), (0, 1)].map(|(source, destination): (usize, usize)| {
+            binding_builder::BindGroupBuilder::new(read_write_bind_group_layout_with_desc)
+                .resource(id_buffers[source].as_entire_binding())
+                .resource(id_buffers[destination].as_entire_binding())
+                .create(device, Some(&descriptor.label(&format!("merge sort bind_group (into id buffer {})", destination))))
+        });
+
+        let merge_passes = std::iter::successors(Some(1u32), |&run_size| run_size.checked_mul(2))
+            .take_while(|&run_size| run_size < element_count)
+            .map(|run_size| {
+                let invocations_per_run_pair = (2 * run_size as u64).div_ceil(MERGE_ITEMS_PER_INVOCATION as u64) as u32;
+                let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some(&descriptor.label("merge sort pass buffer")),
+                    contents: bytemuck::cast_slice(&[run_size, invocations_per_run_pair]),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                });
+
+                MergePass {
+                    invocation_count: (element_count as u64).div_ceil(2 * run_size as u64) as u32 * invocations_per_run_pair,
+                    bind_group: binding_builder::BindGroupBuilder::new(merge_pass_bind_group_layout_with_desc)
+                        .resource(buffer.as_entire_binding())
+                        .create(device, Some(&descriptor.label(&format!("merge sort pass bind_group (runs of {})", run_size)))),
+                    _buffer: buffer,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // Pipelines
+        let shader_source = format!("{}\n{}", comparator.source, include_str!("../shaders/merge_sort.wgsl"));
+        let shader_defines = [("WORKGROUP_SIZE", workgroup_size), ("ITEMS_PER_INVOCATION", MERGE_ITEMS_PER_INVOCATION)];
+
+        let init_ids_pipeline = pipeline_cache.get_or_create(
+            device,
+            "merge_sort",
+            &shader_source,
+            "init_ids",
+            &shader_defines,
+            &[&read_write_bind_group_layout_with_desc.layout],
+        )?;
+
+        let mut merge_runs_bind_group_layouts = vec![&read_write_bind_group_layout_with_desc.layout, &merge_pass_bind_group_layout_with_desc.layout];
+        merge_runs_bind_group_layouts.extend(comparator.resources.as_ref().map(|(bind_group_layout, _)| *bind_group_layout));
+        let merge_runs_pipeline =
+            pipeline_cache.get_or_create(device, "merge_sort", &shader_source, "merge_runs", &shader_defines, &merge_runs_bind_group_layouts)?;
+
+        error_scope.pop()?;
+
+        Ok(Self {
+            workgroup_size,
+            max_workgroups_per_dimension: limits.max_compute_workgroups_per_dimension,
+            element_count,
+
+            id_buffers,
+            bind_groups,
+            comparator_bind_group: comparator.resources.map(|(_, bind_group)| bind_group),
+            merge_passes,
+
+            init_ids_pipeline,
+            merge_runs_pipeline,
+        })
+    }
+
+    pub fn dispatch_work(&self, encoder: &mut wgpu::CommandEncoder) {
+        log::trace!("[GpuMergeSortModule] {} merge passes (for {} elements)", self.merge_passes.len(), self.element_count);
+
+        encoder.push_debug_group("Merge Sort");
+
+        {
+            let merge_pass = &mut encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Merge Sort Pass"),
+                timestamp_writes: None,
+            });
+
+            merge_pass.set_pipeline(&self.init_ids_pipeline);
+            merge_pass.set_bind_group(0, &self.bind_groups[0], &[]);
+            let [x, y, z] = fold_workgroup_count(self.element_count.div_ceil(self.workgroup_size), self.max_workgroups_per_dimension);
+            merge_pass.dispatch_workgroups(x, y, z);
+
+            merge_pass.set_pipeline(&self.merge_runs_pipeline);
+            if let Some(comparator_bind_group) = &self.comparator_bind_group {
+                merge_pass.set_bind_group(2, comparator_bind_group, &[]);
+            }
+            for (pass_index, pass) in self.merge_passes.iter().enumerate() {
+                merge_pass.set_bind_group(0, &self.bind_groups[(pass_index + 1) % 2], &[]);
+                merge_pass.set_bind_group(1, &pass.bind_group, &[]);
+                let [x, y, z] = fold_workgroup_count(pass.invocation_count.div_ceil(self.workgroup_size), self.max_workgroups_per_dimension);
+                merge_pass.dispatch_workgroups(x, y, z);
+            }
+        }
+        encoder.pop_debug_group();
+    }
+
+    // Ids of the elements sorted by the comparator
+    pub fn sorting_id_buffer(&self) -> &wgpu::Buffer { &self.id_buffers[self.merge_passes.len() % 2] }
+
+    pub fn workgroup_size(&self) -> u32 { self.workgroup_size }
+
+    pub fn element_count(&self) -> u32 { self.element_count }
+
+    // ceil(log2(element_count))
+    pub fn merge_pass_count(&self) -> u32 { self.merge_passes.len() as u32 }
+}
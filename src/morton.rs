@@ -0,0 +1,218 @@
+use oxyde::{
+    wgpu::{self, util::DeviceExt},
+    wgpu_utils::{binding_builder, buffers, ShaderComposer},
+};
+
+use crate::{fold_workgroup_count, with_common_wgsl, CountingSortingError, ErrorScope, WorkgroupSize};
+
+// WGSL helpers to quantize positions and interleave their bits into Morton codes
+// Prepend it to a shader source to use `morton_code_3d` / `morton_code_2d` with your own `MortonParams`
+pub const MORTON_WGSL: &str = include_str!("../shaders/morton_common.wgsl");
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MortonDimension {
+    // Positions are `vec2<f32>`, up to 16 bits per axis (32-bit codes)
+    Two,
+    // Positions are `vec4<f32>` (w is ignored), up to 10 bits per axis (30-bit codes)
+    Three,
+}
+
+impl MortonDimension {
+    pub fn axis_count(&self) -> u32 {
+        match self {
+            MortonDimension::Two => 2,
+            MortonDimension::Three => 3,
+        }
+    }
+
+    pub fn max_bits_per_axis(&self) -> u32 {
+        match self {
+            MortonDimension::Two => 16,
+            MortonDimension::Three => 10,
+        }
+    }
+
+    fn position_size(&self) -> wgpu::BufferAddress {
+        match self {
+            MortonDimension::Two => std::mem::size_of::<[f32; 2]>() as _,
+            MortonDimension::Three => std::mem::size_of::<[f32; 4]>() as _,
+        }
+    }
+}
+
+// Same layout as the `MortonParams` struct of the WGSL helpers
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+pub struct MortonParams {
+    pub bounds_min: [f32; 3],
+    pub bits_per_axis: u32,
+    pub bounds_max: [f32; 3],
+    padding: u32,
+}
+
+impl MortonParams {
+    pub fn new(bounds_min: [f32; 3], bounds_max: [f32; 3], bits_per_axis: u32) -> Self {
+        Self {
+            bounds_min,
+            bits_per_axis,
+            bounds_max,
+            padding: 0,
+        }
+    }
+}
+
+// Compute the Morton (Z-order) code of each position within the given bounds into a key buffer ready for sorting
+// Codes are made of `bits_per_axis` bits per axis, so with fewer bits the key range (`key_range`) is small enough for the counting sort
+pub struct GpuMortonKeyModule {
+    workgroup_size: u32,
+    max_workgroups_per_dimension: u32,
+    element_count: u32,
+    dimension: MortonDimension,
+    params: MortonParams,
+
+    params_buffer: wgpu::Buffer,
+    key_buffer: wgpu::Buffer,
+
+    keys_bind_group: wgpu::BindGroup,
+    params_bind_group: wgpu::BindGroup,
+    pipeline: wgpu::ComputePipeline,
+}
+
+impl GpuMortonKeyModule {
+    pub fn new(
+        device: &wgpu::Device,
+        positions_buffer: &wgpu::Buffer,
+        dimension: MortonDimension,
+        bounds_min: [f32; 3],
+        bounds_max: [f32; 3],
+        bits_per_axis: u32,
+        workgroup_size: impl Into<WorkgroupSize>,
+    ) -> Result<Self, CountingSortingError> {
+        if !positions_buffer.usage().contains(wgpu::BufferUsages::STORAGE) {
+            return Err(CountingSortingError::MissingBufferUsage(wgpu::BufferUsages::STORAGE, "Positions buffer"));
+        }
+
+        if bits_per_axis == 0 || bits_per_axis > dimension.max_bits_per_axis() {
+            return Err(CountingSortingError::InvalidMortonBitsPerAxis(bits_per_axis, dimension.max_bits_per_axis()));
+        }
+
+        let workgroup_size = workgroup_size.into().resolve(&device.limits())?;
+
+        let params = MortonParams::new(bounds_min, bounds_max, bits_per_axis);
+
+        let element_count = (positions_buffer.size() / dimension.position_size()) as u32;
+
+        let error_scope = ErrorScope::push(device);
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("morton params buffer"),
+            contents: bytemuck::bytes_of(&params),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let key_buffer = buffers::create_buffer_for_size(
+            device,
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            Some("morton key buffer"),
+            element_count as wgpu::BufferAddress * std::mem::size_of::<u32>() as wgpu::BufferAddress,
+        );
+
+        let read_write_bind_group_layout_with_desc = binding_builder::BindGroupLayoutBuilder::new()
+            .add_binding_compute(wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            })
+            .add_binding_compute(wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            })
+            .create(device, None);
+
+        let uniform_bind_group_layout_with_desc = binding_builder::BindGroupLayoutBuilder::new()
+            .add_binding_compute(wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            })
+            .create(device, None);
+
+        let keys_bind_group = binding_builder::BindGroupBuilder::new(&read_write_bind_group_layout_with_desc)
+            .resource(positions_buffer.as_entire_binding())
+            .resource(key_buffer.as_entire_binding())
+            .create(device, Some("morton_keys_bind_group"));
+
+        let params_bind_group = binding_builder::BindGroupBuilder::new(&uniform_bind_group_layout_with_desc)
+            .resource(params_buffer.as_entire_binding())
+            .create(device, Some("morton_params_bind_group"));
+
+        let shader_source = with_common_wgsl(&format!("{}\n{}", MORTON_WGSL, include_str!("../shaders/morton.wgsl")));
+        let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("morton shader"),
+            source: wgpu::ShaderSource::Naga(std::borrow::Cow::Owned(
+                ShaderComposer::new(&shader_source, Some("morton"))
+                    .with_shader_define("WORKGROUP_SIZE", workgroup_size.into())
+                    .with_shader_define("MORTON_DIMENSION", dimension.axis_count().into())
+                    .build()
+                    .map_err(|err| CountingSortingError::ShaderComposition("morton", err.to_string()))?,
+            )),
+        });
+
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("morton keys pipeline"),
+            layout: Some(&device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("morton keys pipeline layout"),
+                bind_group_layouts: &[&read_write_bind_group_layout_with_desc.layout, &uniform_bind_group_layout_with_desc.layout],
+                push_constant_ranges: &[],
+            })),
+            module: &shader_module,
+            entry_point: "compute_morton_keys",
+        });
+
+        error_scope.pop()?;
+
+        Ok(Self {
+            workgroup_size,
+            max_workgroups_per_dimension: device.limits().max_compute_workgroups_per_dimension,
+            element_count,
+            dimension,
+            params,
+
+            params_buffer,
+            key_buffer,
+
+            keys_bind_group,
+            params_bind_group,
+            pipeline,
+        })
+    }
+
+    pub fn dispatch_work(&self, encoder: &mut wgpu::CommandEncoder) {
+        let morton_pass = &mut encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Morton Keys Pass"),
+            timestamp_writes: None,
+        });
+
+        morton_pass.set_pipeline(&self.pipeline);
+        morton_pass.set_bind_group(0, &self.keys_bind_group, &[]);
+        morton_pass.set_bind_group(1, &self.params_bind_group, &[]);
+        let [x, y, z] = fold_workgroup_count(self.element_count.div_ceil(self.workgroup_size), self.max_workgroups_per_dimension);
+        morton_pass.dispatch_workgroups(x, y, z);
+    }
+
+    // Update the bounds used to quantize the positions (e.g. the bounding box of this frame)
+    pub fn set_bounds(&mut self, queue: &wgpu::Queue, bounds_min: [f32; 3], bounds_max: [f32; 3]) {
+        self.params.bounds_min = bounds_min;
+        self.params.bounds_max = bounds_max;
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&self.params));
+    }
+
+    // Number of distinct keys (the count buffer size needed to counting sort the keys)
+    pub fn key_range(&self) -> u64 { 1u64 << (self.dimension.axis_count() * self.params.bits_per_axis) }
+
+    pub fn key_buffer(&self) -> &wgpu::Buffer { &self.key_buffer }
+
+    pub fn params(&self) -> &MortonParams { &self.params }
+
+    pub fn element_count(&self) -> u32 { self.element_count }
+}
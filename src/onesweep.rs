@@ -0,0 +1,323 @@
+use std::sync::Arc;
+
+use oxyde::{
+    wgpu::{self, util::DeviceExt},
+    wgpu_utils::{binding_builder, buffers},
+};
+
+use crate::{
+    create_clearing_pipeline, create_histogram_pipeline, fold_workgroup_count, scan::ScanPipelines, validate_storage_binding, CountingSortDescriptor,
+    CountingSortingError, ErrorScope, WorkgroupSize,
+};
+
+// Bits of each digit (a scatter pass per digit)
+const RADIX_BITS: u32 = 8;
+const RADIX: u32 = 1 << RADIX_BITS;
+// Tile status values keep 2 bits for the flags
+const MAX_VALUE_SIZE: u32 = 1 << 30;
+
+// Scatter pass of a digit
+struct DigitPass {
+    bind_group: wgpu::BindGroup,
+    radix_pass_bind_group: wgpu::BindGroup,
+    // Kept alive for radix_pass_bind_group
+    _radix_pass_buffer: wgpu::Buffer,
+}
+
+// Onesweep radix sort of u32 keys, for large value counts where it beats passes of counting sort per digit:
+// 1. The histograms of every 8 bits digit are counted in a single pass over the keys (the counting pass in digit mode)
+// 2. They are scanned at once (a single scan of digit_count * 256 buckets)
+// 3. A scatter pass per digit, where each tile of keys gets its offset in each bucket with a chained scan (decoupled look back) over the previous tiles
+//
+// Keys and ids are ping-ponged between two pairs of buffers and the sort is stable
+// The chained scan waits for the previous tiles with a single spin budget per look back, once it is spent the previous tiles
+// that haven't published their count are counted by the waiting workgroup itself
+// so the sort doesn't rely on the forward progress of running workgroups (which most GPUs guarantee but WebGPU doesn't)
+pub struct GpuOnesweepRadixSortModule {
+    workgroup_size: u32,
+    max_workgroups_per_dimension: u32,
+    value_size: u32,
+    key_bits: u32,
+
+    // The sorted keys and ids end up in the buffers of index (digit count - 1) % 2
+    key_buffers: [wgpu::Buffer; 2],
+    id_buffers: [wgpu::Buffer; 2],
+    // Kept alive for the bind groups
+    _digit_histogram_buffer: wgpu::Buffer,
+    tile_status_buffer_size: u32,
+    _tile_status_buffer: wgpu::Buffer,
+
+    histogram_bind_group: wgpu::BindGroup,
+    digit_histogram_bind_group: wgpu::BindGroup,
+    tile_status_bind_group: wgpu::BindGroup,
+    digit_passes: Vec<DigitPass>,
+
+    clearing_pipeline: Arc<wgpu::ComputePipeline>,
+    histogram_pipeline: Arc<wgpu::ComputePipeline>,
+    scan_pipelines: ScanPipelines,
+    first_scatter_pipeline: Arc<wgpu::ComputePipeline>,
+    scatter_pipeline: Arc<wgpu::ComputePipeline>,
+}
+
+impl GpuOnesweepRadixSortModule {
+    // Only the key_bits low bits of the keys are sorted (e.g. 30 for 3D Morton codes), the higher bits are ignored but kept in `sorted_key_buffer`
+    pub fn new(
+        device: &wgpu::Device,
+        values_buffer: &wgpu::Buffer,
+        key_bits: u32,
+        workgroup_size: impl Into<WorkgroupSize>,
+    ) -> Result<Self, CountingSortingError> {
+        Self::from_descriptor(device, &CountingSortDescriptor::default().with_workgroup_size(workgroup_size), values_buffer, key_bits)
+    }
+
+    // Uses the label, workgroup size, output usages (of the key and id buffers) and pipeline cache of the descriptor,
+    // the other options are rejected with `UnsupportedOption`
+    pub fn from_descriptor(
+        device: &wgpu::Device,
+        descriptor: &CountingSortDescriptor,
+        values_buffer: &wgpu::Buffer,
+        key_bits: u32,
+    ) -> Result<Self, CountingSortingError> {
+        let limits = device.limits();
+        let workgroup_size = descriptor.validate(&limits)?;
+        descriptor.validate_base_options("onesweep radix sort")?;
+        let size_of_u32 = std::mem::size_of::<u32>() as wgpu::BufferAddress;
+
+        if key_bits == 0 || key_bits > u32::BITS {
+            return Err(CountingSortingError::InvalidKeyBits(key_bits));
+        }
+
+        let values_size = validate_storage_binding(&limits, &values_buffer.as_entire_buffer_binding(), "Values buffer")?;
+        let value_size = (values_size / size_of_u32) as u32;
+        if value_size >= MAX_VALUE_SIZE {
+            return Err(CountingSortingError::DeviceLimitExceeded(value_size as u64, MAX_VALUE_SIZE as u64, "Onesweep value count"));
+        }
+
+        let digit_count = key_bits.div_ceil(RADIX_BITS);
+        let digit_histogram_size = digit_count * RADIX;
+        let tile_count = value_size.div_ceil(workgroup_size);
+        let tile_status_buffer_size = 1 + tile_count * RADIX;
+        let tile_status_binding_size = tile_status_buffer_size as wgpu::BufferAddress * size_of_u32;
+        if tile_status_binding_size > limits.max_storage_buffer_binding_size as wgpu::BufferAddress {
+            return Err(CountingSortingError::DeviceLimitExceeded(
+                tile_status_binding_size,
+                limits.max_storage_buffer_binding_size as wgpu::BufferAddress,
+                "max_storage_buffer_binding_size",
+            ));
+        }
+
+        let error_scope = ErrorScope::push(device);
+
+        let output_buffer = |name: &str, index: usize| {
+            buffers::create_buffer_for_size(
+                device,
+                wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | descriptor.output_usages,
+                Some(&descriptor.label(&format!("onesweep {} buffer {}", name, index))),
+                values_size,
+            )
+        };
+        let key_buffers = [0, 1].map(|index| output_buffer("key", index));
+        let id_buffers = [0, 1].map(|index| output_buffer("id", index));
+
+        let digit_histogram_buffer = buffers::create_buffer_for_size(
+            device,
+            wgpu::BufferUsages::STORAGE,
+            Some(&descriptor.label("onesweep digit histogram buffer")),
+            digit_histogram_size as wgpu::BufferAddress * size_of_u32,
+        );
+
+        let tile_status_buffer = buffers::create_buffer_for_size(
+            device,
+            wgpu::BufferUsages::STORAGE,
+            Some(&descriptor.label("onesweep tile status buffer")),
+            tile_status_binding_size,
+        );
+
+        // init bind groups (the histogram pass and the scan use the layouts of the counting sort)
+        let pipeline_cache = descriptor.pipeline_cache_or_private(device, Some("onesweep"));
+
+        let storage_binding_type = |read_only| wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        };
+
+        let scatter_bind_group_layout_with_desc = pipeline_cache.get_or_create_bind_group_layout(device, "onesweep scatter", || {
+            binding_builder::BindGroupLayoutBuilder::new()
+                .add_binding_compute(storage_binding_type(true))
+                .add_binding_compute(storage_binding_type(true))
+                .add_binding_compute(storage_binding_type(false))
+                .add_binding_compute(storage_binding_type(false))
+                .add_binding_compute(storage_binding_type(true))
+                .add_binding_compute(storage_binding_type(false))
+        });
+        let radix_pass_bind_group_layout_with_desc = pipeline_cache.uniform_bind_group_layout();
+
+        let histogram_bind_group = binding_builder::BindGroupBuilder::new(pipeline_cache.read_write_bind_group_layout())
+            .resource(values_buffer.as_entire_binding())
+            .resource(digit_histogram_buffer.as_entire_binding())
+            .create(device, Some(&descriptor.label("onesweep histogram_bind_group")));
+
+        let digit_histogram_bind_group = binding_builder::BindGroupBuilder::new(pipeline_cache.storage_buffer_bind_group_layout())
+            .resource(digit_histogram_buffer.as_entire_binding())
+            .create(device, Some(&descriptor.label("onesweep digit_histogram_bind_group")));
+
+        let tile_status_bind_group = binding_builder::BindGroupBuilder::new(pipeline_cache.storage_buffer_bind_group_layout())
+            .resource(tile_status_buffer.as_entire_binding())
+            .create(device, Some(&descriptor.label("onesweep tile_status_bind_group")));
+
+        // Digit d reads the keys and ids written by digit d - 1 (the values for the first one, with ids_in unused) and writes into the buffers of index d % 2
+        let digit_passes = (0..digit_count)
+            .map(|digit| {
+                let (input, output) = ((digit as usize + 1) % 2, digit as usize % 2);
+                let keys_in = if digit == 0 { values_buffer } else { &key_buffers[input] };
+
+                let radix_pass_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                    label: Some(&descriptor.label("onesweep radix pass buffer")),
+                    contents: bytemuck::cast_slice(&[digit]),
+                    usage: wgpu::BufferUsages::UNIFORM,
+                });
+
+                DigitPass {
+                    bind_group: binding_builder::BindGroupBuilder::new(&scatter_bind_group_layout_with_desc)
+                        .resource(keys_in.as_entire_binding())
+                        .resource(id_buffers[input].as_entire_binding())
+                        .resource(key_buffers[output].as_entire_binding())
+                        .resource(id_buffers[output].as_entire_binding())
+                        .resource(digit_histogram_buffer.as_entire_binding())
+                        .resource(tile_status_buffer.as_entire_binding())
+                        .create(device, Some(&descriptor.label(&format!("onesweep scatter bind_group (digit {})", digit)))),
+                    radix_pass_bind_group: binding_builder::BindGroupBuilder::new(radix_pass_bind_group_layout_with_desc)
+                        .resource(radix_pass_buffer.as_entire_binding())
+                        .create(device, Some(&descriptor.label(&format!("onesweep radix_pass_bind_group (digit {})", digit)))),
+                    _radix_pass_buffer: radix_pass_buffer,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // Pipelines
+        let clearing_pipeline = create_clearing_pipeline(device, &pipeline_cache, workgroup_size)?;
+        let histogram_pipeline = create_histogram_pipeline(
+            device,
+            &pipeline_cache,
+            value_size,
+            digit_histogram_size,
+            workgroup_size,
+            1,
+            &[("DIGIT_COUNT", digit_count), ("RADIX_BITS", RADIX_BITS), ("KEY_BITS", key_bits)],
+        )?;
+        let scan_pipelines = ScanPipelines::new(device, &pipeline_cache, digit_histogram_size, workgroup_size)?;
+
+        let create_scatter_pipeline = |first_pass: bool| {
+            pipeline_cache.get_or_create(
+                device,
+                "onesweep",
+                include_str!("../shaders/onesweep.wgsl"),
+                "scatter",
+                &[
+                    ("WORKGROUP_SIZE", workgroup_size),
+                    ("RADIX", RADIX),
+                    ("RADIX_BITS", RADIX_BITS),
+                    ("KEY_BITS", key_bits),
+                    ("FIRST_PASS", first_pass as u32),
+                ],
+                &[&scatter_bind_group_layout_with_desc.layout, &radix_pass_bind_group_layout_with_desc.layout],
+            )
+        };
+        let first_scatter_pipeline = create_scatter_pipeline(true)?;
+        let scatter_pipeline = create_scatter_pipeline(false)?;
+
+        error_scope.pop()?;
+
+        Ok(Self {
+            workgroup_size,
+            max_workgroups_per_dimension: limits.max_compute_workgroups_per_dimension,
+            value_size,
+            key_bits,
+
+            key_buffers,
+            id_buffers,
+            _digit_histogram_buffer: digit_histogram_buffer,
+            tile_status_buffer_size,
+            _tile_status_buffer: tile_status_buffer,
+
+            histogram_bind_group,
+            digit_histogram_bind_group,
+            tile_status_bind_group,
+            digit_passes,
+
+            clearing_pipeline,
+            histogram_pipeline,
+            scan_pipelines,
+            first_scatter_pipeline,
+            scatter_pipeline,
+        })
+    }
+
+    pub fn dispatch_work(&self, encoder: &mut wgpu::CommandEncoder) {
+        log::trace!(
+            "[GpuOnesweepRadixSortModule] {} digit passes of {} tiles (for value buffer of {})",
+            self.digit_passes.len(),
+            self.value_size.div_ceil(self.workgroup_size),
+            self.value_size
+        );
+
+        let digit_histogram_size = self.digit_passes.len() as u32 * RADIX;
+        encoder.push_debug_group("Onesweep Radix Sort");
+
+        {
+            let histogram_pass = &mut encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Onesweep Histogram Pass"),
+                timestamp_writes: None,
+            });
+
+            histogram_pass.set_pipeline(&self.clearing_pipeline);
+            histogram_pass.set_bind_group(0, &self.digit_histogram_bind_group, &[]);
+            let [x, y, z] = fold_workgroup_count(digit_histogram_size.div_ceil(self.workgroup_size), self.max_workgroups_per_dimension);
+            histogram_pass.dispatch_workgroups(x, y, z);
+
+            histogram_pass.set_pipeline(&self.histogram_pipeline);
+            histogram_pass.set_bind_group(0, &self.histogram_bind_group, &[]);
+            let [x, y, z] = fold_workgroup_count(self.value_size.div_ceil(self.workgroup_size), self.max_workgroups_per_dimension);
+            histogram_pass.dispatch_workgroups(x, y, z);
+        }
+
+        self.scan_pipelines
+            .dispatch(encoder, &self.digit_histogram_bind_group, digit_histogram_size, self.workgroup_size, self.max_workgroups_per_dimension, None);
+
+        {
+            let scatter_pass = &mut encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Onesweep Scatter Pass"),
+                timestamp_writes: None,
+            });
+
+            for (digit, digit_pass) in self.digit_passes.iter().enumerate() {
+                // The tile counter and status are reset for each digit
+                scatter_pass.set_pipeline(&self.clearing_pipeline);
+                scatter_pass.set_bind_group(0, &self.tile_status_bind_group, &[]);
+                let [x, y, z] = fold_workgroup_count(self.tile_status_buffer_size.div_ceil(self.workgroup_size), self.max_workgroups_per_dimension);
+                scatter_pass.dispatch_workgroups(x, y, z);
+
+                scatter_pass.set_pipeline(if digit == 0 { &self.first_scatter_pipeline } else { &self.scatter_pipeline });
+                scatter_pass.set_bind_group(0, &digit_pass.bind_group, &[]);
+                scatter_pass.set_bind_group(1, &digit_pass.radix_pass_bind_group, &[]);
+                let [x, y, z] = fold_workgroup_count(self.value_size.div_ceil(self.workgroup_size), self.max_workgroups_per_dimension);
+                scatter_pass.dispatch_workgroups(x, y, z);
+            }
+        }
+        encoder.pop_debug_group();
+    }
+
+    pub fn sorting_id_buffer(&self) -> &wgpu::Buffer { &self.id_buffers[(self.digit_passes.len() - 1) % 2] }
+
+    // Keys in sorted order
+    pub fn sorted_key_buffer(&self) -> &wgpu::Buffer { &self.key_buffers[(self.digit_passes.len() - 1) % 2] }
+
+    pub fn workgroup_size(&self) -> u32 { self.workgroup_size }
+
+    pub fn value_size(&self) -> u32 { self.value_size }
+
+    pub fn key_bits(&self) -> u32 { self.key_bits }
+
+    pub fn digit_count(&self) -> u32 { self.digit_passes.len() as u32 }
+}
@@ -0,0 +1,204 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    sync::{Arc, Mutex},
+};
+
+use oxyde::{
+    wgpu,
+    wgpu_utils::{binding_builder, ShaderComposer},
+};
+
+use crate::{descriptor::prefixed_label, with_common_wgsl, CountingSortingError, ErrorScope};
+
+// Pipelines specialization: shader, entry point and shader defines (workgroup size, scan level, items per invocation, ...)
+// The source hash and the layouts tell apart pipelines sharing a shader name (e.g. merge sorts composed with different comparators)
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct PipelineKey {
+    shader_name: &'static str,
+    shader_source_hash: u64,
+    entry_point: &'static str,
+    shader_defines: Vec<(&'static str, u32)>,
+    bind_group_layouts: Vec<wgpu::Id<wgpu::BindGroupLayout>>,
+}
+
+// Compute pipelines (and the bind group layouts they use) shared between sorting modules
+// Modules built with the same cache (see `CountingSortDescriptor::with_pipeline_cache`) only compile the pipelines they don't share yet
+// Modules built without a cache use a private one
+pub struct SortPipelineCache {
+    label: Option<String>,
+    storage_buffer_bind_group_layout: binding_builder::BindGroupLayoutWithDesc,
+    read_write_bind_group_layout: binding_builder::BindGroupLayoutWithDesc,
+    read_write_pair_bind_group_layout: binding_builder::BindGroupLayoutWithDesc,
+    uniform_bind_group_layout: binding_builder::BindGroupLayoutWithDesc,
+    // Layouts specific to a sorting module (bitonic, onesweep scatter, ...), by name
+    bind_group_layouts: Mutex<HashMap<&'static str, Arc<binding_builder::BindGroupLayoutWithDesc>>>,
+    pipelines: Mutex<HashMap<PipelineKey, Arc<wgpu::ComputePipeline>>>,
+}
+
+impl SortPipelineCache {
+    // The label prefixes the labels of the layouts and pipelines of the cache
+    pub fn new(device: &wgpu::Device, label: Option<&str>) -> Self {
+        let storage_buffer_bind_group_layout = binding_builder::BindGroupLayoutBuilder::new()
+            .add_binding_compute(wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            })
+            .create(device, Some(&prefixed_label(label, "single storage buffer bind group layout")));
+
+        let read_write_bind_group_layout = binding_builder::BindGroupLayoutBuilder::new()
+            .add_binding_compute(wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            })
+            .add_binding_compute(wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            })
+            .create(device, Some(&prefixed_label(label, "read write bind group layout")));
+
+        let read_write_pair_bind_group_layout = binding_builder::BindGroupLayoutBuilder::new()
+            .add_binding_compute(wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            })
+            .add_binding_compute(wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            })
+            .create(device, Some(&prefixed_label(label, "read write pair bind group layout")));
+
+        let uniform_bind_group_layout = binding_builder::BindGroupLayoutBuilder::new()
+            .add_binding_compute(wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            })
+            .create(device, Some(&prefixed_label(label, "uniform bind group layout")));
+
+        Self {
+            label: label.map(str::to_owned),
+            storage_buffer_bind_group_layout,
+            read_write_bind_group_layout,
+            read_write_pair_bind_group_layout,
+            uniform_bind_group_layout,
+            bind_group_layouts: Mutex::new(HashMap::new()),
+            pipelines: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Layout of a single read write storage buffer
+    pub(crate) fn storage_buffer_bind_group_layout(&self) -> &binding_builder::BindGroupLayoutWithDesc { &self.storage_buffer_bind_group_layout }
+
+    // Layout of a read only storage buffer followed by a read write one
+    pub(crate) fn read_write_bind_group_layout(&self) -> &binding_builder::BindGroupLayoutWithDesc { &self.read_write_bind_group_layout }
+
+    // Layout of two read write storage buffers, for values sharing their buffer with a read write binding
+    pub(crate) fn read_write_pair_bind_group_layout(&self) -> &binding_builder::BindGroupLayoutWithDesc { &self.read_write_pair_bind_group_layout }
+
+    // Layout of a single uniform buffer (pass parameters, e.g. the chunk starts of the chunked sort)
+    pub(crate) fn uniform_bind_group_layout(&self) -> &binding_builder::BindGroupLayoutWithDesc { &self.uniform_bind_group_layout }
+
+    // Get the layout of the given name, creating it from the bindings of layout_builder on the first request
+    // so that modules sharing the cache bind their resources with the same layouts (and share their pipelines)
+    pub(crate) fn get_or_create_bind_group_layout(
+        &self,
+        device: &wgpu::Device,
+        name: &'static str,
+        layout_builder: impl FnOnce() -> binding_builder::BindGroupLayoutBuilder,
+    ) -> Arc<binding_builder::BindGroupLayoutWithDesc> {
+        self.bind_group_layouts
+            .lock()
+            .unwrap()
+            .entry(name)
+            .or_insert_with(|| {
+                let label = prefixed_label(self.label.as_deref(), &format!("{} bind group layout", name));
+                Arc::new(layout_builder().create(device, Some(&label)))
+            })
+            .clone()
+    }
+
+    // Get the pipeline of a shader entry point specialized with shader_defines, compiling it on the first request
+    // Pipelines are only cached once created without validation error, so a failed request is retried by the next one
+    pub(crate) fn get_or_create(
+        &self,
+        device: &wgpu::Device,
+        shader_name: &'static str,
+        shader_source: &str,
+        entry_point: &'static str,
+        shader_defines: &[(&'static str, u32)],
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+    ) -> Result<Arc<wgpu::ComputePipeline>, CountingSortingError> {
+        let mut hasher = DefaultHasher::new();
+        shader_source.hash(&mut hasher);
+        let key = PipelineKey {
+            shader_name,
+            shader_source_hash: hasher.finish(),
+            entry_point,
+            shader_defines: shader_defines.to_vec(),
+            bind_group_layouts: bind_group_layouts.iter().map(|layout| layout.global_id()).collect(),
+        };
+
+        // The lock isn't held while compiling so that modules sharing the cache don't wait for each other's pipelines
+        if let Some(pipeline) = self.pipelines.lock().unwrap().get(&key) {
+            return Ok(pipeline.clone());
+        }
+
+        let error_scope = ErrorScope::push(device);
+        let shader_source = with_common_wgsl(shader_source);
+        let mut shader_composer = ShaderComposer::new(&shader_source, Some(shader_name));
+        for &(name, value) in shader_defines {
+            shader_composer.add_shader_define(name, value.into());
+        }
+
+        let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some(&prefixed_label(self.label.as_deref(), &format!("{} shader", shader_name))),
+            source: wgpu::ShaderSource::Naga(std::borrow::Cow::Owned(
+                shader_composer
+                    .build()
+                    .map_err(|err| CountingSortingError::ShaderComposition(shader_name, err.to_string()))?,
+            )),
+        });
+
+        let pipeline_label = prefixed_label(self.label.as_deref(), &format!("{} pipeline {:?}", entry_point, shader_defines));
+        let pipeline = Arc::new(device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some(&pipeline_label),
+            layout: Some(&device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(&format!("{} layout", pipeline_label)),
+                bind_group_layouts,
+                push_constant_ranges: &[],
+            })),
+            module: &shader_module,
+            entry_point,
+        }));
+
+        error_scope.pop()?;
+
+        // Another module may have compiled the same pipeline meanwhile, the first one inserted is kept
+        Ok(self.pipelines.lock().unwrap().entry(key).or_insert(pipeline).clone())
+    }
+
+    // Number of distinct pipelines compiled so far
+    pub fn pipeline_count(&self) -> usize { self.pipelines.lock().unwrap().len() }
+}
+
+impl std::fmt::Debug for SortPipelineCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("SortPipelineCache")
+            .field("label", &self.label)
+            .field("pipeline_count", &self.pipeline_count())
+            .finish()
+    }
+}
+
+// Caches are compared by identity so that descriptors sharing a cache compare equal
+impl PartialEq for SortPipelineCache {
+    fn eq(&self, other: &Self) -> bool { std::ptr::eq(self, other) }
+}
+
+impl Eq for SortPipelineCache {}
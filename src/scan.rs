@@ -0,0 +1,188 @@
+use std::sync::Arc;
+
+use oxyde::wgpu;
+
+use crate::{fold_workgroup_count, timestamps::StageTimestamps, CountingSortingError, SortPipelineCache};
+
+// Maximum number of scan then propagate levels (a buffer of up to workgroup_size^4 elements)
+pub(crate) const MAX_SCAN_THEN_PROPAGATE_LEVELS: u32 = 4;
+
+//This function is used to compute the number of scan then propagate levels required to scan the count buffer for a given size and workgroup size
+pub(crate) fn scan_then_propagate_level_count(size: u32, workgroup_size: u32) -> u32 {
+    let mut count = 1;
+    let mut temp_size = size / workgroup_size;
+    while temp_size > 0 {
+        count += 1;
+        temp_size /= workgroup_size;
+    }
+    count
+}
+
+fn workgroup_size_per_level(size: u32, workgroup_size: u32, level: u32) -> Vec<u32> {
+    std::iter::successors(
+        Some(size),
+        |&x| Some(x.div_ceil(workgroup_size)))
+    .take((level+1) as usize)
+    .skip(1)
+    .collect()
+}
+
+// Pipelines of the in place inclusive scan (prefix sum) of a u32 buffer using the "scan then propagate" strategy
+// There is a scan pipeline per level and a propagate pipeline per level except the last one
+pub(crate) struct ScanPipelines {
+    workgroup_scan_pipelines: Vec<Arc<wgpu::ComputePipeline>>,
+    workgroup_propagate_pipelines: Vec<Arc<wgpu::ComputePipeline>>,
+}
+
+impl ScanPipelines {
+    pub(crate) fn new(
+        device: &wgpu::Device,
+        pipeline_cache: &SortPipelineCache,
+        size: u32,
+        workgroup_size: u32,
+    ) -> Result<Self, CountingSortingError> {
+        let scan_then_propagate_level_count = scan_then_propagate_level_count(size, workgroup_size);
+
+        if scan_then_propagate_level_count > MAX_SCAN_THEN_PROPAGATE_LEVELS {
+            return Err(CountingSortingError::ToManyScanThenPropagateLevels(size, workgroup_size, scan_then_propagate_level_count));
+        }
+
+        let mut workgroup_scan_pipelines = Vec::with_capacity(scan_then_propagate_level_count as usize);
+        let mut workgroup_propagate_pipelines = Vec::with_capacity((scan_then_propagate_level_count-1) as usize);
+
+        for scan_then_propagate_level in 0..scan_then_propagate_level_count {
+            // Unable to use push_constant as it's not available in wgpu yet so we have to use a shader define for the scan level and recompile the shader for each level
+            // Otherwise we could have used a uniform buffer to pass the scan level but this force use to submit the queue for each scan level
+            let shader_defines = [("WORKGROUP_SIZE", workgroup_size), ("SCAN_LEVEL", scan_then_propagate_level)];
+            let bind_group_layouts = [&pipeline_cache.storage_buffer_bind_group_layout().layout];
+
+            workgroup_scan_pipelines.push(pipeline_cache.get_or_create(
+                device,
+                "scan",
+                include_str!("../shaders/scan.wgsl"),
+                "workgroup_scan",
+                &shader_defines,
+                &bind_group_layouts,
+            )?);
+
+            if scan_then_propagate_level < scan_then_propagate_level_count - 1 {
+                workgroup_propagate_pipelines.push(pipeline_cache.get_or_create(
+                    device,
+                    "scan",
+                    include_str!("../shaders/scan.wgsl"),
+                    "workgroup_propagate",
+                    &shader_defines,
+                    &bind_group_layouts,
+                )?);
+            }
+        }
+
+        Ok(Self {
+            workgroup_scan_pipelines,
+            workgroup_propagate_pipelines,
+        })
+    }
+
+    pub(crate) fn level_count(&self) -> u32 { self.workgroup_scan_pipelines.len() as u32 }
+
+    // (name, pipeline, workgroup count) of each scan level then of each propagate level, in dispatch order
+    fn stages(&self, size: u32, workgroup_size: u32) -> Vec<(String, &Arc<wgpu::ComputePipeline>, u32)> {
+        let scan_workgroup_sizes = workgroup_size_per_level(size, workgroup_size, self.workgroup_scan_pipelines.len() as u32);
+
+        let scan_stages = self
+            .workgroup_scan_pipelines
+            .iter()
+            .zip(scan_workgroup_sizes.iter())
+            .enumerate()
+            .map(|(level, (pipeline, &workgroup_size_x))| (format!("scan level {}", level), pipeline, workgroup_size_x));
+
+        let propagate_stages = self
+            .workgroup_propagate_pipelines
+            .iter()
+            .zip(scan_workgroup_sizes.iter())
+            .enumerate()
+            .rev()
+            .map(|(level, (pipeline, &workgroup_size_x))| (format!("propagate level {}", level), pipeline, workgroup_size_x));
+
+        scan_stages.chain(propagate_stages).collect()
+    }
+
+    // Names of the stages of `dispatch` (e.g. "scan level 0", "propagate level 0")
+    pub(crate) fn stage_names(&self, size: u32, workgroup_size: u32) -> Vec<String> {
+        self.stages(size, workgroup_size).into_iter().map(|(name, _, _)| name).collect()
+    }
+
+    // Scan the buffer bound (as a single read write storage buffer) by bind_group in its own compute pass
+    // With level passes, each level runs in its own compute pass instead (see `LevelPasses`)
+    pub(crate) fn dispatch(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        bind_group: &wgpu::BindGroup,
+        size: u32,
+        workgroup_size: u32,
+        max_workgroups_per_dimension: u32,
+        level_passes: Option<LevelPasses>,
+    ) {
+        let stages = self.stages(size, workgroup_size);
+
+        match level_passes {
+            Some(level_passes) => {
+                for (stage_index, (name, pipeline, workgroup_size_x)) in stages.into_iter().enumerate() {
+                    {
+                        let scan_pass = &mut encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                            label: Some("Scan Pass"),
+                            timestamp_writes: level_passes
+                                .timestamps
+                                .map(|(timestamps, first_stage)| timestamps.pass_writes(first_stage + stage_index as u32)),
+                        });
+
+                        scan_pass.set_bind_group(0, bind_group, &[]);
+                        dispatch_stage(scan_pass, &name, pipeline, workgroup_size_x, max_workgroups_per_dimension);
+                    }
+
+                    if let Some(after_pass) = level_passes.after_pass {
+                        after_pass(encoder, stage_index);
+                    }
+                }
+            }
+            None => {
+                let scan_pass = &mut encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                    label: Some("Scan Pass"),
+                    timestamp_writes: None,
+                });
+
+                scan_pass.set_bind_group(0, bind_group, &[]);
+                for (name, pipeline, workgroup_size_x) in stages {
+                    dispatch_stage(scan_pass, &name, pipeline, workgroup_size_x, max_workgroups_per_dimension);
+                }
+            }
+        }
+    }
+}
+
+// Recorded after the pass of a scan level with its index
+pub(crate) type AfterLevelPass<'a> = dyn Fn(&mut wgpu::CommandEncoder, usize) + 'a;
+
+// Options of a scan with a compute pass per level (in the order of `ScanPipelines::stage_names`)
+#[derive(Clone, Copy, Default)]
+pub(crate) struct LevelPasses<'a> {
+    // Timestamps of the level passes, written as the stages first_stage, first_stage + 1, ...
+    pub(crate) timestamps: Option<(&'a StageTimestamps, u32)>,
+    // e.g. to snapshot the scanned buffer after each level
+    pub(crate) after_pass: Option<&'a AfterLevelPass<'a>>,
+}
+
+fn dispatch_stage<'a>(
+    scan_pass: &mut wgpu::ComputePass<'a>,
+    name: &str,
+    pipeline: &'a wgpu::ComputePipeline,
+    workgroup_size_x: u32,
+    max_workgroups_per_dimension: u32,
+) {
+    scan_pass.push_debug_group(format!("{} ({} workgroups)", name, workgroup_size_x).as_str());
+    log::trace!("[GpuCountingSortModule] Dispatching {} ({} workgroups)", name, workgroup_size_x);
+    scan_pass.set_pipeline(pipeline);
+    let [x, y, z] = fold_workgroup_count(workgroup_size_x, max_workgroups_per_dimension);
+    scan_pass.dispatch_workgroups(x, y, z);
+    scan_pass.pop_debug_group();
+}
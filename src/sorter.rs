@@ -0,0 +1,105 @@
+use oxyde::wgpu;
+
+use crate::{GpuBitonicSortModule, GpuChunkedCountingSortModule, GpuCountingSortModule, GpuMergeSortModule, GpuOnesweepRadixSortModule};
+
+// What a sorting algorithm expects from its keys, to pick one at runtime
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SorterRequirements {
+    // Keys must be in [0, key_range) (the bucket count of counting sorts), None when any u32 key is supported
+    pub key_range: Option<u32>,
+    // Whether the ids of equal keys keep their order
+    pub stable: bool,
+}
+
+impl SorterRequirements {
+    pub fn supports_key_range(&self, key_range: u32) -> bool { self.key_range.is_none_or(|supported_key_range| key_range <= supported_key_range) }
+}
+
+// Sorting algorithm writing the ids of a values buffer in sorted order
+// Lets call sites hold a `Box<dyn GpuSorter>` whatever the algorithm picked for the key range and value count
+pub trait GpuSorter {
+    fn dispatch(&self, encoder: &mut wgpu::CommandEncoder);
+
+    // Buffer of the sorted ids, None when they are written into a binding provided by the caller
+    fn output_ids(&self) -> Option<&wgpu::Buffer>;
+
+    // Number of values sorted by each dispatch
+    fn capacity(&self) -> u32;
+
+    fn requirements(&self) -> SorterRequirements;
+}
+
+impl GpuSorter for GpuCountingSortModule {
+    fn dispatch(&self, encoder: &mut wgpu::CommandEncoder) { self.dispatch_work(encoder) }
+
+    fn output_ids(&self) -> Option<&wgpu::Buffer> { self.sorting_id_buffer() }
+
+    fn capacity(&self) -> u32 { self.value_size() }
+
+    fn requirements(&self) -> SorterRequirements {
+        SorterRequirements {
+            key_range: Some(self.count_size()),
+            stable: false,
+        }
+    }
+}
+
+impl GpuSorter for GpuChunkedCountingSortModule {
+    fn dispatch(&self, encoder: &mut wgpu::CommandEncoder) { self.dispatch_work(encoder) }
+
+    fn output_ids(&self) -> Option<&wgpu::Buffer> { Some(self.sorting_id_buffer()) }
+
+    fn capacity(&self) -> u32 { self.value_size() }
+
+    fn requirements(&self) -> SorterRequirements {
+        SorterRequirements {
+            key_range: Some(self.count_size()),
+            stable: false,
+        }
+    }
+}
+
+impl GpuSorter for GpuBitonicSortModule {
+    fn dispatch(&self, encoder: &mut wgpu::CommandEncoder) { self.dispatch_work(encoder) }
+
+    fn output_ids(&self) -> Option<&wgpu::Buffer> { Some(self.sorting_id_buffer()) }
+
+    fn capacity(&self) -> u32 { self.value_size() }
+
+    fn requirements(&self) -> SorterRequirements {
+        SorterRequirements {
+            key_range: None,
+            stable: true,
+        }
+    }
+}
+
+impl GpuSorter for GpuMergeSortModule {
+    fn dispatch(&self, encoder: &mut wgpu::CommandEncoder) { self.dispatch_work(encoder) }
+
+    fn output_ids(&self) -> Option<&wgpu::Buffer> { Some(self.sorting_id_buffer()) }
+
+    fn capacity(&self) -> u32 { self.element_count() }
+
+    fn requirements(&self) -> SorterRequirements {
+        SorterRequirements {
+            key_range: None,
+            stable: true,
+        }
+    }
+}
+
+impl GpuSorter for GpuOnesweepRadixSortModule {
+    fn dispatch(&self, encoder: &mut wgpu::CommandEncoder) { self.dispatch_work(encoder) }
+
+    fn output_ids(&self) -> Option<&wgpu::Buffer> { Some(self.sorting_id_buffer()) }
+
+    fn capacity(&self) -> u32 { self.value_size() }
+
+    fn requirements(&self) -> SorterRequirements {
+        SorterRequirements {
+            key_range: 1u32.checked_shl(self.key_bits()),
+            stable: true,
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use oxyde::{
+    wgpu::{self, util::DeviceExt},
+    wgpu_utils::{binding_builder, buffers, ShaderComposer},
+};
+
+use crate::{fold_workgroup_count, with_common_wgsl, CountingSortDescriptor, CountingSortingError, ErrorScope, GpuCountingSortModule, WorkgroupSize};
+
+// WGSL helpers to compute cell keys and iterate over the 27 neighbour cells of a position
+// Prepend it to a shader source that binds a `SpatialHashGridParams` uniform (see `GpuSpatialHashGrid::params_buffer`)
+pub const SPATIAL_HASH_GRID_WGSL: &str = include_str!("../shaders/spatial_hash_grid_common.wgsl");
+
+// Same layout as the `SpatialHashGridParams` struct of the WGSL helpers
+#[derive(Clone, Copy, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+pub struct SpatialHashGridParams {
+    pub origin: [f32; 3],
+    pub cell_size: f32,
+    pub cell_count: [u32; 3],
+    pub hash_table_size: u32,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum SpatialHashGridLayout {
+    // Dense grid of cell_count cells starting at origin, positions outside of it are clamped into the border cells
+    Bounded { origin: [f32; 3], cell_count: [u32; 3] },
+    // Unbounded grid where cells are hashed into table_size buckets
+    Hashed { table_size: u32 },
+}
+
+// Spatial hash grid for fixed-radius neighbour search built on top of the counting sort
+// 1. Compute the cell key of each position (vec4<f32> positions, w is ignored)
+// 2. Sort the particles by cell key
+// 3. Expose the [start, end) range of each cell in the sorted ids so that neighbour cells can be looked up (see `SPATIAL_HASH_GRID_WGSL`)
+pub struct GpuSpatialHashGrid {
+    workgroup_size: u32,
+    max_workgroups_per_dimension: u32,
+    particle_count: u32,
+    params: SpatialHashGridParams,
+
+    params_buffer: wgpu::Buffer,
+    cell_key_buffer: wgpu::Buffer,
+
+    cell_key_bind_group: wgpu::BindGroup,
+    params_bind_group: wgpu::BindGroup,
+    cell_key_pipeline: wgpu::ComputePipeline,
+
+    counting_sort_module: GpuCountingSortModule,
+}
+
+impl GpuSpatialHashGrid {
+    pub fn new(
+        device: &wgpu::Device,
+        positions_buffer: &wgpu::Buffer,
+        cell_size: f32,
+        layout: SpatialHashGridLayout,
+        workgroup_size: impl Into<WorkgroupSize>,
+    ) -> Result<Self, CountingSortingError> {
+        if !positions_buffer.usage().contains(wgpu::BufferUsages::STORAGE) {
+            return Err(CountingSortingError::MissingBufferUsage(wgpu::BufferUsages::STORAGE, "Positions buffer"));
+        }
+
+        let workgroup_size = workgroup_size.into().resolve(&device.limits())?;
+
+        let params = match layout {
+            SpatialHashGridLayout::Bounded { origin, cell_count } => SpatialHashGridParams {
+                origin,
+                cell_size,
+                cell_count,
+                hash_table_size: 0,
+            },
+            SpatialHashGridLayout::Hashed { table_size } => SpatialHashGridParams {
+                origin: [0.0; 3],
+                cell_size,
+                cell_count: [0; 3],
+                hash_table_size: table_size,
+            },
+        };
+
+        let cell_count = match layout {
+            SpatialHashGridLayout::Bounded { cell_count, .. } => cell_count.iter().try_fold(1u32, |acc, &count| acc.checked_mul(count)),
+            SpatialHashGridLayout::Hashed { table_size } => Some(table_size),
+        };
+        let cell_count = match cell_count {
+            Some(cell_count) if cell_count > 0 => cell_count,
+            _ => return Err(CountingSortingError::InvalidGridCellCount(layout)),
+        };
+
+        let size_of_u32 = std::mem::size_of::<u32>() as wgpu::BufferAddress;
+        let particle_count = (positions_buffer.size() / std::mem::size_of::<[f32; 4]>() as wgpu::BufferAddress) as u32;
+
+        let error_scope = ErrorScope::push(device);
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("spatial hash grid params buffer"),
+            contents: bytemuck::bytes_of(&params),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let cell_key_buffer = buffers::create_buffer_for_size(
+            device,
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            Some("spatial hash grid cell key buffer"),
+            particle_count as wgpu::BufferAddress * size_of_u32,
+        );
+
+        let counting_sort_module = CountingSortDescriptor::default()
+            .with_label("spatial hash grid")
+            .with_workgroup_size(workgroup_size)
+            .with_bucket_ranges(true)
+            .build_with_count_size(device, &cell_key_buffer, cell_count)?;
+
+        let read_write_bind_group_layout_with_desc = binding_builder::BindGroupLayoutBuilder::new()
+            .add_binding_compute(wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            })
+            .add_binding_compute(wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            })
+            .create(device, None);
+
+        let uniform_bind_group_layout_with_desc = binding_builder::BindGroupLayoutBuilder::new()
+            .add_binding_compute(wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            })
+            .create(device, None);
+
+        let cell_key_bind_group = binding_builder::BindGroupBuilder::new(&read_write_bind_group_layout_with_desc)
+            .resource(positions_buffer.as_entire_binding())
+            .resource(cell_key_buffer.as_entire_binding())
+            .create(device, Some("cell_key_bind_group"));
+
+        let params_bind_group = binding_builder::BindGroupBuilder::new(&uniform_bind_group_layout_with_desc)
+            .resource(params_buffer.as_entire_binding())
+            .create(device, Some("spatial_hash_grid_params_bind_group"));
+
+        let cell_key_shader_source = with_common_wgsl(&format!("{}\n{}", SPATIAL_HASH_GRID_WGSL, include_str!("../shaders/spatial_hash_grid.wgsl")));
+        let cell_key_shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("cell key shader"),
+            source: wgpu::ShaderSource::Naga(std::borrow::Cow::Owned(
+                ShaderComposer::new(&cell_key_shader_source, Some("spatial_hash_grid"))
+                    .with_shader_define("WORKGROUP_SIZE", workgroup_size.into())
+                    .build()
+                    .map_err(|err| CountingSortingError::ShaderComposition("spatial_hash_grid", err.to_string()))?,
+            )),
+        });
+
+        let cell_key_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("cell key pipeline"),
+            layout: Some(&device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("cell key pipeline layout"),
+                bind_group_layouts: &[&read_write_bind_group_layout_with_desc.layout, &uniform_bind_group_layout_with_desc.layout],
+                push_constant_ranges: &[],
+            })),
+            module: &cell_key_shader_module,
+            entry_point: "compute_cell_keys",
+        });
+
+        error_scope.pop()?;
+
+        Ok(Self {
+            workgroup_size,
+            max_workgroups_per_dimension: device.limits().max_compute_workgroups_per_dimension,
+            particle_count,
+            params,
+
+            params_buffer,
+            cell_key_buffer,
+
+            cell_key_bind_group,
+            params_bind_group,
+            cell_key_pipeline,
+
+            counting_sort_module,
+        })
+    }
+
+    pub fn dispatch_work(&self, encoder: &mut wgpu::CommandEncoder) {
+        encoder.push_debug_group("Spatial Hash Grid");
+
+        {
+            let cell_key_pass = &mut encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Cell Key Pass"),
+                timestamp_writes: None,
+            });
+
+            cell_key_pass.set_pipeline(&self.cell_key_pipeline);
+            cell_key_pass.set_bind_group(0, &self.cell_key_bind_group, &[]);
+            cell_key_pass.set_bind_group(1, &self.params_bind_group, &[]);
+            let [x, y, z] = fold_workgroup_count(self.particle_count.div_ceil(self.workgroup_size), self.max_workgroups_per_dimension);
+            cell_key_pass.dispatch_workgroups(x, y, z);
+        }
+
+        self.counting_sort_module.dispatch_work(encoder);
+        encoder.pop_debug_group();
+    }
+
+    // Change the cell size (and the origin for a bounded grid) without rebuilding the grid, the cell count can't change
+    pub fn set_cell_size(&mut self, queue: &wgpu::Queue, cell_size: f32) {
+        self.params.cell_size = cell_size;
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&self.params));
+    }
+
+    pub fn set_origin(&mut self, queue: &wgpu::Queue, origin: [f32; 3]) {
+        self.params.origin = origin;
+        queue.write_buffer(&self.params_buffer, 0, bytemuck::bytes_of(&self.params));
+    }
+
+    pub fn params(&self) -> &SpatialHashGridParams { &self.params }
+
+    // Uniform buffer of `SpatialHashGridParams` to bind in shaders using `SPATIAL_HASH_GRID_WGSL`
+    pub fn params_buffer(&self) -> &wgpu::Buffer { &self.params_buffer }
+
+    pub fn cell_key_buffer(&self) -> &wgpu::Buffer { &self.cell_key_buffer }
+
+    // Particle ids sorted by cell key
+    pub fn sorting_id_buffer(&self) -> &wgpu::Buffer { self.counting_sort_module.sorting_id_buffer().unwrap() }
+
+    // Buffer of `vec2<u32>` holding the [start, end) range of each cell in the sorting id buffer
+    pub fn cell_range_buffer(&self) -> &wgpu::Buffer { self.counting_sort_module.bucket_range_buffer().unwrap() }
+
+    pub fn particle_count(&self) -> u32 { self.particle_count }
+
+    pub fn cell_count(&self) -> u32 { self.counting_sort_module.count_size() }
+}
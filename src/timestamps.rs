@@ -0,0 +1,91 @@
+use std::future::Future;
+
+use oxyde::wgpu;
+
+use crate::{host, CountingSortingError};
+
+// GPU duration of a stage (compute pass) of a sort, see `GpuCountingSortModule::read_stage_durations`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StageDuration {
+    // e.g. "count", "scan level 1", "propagate level 0" or "scatter"
+    pub stage: String,
+    pub duration_ns: u64,
+}
+
+// Begin and end timestamps of each stage, written by the compute passes and resolved at the end of `dispatch_work`
+pub(crate) struct StageTimestamps {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    stage_capacity: u32,
+}
+
+impl StageTimestamps {
+    pub(crate) fn new(device: &wgpu::Device, label: &str, stage_capacity: u32) -> Result<Self, CountingSortingError> {
+        if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+            return Err(CountingSortingError::MissingDeviceFeature(wgpu::Features::TIMESTAMP_QUERY));
+        }
+
+        let query_set = device.create_query_set(&wgpu::QuerySetDescriptor {
+            label: Some(&format!("{} query set", label)),
+            ty: wgpu::QueryType::Timestamp,
+            count: 2 * stage_capacity,
+        });
+
+        let resolve_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(&format!("{} resolve buffer", label)),
+            size: 2 * stage_capacity as wgpu::BufferAddress * wgpu::QUERY_SIZE as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+
+        Ok(Self {
+            query_set,
+            resolve_buffer,
+            stage_capacity,
+        })
+    }
+
+    // Timestamp writes of the compute pass of the given stage (in dispatch order)
+    pub(crate) fn pass_writes(&self, stage: u32) -> wgpu::ComputePassTimestampWrites<'_> {
+        debug_assert!(stage < self.stage_capacity);
+        wgpu::ComputePassTimestampWrites {
+            query_set: &self.query_set,
+            beginning_of_pass_write_index: Some(2 * stage),
+            end_of_pass_write_index: Some(2 * stage + 1),
+        }
+    }
+
+    pub(crate) fn resolve(&self, encoder: &mut wgpu::CommandEncoder, stage_count: u32) {
+        encoder.resolve_query_set(&self.query_set, 0..2 * stage_count, &self.resolve_buffer, 0);
+    }
+
+    // Durations of the stages, named in dispatch order
+    pub(crate) fn read_durations<'a>(
+        &self,
+        device: &'a wgpu::Device,
+        queue: &wgpu::Queue,
+        stages: Vec<String>,
+    ) -> impl Future<Output = Result<Vec<StageDuration>, CountingSortingError>> + 'a {
+        let timestamp_period = queue.get_timestamp_period() as f64;
+        let read = host::read_buffer::<u64>(
+            device,
+            queue,
+            &self.resolve_buffer,
+            0,
+            2 * stages.len() as wgpu::BufferAddress * wgpu::QUERY_SIZE as wgpu::BufferAddress,
+        );
+
+        async move {
+            let timestamps = read.await?;
+            Ok(stages
+                .into_iter()
+                .zip(timestamps.chunks_exact(2))
+                .map(|(stage, timestamps)| StageDuration {
+                    stage,
+                    // Some drivers don't guarantee monotonic timestamps across passes
+                    duration_ns: (timestamps[1].saturating_sub(timestamps[0]) as f64 * timestamp_period) as u64,
+                })
+                .collect())
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use std::{future::Future, sync::Arc};
+
+use oxyde::{
+    wgpu,
+    wgpu_utils::{binding_builder, buffers},
+};
+
+use crate::{
+    create_clearing_pipeline, fold_workgroup_count, host, validate_storage_binding, CountingSortDescriptor, CountingSortingError, ErrorScope,
+    WorkgroupSize,
+};
+
+// Value of `first_violation` in the result buffer when the sort is valid
+const NO_VIOLATION: u32 = u32::MAX;
+
+// Outcome of a `GpuSortVerifyModule` dispatch
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SortVerification {
+    // The sorting ids are a permutation of [0, value_size) and the keys are non-decreasing along them
+    pub ok: bool,
+    // Smallest index in the sorting id buffer of an out of range or duplicated id (any of its occurrences), or of a key smaller than the previous one
+    pub first_violation: Option<u32>,
+    // Number of extra occurrences of the ids found more than once (as many ids are then missing)
+    pub duplicate_count: u32,
+}
+
+// Check a sort on the GPU without reading the sorting ids back, e.g. every frame of a soak test
+// The result buffer holds [ok, first_violation (u32::MAX when there is none), duplicate_count] as u32, see `read_result`
+// Works with the output of any sorter, as long as its sorting ids index the keys of values_buffer
+pub struct GpuSortVerifyModule {
+    workgroup_size: u32,
+    max_workgroups_per_dimension: u32,
+    value_size: u32,
+
+    result_buffer: wgpu::Buffer,
+    // Kept alive for the bind groups
+    _seen_buffer: wgpu::Buffer,
+
+    bind_group: wgpu::BindGroup,
+    seen_bind_group: wgpu::BindGroup,
+
+    clearing_pipeline: Arc<wgpu::ComputePipeline>,
+    init_result_pipeline: Arc<wgpu::ComputePipeline>,
+    count_ids_pipeline: Arc<wgpu::ComputePipeline>,
+    verify_pipeline: Arc<wgpu::ComputePipeline>,
+    finalize_result_pipeline: Arc<wgpu::ComputePipeline>,
+}
+
+impl GpuSortVerifyModule {
+    // The sorting id buffer needs at least as many ids as there are keys in values_buffer, only the first ones are checked
+    pub fn new(
+        device: &wgpu::Device,
+        values_buffer: &wgpu::Buffer,
+        sorting_id_buffer: &wgpu::Buffer,
+        workgroup_size: impl Into<WorkgroupSize>,
+    ) -> Result<Self, CountingSortingError> {
+        Self::from_descriptor(device, &CountingSortDescriptor::default().with_workgroup_size(workgroup_size), values_buffer, sorting_id_buffer)
+    }
+
+    // Uses the label, workgroup size, output usages (of the result buffer) and pipeline cache of the descriptor,
+    // the other options are rejected with `UnsupportedOption`
+    pub fn from_descriptor(
+        device: &wgpu::Device,
+        descriptor: &CountingSortDescriptor,
+        values_buffer: &wgpu::Buffer,
+        sorting_id_buffer: &wgpu::Buffer,
+    ) -> Result<Self, CountingSortingError> {
+        let limits = device.limits();
+        let workgroup_size = descriptor.validate(&limits)?;
+        descriptor.validate_base_options("sort verification")?;
+        let size_of_u32 = std::mem::size_of::<u32>() as wgpu::BufferAddress;
+
+        let values_size = validate_storage_binding(&limits, &values_buffer.as_entire_buffer_binding(), "Values buffer")?;
+        let sorting_ids_size = validate_storage_binding(&limits, &sorting_id_buffer.as_entire_buffer_binding(), "Sorting id buffer")?;
+        if sorting_ids_size < values_size {
+            return Err(CountingSortingError::BufferTooSmall(sorting_ids_size, values_size, "Sorting id buffer"));
+        }
+        let value_size = (values_size / size_of_u32) as u32;
+
+        let error_scope = ErrorScope::push(device);
+
+        let result_buffer = buffers::create_buffer_for_size(
+            device,
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | descriptor.output_usages,
+            Some(&descriptor.label("verify result buffer")),
+            3 * size_of_u32,
+        );
+
+        let seen_buffer =
+            buffers::create_buffer_for_size(device, wgpu::BufferUsages::STORAGE, Some(&descriptor.label("verify seen buffer")), values_size);
+
+        // init bind groups
+        let pipeline_cache = descriptor.pipeline_cache_or_private(device, Some("verify"));
+
+        let storage_binding_type = |read_only| wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        };
+
+        let bind_group_layout_with_desc = pipeline_cache.get_or_create_bind_group_layout(device, "verify", || {
+            binding_builder::BindGroupLayoutBuilder::new()
+                .add_binding_compute(storage_binding_type(true))
+                .add_binding_compute(storage_binding_type(true))
+                .add_binding_compute(storage_binding_type(false))
+                .add_binding_compute(storage_binding_type(false))
+        });
+
+        // Only the first value_size ids are bound
+        let bind_group = binding_builder::BindGroupBuilder::new(&bind_group_layout_with_desc)
+            .resource(values_buffer.as_entire_binding())
+            .resource(wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                buffer: sorting_id_buffer,
+                offset: 0,
+                size: wgpu::BufferSize::new(values_size),
+            }))
+            .resource(seen_buffer.as_entire_binding())
+            .resource(result_buffer.as_entire_binding())
+            .create(device, Some(&descriptor.label("verify bind_group")));
+
+        let seen_bind_group = binding_builder::BindGroupBuilder::new(pipeline_cache.storage_buffer_bind_group_layout())
+            .resource(seen_buffer.as_entire_binding())
+            .create(device, Some(&descriptor.label("verify seen_bind_group")));
+
+        // Pipelines
+        let clearing_pipeline = create_clearing_pipeline(device, &pipeline_cache, workgroup_size)?;
+        let create_pipeline = |entry_point| {
+            pipeline_cache.get_or_create(
+                device,
+                "verify",
+                include_str!("../shaders/verify.wgsl"),
+                entry_point,
+                &[("WORKGROUP_SIZE", workgroup_size)],
+                &[&bind_group_layout_with_desc.layout],
+            )
+        };
+
+        let init_result_pipeline = create_pipeline("init_result")?;
+        let count_ids_pipeline = create_pipeline("count_ids")?;
+        let verify_pipeline = create_pipeline("verify")?;
+        let finalize_result_pipeline = create_pipeline("finalize_result")?;
+
+        error_scope.pop()?;
+
+        Ok(Self {
+            workgroup_size,
+            max_workgroups_per_dimension: limits.max_compute_workgroups_per_dimension,
+            value_size,
+
+            result_buffer,
+            _seen_buffer: seen_buffer,
+
+            bind_group,
+            seen_bind_group,
+
+            clearing_pipeline,
+            init_result_pipeline,
+            count_ids_pipeline,
+            verify_pipeline,
+            finalize_result_pipeline,
+        })
+    }
+
+    // To record after the sort (in the same encoder or a later one)
+    pub fn dispatch_work(&self, encoder: &mut wgpu::CommandEncoder) {
+        log::trace!("[GpuSortVerifyModule] Checking {} sorting ids", self.value_size);
+
+        let [x, y, z] = fold_workgroup_count(self.value_size.div_ceil(self.workgroup_size), self.max_workgroups_per_dimension);
+
+        let verify_pass = &mut encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Verify Pass"),
+            timestamp_writes: None,
+        });
+
+        verify_pass.set_pipeline(&self.clearing_pipeline);
+        verify_pass.set_bind_group(0, &self.seen_bind_group, &[]);
+        verify_pass.dispatch_workgroups(x, y, z);
+
+        verify_pass.set_bind_group(0, &self.bind_group, &[]);
+        verify_pass.set_pipeline(&self.init_result_pipeline);
+        verify_pass.dispatch_workgroups(1, 1, 1);
+
+        verify_pass.set_pipeline(&self.count_ids_pipeline);
+        verify_pass.dispatch_workgroups(x, y, z);
+
+        verify_pass.set_pipeline(&self.verify_pipeline);
+        verify_pass.dispatch_workgroups(x, y, z);
+
+        verify_pass.set_pipeline(&self.finalize_result_pipeline);
+        verify_pass.dispatch_workgroups(1, 1, 1);
+    }
+
+    // [ok, first_violation, duplicate_count] as u32 (STORAGE | COPY_SRC), to copy into an application readback buffer
+    pub fn result_buffer(&self) -> &wgpu::Buffer { &self.result_buffer }
+
+    // Result of the last submitted `dispatch_work`
+    pub fn read_result<'a>(
+        &self,
+        device: &'a wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> impl Future<Output = Result<SortVerification, CountingSortingError>> + 'a {
+        let read = host::read_buffer::<u32>(device, queue, &self.result_buffer, 0, self.result_buffer.size());
+        async move {
+            let result = read.await?;
+            Ok(SortVerification {
+                ok: result[0] == 1,
+                first_violation: Some(result[1]).filter(|&first_violation| first_violation != NO_VIOLATION),
+                duplicate_count: result[2],
+            })
+        }
+    }
+
+    pub fn workgroup_size(&self) -> u32 { self.workgroup_size }
+
+    pub fn value_size(&self) -> u32 { self.value_size }
+}
@@ -0,0 +1,90 @@
+use oxyde::wgpu;
+
+use crate::CountingSortingError;
+
+// Preferred workgroup size when picked automatically, capped by the device limits
+const PREFERRED_WORKGROUP_SIZE: u32 = 256;
+// Software rasterizers (llvmpipe, WARP, ...) run workgroups on CPU threads and prefer small ones
+const PREFERRED_CPU_WORKGROUP_SIZE: u32 = 64;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WorkgroupSize {
+    // Pick the biggest valid size (up to 256) from the device limits
+    Auto,
+    // Should be a power of two greater than 1 (required by the Kogge-Stone scan) within the device limits
+    Fixed(u32),
+}
+
+impl From<u32> for WorkgroupSize {
+    fn from(workgroup_size: u32) -> Self { WorkgroupSize::Fixed(workgroup_size) }
+}
+
+impl WorkgroupSize {
+    // Same as `Auto` but also takes the kind of adapter into account
+    pub fn for_adapter(adapter_info: &wgpu::AdapterInfo, limits: &wgpu::Limits) -> Self {
+        let preferred = match adapter_info.device_type {
+            wgpu::DeviceType::Cpu => PREFERRED_CPU_WORKGROUP_SIZE,
+            _ => PREFERRED_WORKGROUP_SIZE,
+        };
+        WorkgroupSize::Fixed(max_valid_workgroup_size(limits, preferred))
+    }
+
+    // Resolve `Auto` and check a fixed size against the device limits
+    pub fn resolve(self, limits: &wgpu::Limits) -> Result<u32, CountingSortingError> {
+        match self {
+            WorkgroupSize::Auto => WorkgroupSize::Fixed(max_valid_workgroup_size(limits, PREFERRED_WORKGROUP_SIZE)).resolve(limits),
+            WorkgroupSize::Fixed(workgroup_size) => {
+                validate_workgroup_size(limits, workgroup_size)?;
+                Ok(workgroup_size)
+            }
+        }
+    }
+}
+
+// Workgroup memory used by the scan (a u32 per invocation)
+fn scan_workgroup_storage_size(workgroup_size: u32) -> u32 { workgroup_size * std::mem::size_of::<u32>() as u32 }
+
+// Biggest power of two up to preferred within the device limits
+fn max_valid_workgroup_size(limits: &wgpu::Limits, preferred: u32) -> u32 {
+    let max_size = preferred
+        .min(limits.max_compute_invocations_per_workgroup)
+        .min(limits.max_compute_workgroup_size_x)
+        .min(limits.max_compute_workgroup_storage_size / std::mem::size_of::<u32>() as u32);
+    // Rounded down to a power of two (0 when the limits are unusable, which the validation rejects)
+    match max_size {
+        0 => 0,
+        max_size => 1 << max_size.ilog2(),
+    }
+}
+
+fn validate_workgroup_size(limits: &wgpu::Limits, workgroup_size: u32) -> Result<(), CountingSortingError> {
+    if workgroup_size < 2 || !workgroup_size.is_power_of_two() {
+        return Err(CountingSortingError::InvalidWorkgroupSize(workgroup_size));
+    }
+
+    if workgroup_size > limits.max_compute_invocations_per_workgroup {
+        return Err(CountingSortingError::WorkgroupSizeExceedsLimit(
+            workgroup_size,
+            limits.max_compute_invocations_per_workgroup,
+            "max_compute_invocations_per_workgroup",
+        ));
+    }
+
+    if workgroup_size > limits.max_compute_workgroup_size_x {
+        return Err(CountingSortingError::WorkgroupSizeExceedsLimit(
+            workgroup_size,
+            limits.max_compute_workgroup_size_x,
+            "max_compute_workgroup_size_x",
+        ));
+    }
+
+    if scan_workgroup_storage_size(workgroup_size) > limits.max_compute_workgroup_storage_size {
+        return Err(CountingSortingError::WorkgroupSizeExceedsLimit(
+            scan_workgroup_storage_size(workgroup_size),
+            limits.max_compute_workgroup_storage_size,
+            "max_compute_workgroup_storage_size",
+        ));
+    }
+
+    Ok(())
+}
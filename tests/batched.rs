@@ -0,0 +1,148 @@
+use oxyde::wgpu::{self, util::DeviceExt};
+
+use oxyde_sorting::{BatchedSortEntry, CountingSortDescriptor, CountingSortingError, GpuBatchedCountingSortModule};
+
+mod common;
+use common::{init_render_instance_and_device, read_buffer};
+
+#[test]
+fn check_batched_sorting() {
+    let (render_instance, device_handle_id) = init_render_instance_and_device();
+    let device_handle = &render_instance.devices[device_handle_id];
+    let device = &device_handle.device;
+    let queue = &device_handle.queue;
+
+    // Entries out of order, with gaps between them and an empty one
+    let entries = [
+        BatchedSortEntry::new(5000..12000, 700),
+        BatchedSortEntry::new(0..1000, 16),
+        BatchedSortEntry::new(1000..1000, 3),
+        BatchedSortEntry::new(1200..4999, 1),
+        BatchedSortEntry::new(12500..20000, 5000),
+    ];
+
+    let mut values = vec![u32::MAX; 20100];
+    for entry in entries.iter() {
+        for index in entry.values.clone() {
+            values[index as usize] = index.wrapping_mul(2654435761) % entry.key_range;
+        }
+    }
+
+    let values_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("values buffer"),
+        contents: bytemuck::cast_slice(&values),
+        usage: wgpu::BufferUsages::STORAGE,
+    });
+
+    let batched_module = GpuBatchedCountingSortModule::new(device, &values_buffer, &entries, 64).unwrap();
+    assert_eq!(batched_module.count_offsets(), [0, 700, 716, 719, 720]);
+    assert_eq!(batched_module.count_size(), 5720);
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("batched sort encoder") });
+    batched_module.dispatch_work(&mut encoder);
+    queue.submit(Some(encoder.finish()));
+
+    let sorting_ids = read_buffer(device, queue, batched_module.sorting_id_buffer());
+    for entry in entries.iter() {
+        let entry_ids = &sorting_ids[entry.values.start as usize..entry.values.end as usize];
+
+        let mut ids_as_set = entry_ids.to_vec();
+        ids_as_set.sort_unstable();
+        assert_eq!(ids_as_set, entry.values.clone().collect::<Vec<u32>>());
+
+        assert!(entry_ids.windows(2).all(|window| values[window[0] as usize] <= values[window[1] as usize]));
+    }
+
+    // Values out of every entry are left untouched
+    assert!(sorting_ids[1000..1200].iter().chain(&sorting_ids[20000..]).all(|&id| id == 0));
+}
+
+#[test]
+fn check_out_of_range_keys_are_sorted_as_last_key() {
+    let (render_instance, device_handle_id) = init_render_instance_and_device();
+    let device_handle = &render_instance.devices[device_handle_id];
+    let device = &device_handle.device;
+    let queue = &device_handle.queue;
+
+    // Half of the keys are out of the key range of the entry
+    let entries = [BatchedSortEntry::new(0..1000, 4)];
+    let values = (0..1000u32).map(|i| i.wrapping_mul(2654435761) % 8).collect::<Vec<u32>>();
+
+    let values_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("values buffer"),
+        contents: bytemuck::cast_slice(&values),
+        usage: wgpu::BufferUsages::STORAGE,
+    });
+
+    let batched_module = GpuBatchedCountingSortModule::new(device, &values_buffer, &entries, 64).unwrap();
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("batched sort encoder") });
+    batched_module.dispatch_work(&mut encoder);
+    queue.submit(Some(encoder.finish()));
+
+    let sorting_ids = read_buffer(device, queue, batched_module.sorting_id_buffer());
+    let sorted_keys = sorting_ids.iter().map(|&id| values[id as usize].min(3)).collect::<Vec<u32>>();
+    let mut expected_keys = values.iter().map(|&value| value.min(3)).collect::<Vec<u32>>();
+    expected_keys.sort_unstable();
+    assert_eq!(sorted_keys, expected_keys);
+}
+
+#[test]
+fn check_invalid_batch_entries_are_rejected() {
+    let (render_instance, device_handle_id) = init_render_instance_and_device();
+    let device = &render_instance.devices[device_handle_id].device;
+
+    let values_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("values buffer"),
+        size: 4000,
+        usage: wgpu::BufferUsages::STORAGE,
+        mapped_at_creation: false,
+    });
+
+    let check_rejected = |entries: &[BatchedSortEntry], expected_index: usize| {
+        assert!(matches!(
+            GpuBatchedCountingSortModule::new(device, &values_buffer, entries, 64),
+            Err(CountingSortingError::InvalidBatchEntry(index, _)) if index == expected_index
+        ));
+    };
+
+    check_rejected(&[BatchedSortEntry::new(0..10, 4), BatchedSortEntry::new(10..20, 0)], 1);
+    check_rejected(&[BatchedSortEntry::new(0..1001, 4)], 0);
+    check_rejected(&[BatchedSortEntry::new(500..600, 4), BatchedSortEntry::new(0..501, 4)], 0);
+
+    assert!(matches!(
+        GpuBatchedCountingSortModule::new(device, &values_buffer, &[], 64),
+        Err(CountingSortingError::EmptyInput("Batch entries"))
+    ));
+
+    // An empty entry holds no value, so it doesn't overlap the entry around it
+    let entries = [BatchedSortEntry::new(0..10, 4), BatchedSortEntry::new(5..5, 4)];
+    assert!(GpuBatchedCountingSortModule::new(device, &values_buffer, &entries, 64).is_ok());
+}
+
+#[test]
+fn check_unsupported_options_are_rejected() {
+    let (render_instance, device_handle_id) = init_render_instance_and_device();
+    let device = &render_instance.devices[device_handle_id].device;
+
+    let values_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("values buffer"),
+        size: 4000,
+        usage: wgpu::BufferUsages::STORAGE,
+        mapped_at_creation: false,
+    });
+    let entries = [BatchedSortEntry::new(0..1000, 16)];
+    let descriptor = CountingSortDescriptor::default().with_workgroup_size(64);
+
+    for (descriptor, option_name) in [
+        (descriptor.clone().with_items_per_invocation(4), "items_per_invocation"),
+        (descriptor.clone().with_bucket_ranges(true), "bucket_ranges"),
+        (descriptor.clone().with_timestamps(true), "timestamps"),
+        (descriptor.clone().with_debug_snapshots(true), "debug_snapshots"),
+    ] {
+        assert!(matches!(
+            GpuBatchedCountingSortModule::from_descriptor(device, &descriptor, &values_buffer, &entries),
+            Err(CountingSortingError::UnsupportedOption("batched counting sort", name)) if name == option_name
+        ));
+    }
+}
@@ -0,0 +1,111 @@
+use std::sync::Arc;
+
+use oxyde::wgpu::{self, util::DeviceExt};
+
+use oxyde_sorting::{CountingSortDescriptor, CountingSortingError, GpuBitonicSortModule, GpuSorter, SortPipelineCache, WorkgroupSize};
+
+mod common;
+use common::{init_render_instance_and_device, read_buffer};
+
+fn check_bitonic_sorting(value_size: u32, key_modulo: u32, workgroup_size: impl Into<WorkgroupSize>) {
+    let (render_instance, device_handle_id) = init_render_instance_and_device();
+    let device_handle = &render_instance.devices[device_handle_id];
+    let device = &device_handle.device;
+    let queue = &device_handle.queue;
+
+    // Keys over the whole u32 range (with duplicates when key_modulo is small)
+    let values = (0..value_size).map(|i| i.wrapping_mul(2654435761).wrapping_add(0x9e3779b9) % key_modulo).collect::<Vec<u32>>();
+
+    let values_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("values buffer"),
+        contents: bytemuck::cast_slice(&values),
+        usage: wgpu::BufferUsages::STORAGE,
+    });
+
+    let bitonic_module = GpuBitonicSortModule::new(device, &values_buffer, workgroup_size).unwrap();
+    assert!(bitonic_module.padded_size().is_power_of_two() && bitonic_module.padded_size() >= value_size);
+    assert!(bitonic_module.requirements().supports_key_range(u32::MAX));
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("bitonic sort encoder") });
+    bitonic_module.dispatch_work(&mut encoder);
+    queue.submit(Some(encoder.finish()));
+
+    // The sort is stable so the ids are the ones of a stable CPU sort
+    let sorting_ids = read_buffer(device, queue, bitonic_module.sorting_id_buffer());
+    let mut expected_sorting_ids = (0..value_size).collect::<Vec<u32>>();
+    expected_sorting_ids.sort_by_key(|&id| values[id as usize]);
+    assert_eq!(sorting_ids, expected_sorting_ids);
+}
+
+#[test]
+fn check_bitonic_sorting_single_block() { check_bitonic_sorting(100, u32::MAX, 64); }
+
+#[test]
+fn check_bitonic_sorting_multiple_blocks() { check_bitonic_sorting(5000, u32::MAX, 64); }
+
+#[test]
+fn check_bitonic_sorting_with_duplicates() { check_bitonic_sorting(3001, 17, 32); }
+
+#[test]
+fn check_bitonic_sorting_auto_workgroup_size() { check_bitonic_sorting(70000, u32::MAX, WorkgroupSize::Auto); }
+
+#[test]
+fn check_bitonic_modules_share_pipeline_cache() {
+    let (render_instance, device_handle_id) = init_render_instance_and_device();
+    let device_handle = &render_instance.devices[device_handle_id];
+    let device = &device_handle.device;
+    let queue = &device_handle.queue;
+
+    let pipeline_cache = Arc::new(SortPipelineCache::new(device, Some("shared")));
+    let descriptor = CountingSortDescriptor::default().with_label("bitonic test").with_workgroup_size(64).with_pipeline_cache(pipeline_cache.clone());
+
+    let values = [(0..300).rev().collect::<Vec<u32>>(), (0..1000).map(|i| i * 7 % 101).collect::<Vec<u32>>()];
+    let values_buffers = values.each_ref().map(|values| {
+        device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("values buffer"),
+            contents: bytemuck::cast_slice(values),
+            usage: wgpu::BufferUsages::STORAGE,
+        })
+    });
+
+    let first_module = GpuBitonicSortModule::from_descriptor(device, &descriptor, &values_buffers[0]).unwrap();
+    let pipeline_count = pipeline_cache.pipeline_count();
+    assert!(pipeline_count > 0);
+
+    // The second module binds its resources with the layouts of the cache so it reuses the pipelines of the first one
+    let second_module = GpuBitonicSortModule::from_descriptor(device, &descriptor, &values_buffers[1]).unwrap();
+    assert_eq!(pipeline_cache.pipeline_count(), pipeline_count);
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("bitonic sort encoder") });
+    first_module.dispatch_work(&mut encoder);
+    second_module.dispatch_work(&mut encoder);
+    queue.submit(Some(encoder.finish()));
+
+    for (values, module) in values.iter().zip([&first_module, &second_module]) {
+        let mut expected_sorting_ids = (0..values.len() as u32).collect::<Vec<u32>>();
+        expected_sorting_ids.sort_by_key(|&id| values[id as usize]);
+        assert_eq!(read_buffer(device, queue, module.sorting_id_buffer()), expected_sorting_ids);
+    }
+}
+
+#[test]
+fn check_unsupported_options_are_rejected() {
+    let (render_instance, device_handle_id) = init_render_instance_and_device();
+    let device = &render_instance.devices[device_handle_id].device;
+
+    let buffer_usage = wgpu::BufferUsages::STORAGE;
+    let values_buffer = device.create_buffer(&wgpu::BufferDescriptor { label: None, size: 4096, usage: buffer_usage, mapped_at_creation: false });
+    let descriptor = CountingSortDescriptor::default().with_workgroup_size(64);
+
+    for (descriptor, option_name) in [
+        (descriptor.clone().with_items_per_invocation(4), "items_per_invocation"),
+        (descriptor.clone().with_bucket_ranges(true), "bucket_ranges"),
+        (descriptor.clone().with_timestamps(true), "timestamps"),
+        (descriptor.clone().with_debug_snapshots(true), "debug_snapshots"),
+    ] {
+        assert!(matches!(
+            GpuBitonicSortModule::from_descriptor(device, &descriptor, &values_buffer),
+            Err(CountingSortingError::UnsupportedOption("bitonic sort", name)) if name == option_name
+        ));
+    }
+}
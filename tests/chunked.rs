@@ -0,0 +1,167 @@
+use oxyde::wgpu::{self, util::DeviceExt};
+
+use oxyde_sorting::{CountingSortDescriptor, CountingSortingError, GpuChunkedCountingSortModule, SortPipelineCache};
+
+mod common;
+use common::{init_render_instance_and_device, read_buffer};
+
+// Use small chunks to exercise the chunking without allocating buffers bigger than max_storage_buffer_binding_size
+fn check_chunked_sorting(value_size: u32, count_size: u32, chunk_alignment_multiple: u32) {
+    let (render_instance, device_handle_id) = init_render_instance_and_device();
+    let device_handle = &render_instance.devices[device_handle_id];
+    let device = &device_handle.device;
+    let queue = &device_handle.queue;
+
+    let values = (0..value_size).map(|i| i.wrapping_mul(2654435761) % count_size).collect::<Vec<u32>>();
+
+    let values_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("values buffer"),
+        contents: bytemuck::cast_slice(&values),
+        usage: wgpu::BufferUsages::STORAGE,
+    });
+
+    let count_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("count buffer"),
+        size: count_size as wgpu::BufferAddress * std::mem::size_of::<u32>() as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    let chunk_size = device.limits().min_storage_buffer_offset_alignment / std::mem::size_of::<u32>() as u32 * chunk_alignment_multiple;
+    let chunked_module = GpuChunkedCountingSortModule::new_with_chunk_size(device, &values_buffer, &count_buffer, 64, chunk_size).unwrap();
+    assert_eq!(chunked_module.chunk_count(), value_size.div_ceil(chunk_size));
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("chunked sort encoder") });
+    chunked_module.dispatch_work(&mut encoder);
+    queue.submit(Some(encoder.finish()));
+
+    let sorting_ids = read_buffer(device, queue, chunked_module.sorting_id_buffer());
+    let bucket_starts = read_buffer(device, queue, &count_buffer);
+
+    // After the sort the count buffer holds the start offset of each bucket
+    let expected_bucket_starts = values
+        .iter()
+        .fold(vec![0u32; count_size as usize + 1], |mut counts, &value| {
+            counts[value as usize + 1] += 1;
+            counts
+        })
+        .iter()
+        .scan(0, |acc, &count| {
+            *acc += count;
+            Some(*acc)
+        })
+        .take(count_size as usize)
+        .collect::<Vec<u32>>();
+    assert_eq!(bucket_starts, expected_bucket_starts);
+
+    // Sorting ids should be a permutation of the values ids that sorts the values
+    let mut seen = vec![false; value_size as usize];
+    for &id in sorting_ids.iter() {
+        assert!(!seen[id as usize], "id {} appears twice in the sorting ids", id);
+        seen[id as usize] = true;
+    }
+    for window in sorting_ids.windows(2) {
+        assert!(values[window[0] as usize] <= values[window[1] as usize]);
+    }
+}
+
+#[test]
+fn check_chunked_sorting_single_chunk() { check_chunked_sorting(1000, 100, 64); }
+
+#[test]
+fn check_chunked_sorting_multiple_chunks() { check_chunked_sorting(10000, 500, 16); }
+
+#[test]
+fn check_chunked_sorting_partial_last_chunk() { check_chunked_sorting(3001, 37, 4); }
+
+#[test]
+fn check_invalid_chunk_size_is_rejected() {
+    let (render_instance, device_handle_id) = init_render_instance_and_device();
+    let device = &render_instance.devices[device_handle_id].device;
+
+    let buffer_usage = wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST;
+    let values_buffer = device.create_buffer(&wgpu::BufferDescriptor { label: None, size: 4096, usage: buffer_usage, mapped_at_creation: false });
+    let count_buffer = device.create_buffer(&wgpu::BufferDescriptor { label: None, size: 256, usage: buffer_usage, mapped_at_creation: false });
+
+    let chunk_size = device.limits().min_storage_buffer_offset_alignment / std::mem::size_of::<u32>() as u32 + 1;
+    assert!(matches!(
+        GpuChunkedCountingSortModule::new_with_chunk_size(device, &values_buffer, &count_buffer, 64, chunk_size),
+        Err(CountingSortingError::InvalidChunkSize(..))
+    ));
+
+    // 65 chunks of the smallest chunk size
+    let alignment = device.limits().min_storage_buffer_offset_alignment;
+    let large_values_buffer =
+        device.create_buffer(&wgpu::BufferDescriptor { label: None, size: 65 * alignment as u64, usage: buffer_usage, mapped_at_creation: false });
+    let min_chunk_size = alignment / std::mem::size_of::<u32>() as u32;
+    assert!(matches!(
+        GpuChunkedCountingSortModule::new_with_chunk_size(device, &large_values_buffer, &count_buffer, 64, min_chunk_size),
+        Err(CountingSortingError::DeviceLimitExceeded(65, 64, "Chunked chunk count"))
+    ));
+}
+
+#[test]
+fn check_invalid_count_buffer_is_rejected() {
+    let (render_instance, device_handle_id) = init_render_instance_and_device();
+    let device = &render_instance.devices[device_handle_id].device;
+
+    let buffer_usage = wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST;
+    let values_buffer = device.create_buffer(&wgpu::BufferDescriptor { label: None, size: 4096, usage: buffer_usage, mapped_at_creation: false });
+    let descriptor = CountingSortDescriptor::default().with_label("invalid").with_workgroup_size(64);
+
+    let unaligned_count_buffer =
+        device.create_buffer(&wgpu::BufferDescriptor { label: None, size: 258, usage: buffer_usage, mapped_at_creation: false });
+    assert!(matches!(
+        GpuChunkedCountingSortModule::from_descriptor(device, &descriptor, &values_buffer, &unaligned_count_buffer, None),
+        Err(CountingSortingError::InvalidBufferRange(0, 258, 258, "Count buffer"))
+    ));
+
+    assert!(matches!(
+        GpuChunkedCountingSortModule::from_descriptor(device, &descriptor, &values_buffer, &values_buffer, None),
+        Err(CountingSortingError::OverlappingBindings("Values buffer", "Count buffer"))
+    ));
+}
+
+#[test]
+fn check_unsupported_options_are_rejected() {
+    let (render_instance, device_handle_id) = init_render_instance_and_device();
+    let device = &render_instance.devices[device_handle_id].device;
+
+    let buffer_usage = wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST;
+    let values_buffer = device.create_buffer(&wgpu::BufferDescriptor { label: None, size: 4096, usage: buffer_usage, mapped_at_creation: false });
+    let count_buffer = device.create_buffer(&wgpu::BufferDescriptor { label: None, size: 256, usage: buffer_usage, mapped_at_creation: false });
+    let descriptor = CountingSortDescriptor::default().with_workgroup_size(64);
+
+    for (descriptor, option_name) in [
+        (descriptor.clone().with_items_per_invocation(4), "items_per_invocation"),
+        (descriptor.clone().with_bucket_ranges(true), "bucket_ranges"),
+        (descriptor.clone().with_timestamps(true), "timestamps"),
+        (descriptor.clone().with_debug_snapshots(true), "debug_snapshots"),
+    ] {
+        assert!(matches!(
+            GpuChunkedCountingSortModule::from_descriptor(device, &descriptor, &values_buffer, &count_buffer, None),
+            Err(CountingSortingError::UnsupportedOption("chunked counting sort", name)) if name == option_name
+        ));
+    }
+}
+
+#[test]
+fn check_chunked_modules_share_pipeline_cache() {
+    let (render_instance, device_handle_id) = init_render_instance_and_device();
+    let device = &render_instance.devices[device_handle_id].device;
+
+    let buffer_usage = wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST;
+    let values_buffer = device.create_buffer(&wgpu::BufferDescriptor { label: None, size: 4096, usage: buffer_usage, mapped_at_creation: false });
+    let count_buffer = device.create_buffer(&wgpu::BufferDescriptor { label: None, size: 256, usage: buffer_usage, mapped_at_creation: false });
+
+    let pipeline_cache = std::sync::Arc::new(SortPipelineCache::new(device, Some("chunked")));
+    let descriptor = CountingSortDescriptor::default().with_workgroup_size(64).with_pipeline_cache(pipeline_cache.clone());
+    let chunk_size = device.limits().min_storage_buffer_offset_alignment / std::mem::size_of::<u32>() as u32;
+
+    let build = || GpuChunkedCountingSortModule::from_descriptor(device, &descriptor, &values_buffer, &count_buffer, Some(chunk_size)).unwrap();
+
+    let _chunked_module = build();
+    let pipeline_count = pipeline_cache.pipeline_count();
+    let _sibling_module = build();
+    assert_eq!(pipeline_cache.pipeline_count(), pipeline_count);
+}
@@ -0,0 +1,32 @@
+use oxyde::{wgpu, wgpu_utils};
+
+pub fn init_render_instance_and_device() -> (wgpu_utils::render_handles::RenderInstance, usize) {
+    let mut render_instance = wgpu_utils::render_handles::RenderInstance::new(None, None);
+
+    // Force HighPerformance adapter for choosing Dedicated GPU as it seems to not work (Device lost: Dropped - Device is dying.) on integrated intel GPU (Intel(R) Iris(R) Xe Graphics)
+    let device_handle_id = pollster::block_on(render_instance.device(None, Some(wgpu::PowerPreference::HighPerformance)))
+        .expect("Device creation failed");
+
+    let device = &render_instance.devices[device_handle_id].device;
+
+    device.on_uncaptured_error(Box::new(|err| panic!("{}", err)));
+
+    device.set_device_lost_callback(Box::new(|device_lost_reason, str| {
+        match device_lost_reason {
+            wgpu::DeviceLostReason::ReplacedCallback => {
+                log::debug!("Device replace lost callback: {}", str);
+            }
+            _ => {
+                panic!("Device lost: {:?} - {}", device_lost_reason, str);
+            }
+        }
+    }));
+
+    (render_instance, device_handle_id)
+}
+
+// Read a buffer of u32 back through the readback path of the library (blocking)
+#[allow(dead_code)]
+pub fn read_buffer(device: &wgpu::Device, queue: &wgpu::Queue, buffer: &wgpu::Buffer) -> Vec<u32> {
+    oxyde_sorting::block_on_readback(device, oxyde_sorting::read_buffer(device, queue, buffer, 0, buffer.size())).unwrap()
+}
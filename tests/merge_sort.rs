@@ -0,0 +1,134 @@
+use oxyde::{
+    wgpu::{self, util::DeviceExt},
+    wgpu_utils::binding_builder,
+};
+
+use oxyde_sorting::{CountingSortDescriptor, CountingSortingError, GpuMergeSortModule, MergeSortComparator};
+
+mod common;
+use common::{init_render_instance_and_device, read_buffer};
+
+// Lexicographic order on (x, y) with x values closer than EPSILON considered equal
+const LEXICOGRAPHIC_LESS_WGSL: &str = r#"
+@group(2) @binding(0) var<storage, read> records : array<vec2<f32>>;
+
+const EPSILON: f32 = 0.01;
+
+fn less(a: u32, b: u32) -> bool {
+    let record_a = records[a];
+    let record_b = records[b];
+    if (abs(record_a.x - record_b.x) > EPSILON) {
+        return record_a.x < record_b.x;
+    }
+    return record_a.y < record_b.y;
+}
+"#;
+
+fn lexicographic_less(a: [f32; 2], b: [f32; 2]) -> bool {
+    if (a[0] - b[0]).abs() > 0.01 {
+        return a[0] < b[0];
+    }
+    a[1] < b[1]
+}
+
+fn check_merge_sorting(element_count: u32) {
+    let (render_instance, device_handle_id) = init_render_instance_and_device();
+    let device_handle = &render_instance.devices[device_handle_id];
+    let device = &device_handle.device;
+    let queue = &device_handle.queue;
+
+    // Few distinct x values (with some jitter below epsilon) so the y values decide most comparisons
+    let records = (0..element_count)
+        .map(|i| {
+            let hash = i.wrapping_mul(2654435761);
+            [(hash % 7) as f32 + (hash % 3) as f32 * 0.001, (hash >> 16) as f32 % 50.0]
+        })
+        .collect::<Vec<[f32; 2]>>();
+
+    let records_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("records buffer"),
+        contents: bytemuck::cast_slice(&records),
+        usage: wgpu::BufferUsages::STORAGE,
+    });
+
+    let records_bind_group_layout_with_desc = binding_builder::BindGroupLayoutBuilder::new()
+        .add_binding_compute(wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only: true },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        })
+        .create(device, None);
+
+    let records_bind_group = binding_builder::BindGroupBuilder::new(&records_bind_group_layout_with_desc)
+        .resource(records_buffer.as_entire_binding())
+        .create(device, Some("records bind_group"));
+
+    let comparator = MergeSortComparator::new(LEXICOGRAPHIC_LESS_WGSL).with_resources(&records_bind_group_layout_with_desc.layout, records_bind_group);
+    let merge_sort_module = GpuMergeSortModule::new(device, element_count, comparator, 64).unwrap();
+    assert_eq!(merge_sort_module.merge_pass_count(), element_count.next_power_of_two().trailing_zeros());
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("merge sort encoder") });
+    merge_sort_module.dispatch_work(&mut encoder);
+    queue.submit(Some(encoder.finish()));
+
+    // The sort is stable so ids of equal records keep their order
+    let sorting_ids = read_buffer(device, queue, merge_sort_module.sorting_id_buffer());
+    let mut expected_sorting_ids = (0..element_count).collect::<Vec<u32>>();
+    expected_sorting_ids.sort_by(|&a, &b| {
+        let (record_a, record_b) = (records[a as usize], records[b as usize]);
+        lexicographic_less(record_b, record_a).cmp(&lexicographic_less(record_a, record_b))
+    });
+    assert_eq!(sorting_ids, expected_sorting_ids);
+}
+
+#[test]
+fn check_merge_sorting_single_element() { check_merge_sorting(1); }
+
+#[test]
+fn check_merge_sorting_power_of_two() { check_merge_sorting(4096); }
+
+#[test]
+fn check_merge_sorting_odd_size() { check_merge_sorting(3001); }
+
+#[test]
+fn check_invalid_comparator_is_rejected() {
+    let (render_instance, device_handle_id) = init_render_instance_and_device();
+    let device = &render_instance.devices[device_handle_id].device;
+
+    assert!(matches!(
+        GpuMergeSortModule::new(device, 100, MergeSortComparator::new("fn less(a: u32) -> bool { return true; }"), 64),
+        Err(CountingSortingError::ShaderComposition(..))
+    ));
+}
+
+#[test]
+fn check_unsupported_options_are_rejected() {
+    let (render_instance, device_handle_id) = init_render_instance_and_device();
+    let device = &render_instance.devices[device_handle_id].device;
+
+    let descriptor = CountingSortDescriptor::default().with_workgroup_size(64);
+    let comparator = || MergeSortComparator::new("fn less(a: u32, b: u32) -> bool { return a < b; }");
+
+    for (descriptor, option_name) in [
+        (descriptor.clone().with_items_per_invocation(4), "items_per_invocation"),
+        (descriptor.clone().with_bucket_ranges(true), "bucket_ranges"),
+        (descriptor.clone().with_timestamps(true), "timestamps"),
+        (descriptor.clone().with_debug_snapshots(true), "debug_snapshots"),
+    ] {
+        assert!(matches!(
+            GpuMergeSortModule::from_descriptor(device, &descriptor, 100, comparator()),
+            Err(CountingSortingError::UnsupportedOption("merge sort", name)) if name == option_name
+        ));
+    }
+}
+
+#[test]
+fn check_empty_input_is_rejected() {
+    let (render_instance, device_handle_id) = init_render_instance_and_device();
+    let device = &render_instance.devices[device_handle_id].device;
+
+    assert!(matches!(
+        GpuMergeSortModule::new(device, 0, MergeSortComparator::new("fn less(a: u32, b: u32) -> bool { return a < b; }"), 64),
+        Err(CountingSortingError::EmptyInput("Merge sort elements"))
+    ));
+}
@@ -0,0 +1,88 @@
+use oxyde::wgpu::{self, util::DeviceExt};
+
+use oxyde_sorting::{GpuMortonKeyModule, MortonDimension};
+
+mod common;
+use common::{init_render_instance_and_device, read_buffer};
+
+fn expand_bits(value: u32, bits: u32, spacing: u32) -> u32 {
+    (0..bits).fold(0, |code, bit| code | (((value >> bit) & 1) << (bit * spacing)))
+}
+
+// Morton code of integer coordinates on CPU (first axis is the most significant)
+fn morton_code_on_cpu(coords: &[u32], bits_per_axis: u32) -> u32 {
+    let axis_count = coords.len() as u32;
+    coords
+        .iter()
+        .enumerate()
+        .fold(0, |code, (axis, &coord)| code | (expand_bits(coord, bits_per_axis, axis_count) << (axis_count - 1 - axis as u32)))
+}
+
+fn check_morton_keys(dimension: MortonDimension, bits_per_axis: u32) {
+    let (render_instance, device_handle_id) = init_render_instance_and_device();
+    let device_handle = &render_instance.devices[device_handle_id];
+    let device = &device_handle.device;
+    let queue = &device_handle.queue;
+
+    let bounds_min = [-1.0f32, 0.0, 2.0];
+    let bounds_max = [1.0f32, 4.0, 3.0];
+    let axis_count = dimension.axis_count() as usize;
+
+    // Points at the center of quantization cells (and some outside of the bounds which are clamped)
+    let cell_count = 1u32 << bits_per_axis;
+    let coords = (0..512u32).map(|i| [(i * 7) % cell_count, (i * 13) % cell_count, (i * 29) % cell_count]).collect::<Vec<_>>();
+    let positions = coords
+        .iter()
+        .enumerate()
+        .map(|(i, coord)| {
+            let mut position = [0.0, 0.0, 0.0, 1.0];
+            for axis in 0..3 {
+                let extent = bounds_max[axis] - bounds_min[axis];
+                position[axis] = bounds_min[axis] + (coord[axis] as f32 + 0.5) * extent / cell_count as f32;
+            }
+            if i % 17 == 0 {
+                position[0] = if coord[0] < cell_count / 2 { bounds_min[0] - 1.0 } else { bounds_max[0] + 1.0 };
+            }
+            position
+        })
+        .collect::<Vec<[f32; 4]>>();
+    let position_data = positions.iter().flat_map(|position| match dimension {
+        MortonDimension::Two => position[..2].to_vec(),
+        MortonDimension::Three => position.to_vec(),
+    }).collect::<Vec<f32>>();
+
+    let positions_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("positions buffer"),
+        contents: bytemuck::cast_slice(&position_data),
+        usage: wgpu::BufferUsages::STORAGE,
+    });
+
+    let morton_key_module = GpuMortonKeyModule::new(device, &positions_buffer, dimension, bounds_min, bounds_max, bits_per_axis, 64).unwrap();
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("morton encoder") });
+    morton_key_module.dispatch_work(&mut encoder);
+    queue.submit(Some(encoder.finish()));
+
+    let keys = read_buffer(device, queue, morton_key_module.key_buffer());
+
+    device.set_device_lost_callback(Box::new(|_, _| {}));
+
+    for (i, ((position, coord), key)) in positions.iter().zip(coords.iter()).zip(keys.iter()).enumerate() {
+        let mut coord = *coord;
+        if i % 17 == 0 {
+            coord[0] = if coord[0] < cell_count / 2 { 0 } else { cell_count - 1 };
+        }
+        let expected = morton_code_on_cpu(&coord[..axis_count], bits_per_axis);
+        assert_eq!(*key, expected, "wrong Morton code for position {:?}", position);
+        assert!((*key as u64) < morton_key_module.key_range());
+    }
+}
+
+#[test]
+fn check_morton_3d_10_bits() { check_morton_keys(MortonDimension::Three, 10); }
+
+#[test]
+fn check_morton_3d_4_bits() { check_morton_keys(MortonDimension::Three, 4); }
+
+#[test]
+fn check_morton_2d_16_bits() { check_morton_keys(MortonDimension::Two, 16); }
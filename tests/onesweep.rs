@@ -0,0 +1,98 @@
+use oxyde::wgpu::{self, util::DeviceExt};
+
+use oxyde_sorting::{CountingSortDescriptor, CountingSortingError, GpuOnesweepRadixSortModule, GpuSorter, WorkgroupSize};
+
+mod common;
+use common::{init_render_instance_and_device, read_buffer};
+
+fn check_onesweep_sorting(value_size: u32, key_bits: u32, workgroup_size: impl Into<WorkgroupSize>) {
+    let (render_instance, device_handle_id) = init_render_instance_and_device();
+    let device_handle = &render_instance.devices[device_handle_id];
+    let device = &device_handle.device;
+    let queue = &device_handle.queue;
+
+    // Keys over the whole u32 range, only their key_bits low bits are sorted
+    let key_mask = u32::MAX >> (u32::BITS - key_bits);
+    let values = (0..value_size).map(|i| i.wrapping_mul(2654435761).wrapping_add(0x9e3779b9)).collect::<Vec<u32>>();
+
+    let values_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("values buffer"),
+        contents: bytemuck::cast_slice(&values),
+        usage: wgpu::BufferUsages::STORAGE,
+    });
+
+    let onesweep_module = GpuOnesweepRadixSortModule::new(device, &values_buffer, key_bits, workgroup_size).unwrap();
+    assert_eq!(onesweep_module.digit_count(), key_bits.div_ceil(8));
+    assert!(onesweep_module.requirements().supports_key_range(key_mask));
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("onesweep sort encoder") });
+    onesweep_module.dispatch_work(&mut encoder);
+    queue.submit(Some(encoder.finish()));
+
+    // The sort is stable so the ids are the ones of a stable CPU sort
+    let sorting_ids = read_buffer(device, queue, onesweep_module.sorting_id_buffer());
+    let mut expected_sorting_ids = (0..value_size).collect::<Vec<u32>>();
+    expected_sorting_ids.sort_by_key(|&id| values[id as usize] & key_mask);
+    assert_eq!(sorting_ids, expected_sorting_ids);
+
+    let sorted_keys = read_buffer(device, queue, onesweep_module.sorted_key_buffer());
+    assert_eq!(sorted_keys, expected_sorting_ids.iter().map(|&id| values[id as usize]).collect::<Vec<u32>>());
+}
+
+#[test]
+fn check_onesweep_sorting_single_digit() { check_onesweep_sorting(1000, 8, 64); }
+
+#[test]
+fn check_onesweep_sorting_partial_last_digit() { check_onesweep_sorting(20000, 12, 64); }
+
+#[test]
+fn check_onesweep_sorting_morton_keys() { check_onesweep_sorting(50000, 30, 256); }
+
+#[test]
+fn check_onesweep_sorting_full_range() { check_onesweep_sorting(3001, 32, WorkgroupSize::Auto); }
+
+#[test]
+fn check_invalid_key_bits_are_rejected() {
+    let (render_instance, device_handle_id) = init_render_instance_and_device();
+    let device = &render_instance.devices[device_handle_id].device;
+
+    let values_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("values buffer"),
+        size: 64,
+        usage: wgpu::BufferUsages::STORAGE,
+        mapped_at_creation: false,
+    });
+
+    for key_bits in [0, 33] {
+        assert!(matches!(
+            GpuOnesweepRadixSortModule::new(device, &values_buffer, key_bits, 64),
+            Err(CountingSortingError::InvalidKeyBits(bits)) if bits == key_bits
+        ));
+    }
+}
+
+#[test]
+fn check_unsupported_options_are_rejected() {
+    let (render_instance, device_handle_id) = init_render_instance_and_device();
+    let device = &render_instance.devices[device_handle_id].device;
+
+    let values_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("values buffer"),
+        size: 4096,
+        usage: wgpu::BufferUsages::STORAGE,
+        mapped_at_creation: false,
+    });
+    let descriptor = CountingSortDescriptor::default().with_workgroup_size(64);
+
+    for (descriptor, option_name) in [
+        (descriptor.clone().with_items_per_invocation(4), "items_per_invocation"),
+        (descriptor.clone().with_bucket_ranges(true), "bucket_ranges"),
+        (descriptor.clone().with_timestamps(true), "timestamps"),
+        (descriptor.clone().with_debug_snapshots(true), "debug_snapshots"),
+    ] {
+        assert!(matches!(
+            GpuOnesweepRadixSortModule::from_descriptor(device, &descriptor, &values_buffer, 30),
+            Err(CountingSortingError::UnsupportedOption("onesweep radix sort", name)) if name == option_name
+        ));
+    }
+}
@@ -16,16 +16,21 @@ fn rand_u(seed: u32) -> u32 {
 }
 
 @compute @workgroup_size(#WORKGROUP_SIZE)
-fn main(@builtin(global_invocation_id) GlobalInvocationID : vec3<u32>) {
+// Grid-stride loop, so any value count is initialized with at most max_compute_workgroups_per_dimension workgroups
+fn main(
+    @builtin(global_invocation_id) GlobalInvocationID : vec3<u32>,
+    @builtin(num_workgroups) NumWorkgroups : vec3<u32>,
+) {
     let total = arrayLength(&values);
-    let index: u32 = GlobalInvocationID.x;
-    if (index >= total) { return; }
+    let stride = NumWorkgroups.x * u32(#WORKGROUP_SIZE);
 
-    if (uniforms.init_method == 0u) {
-        values[index] = uniforms.init_value % uniforms.max_value;
-    }else if (uniforms.init_method == 1u) {
-        values[index] = index % uniforms.max_value;
-    } else {
-        values[index] = rand_u(index + uniforms.current_time_ms) % uniforms.max_value;
+    for (var index = GlobalInvocationID.x; index < total; index += stride) {
+        if (uniforms.init_method == 0u) {
+            values[index] = uniforms.init_value % uniforms.max_value;
+        }else if (uniforms.init_method == 1u) {
+            values[index] = index % uniforms.max_value;
+        } else {
+            values[index] = rand_u(index + uniforms.current_time_ms) % uniforms.max_value;
+        }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,44 @@
+// Needs spatial_hash_grid_common.wgsl to be prepended
+// Count the particles within RADIUS of each particle (itself included) by iterating over its 27 neighbour cells
+
+// Smaller than the cell size so that every neighbour is in one of the 27 cells
+const RADIUS: f32 = 0.45;
+
+@group(0) @binding(0) var<storage, read> positions : array<vec4<f32>>;
+@group(0) @binding(1) var<storage, read> sorted_ids : array<u32>;
+@group(0) @binding(2) var<storage, read> cell_ranges : array<vec2<u32>>;
+@group(0) @binding(3) var<storage, read_write> neighbour_counts : array<u32>;
+
+@group(1) @binding(0) var<uniform> grid : SpatialHashGridParams;
+
+@compute @workgroup_size(#WORKGROUP_SIZE)
+fn count_neighbours(@builtin(global_invocation_id) globalInvocationId : vec3<u32>) {
+    let particle = globalInvocationId.x;
+    if (particle >= arrayLength(&neighbour_counts)) { return; }
+
+    let position = positions[particle].xyz;
+    let cell = spatial_hash_grid_cell(grid, position);
+
+    var count = 0u;
+    for (var n = 0u; n < SPATIAL_HASH_GRID_NEIGHBOUR_COUNT; n++) {
+        let key = spatial_hash_grid_neighbour_key(grid, cell, n);
+        if (key == SPATIAL_HASH_GRID_INVALID_KEY) { continue; }
+
+        // Neighbour cells of a hashed grid may share a key, its range is only visited once
+        var visited = false;
+        for (var m = 0u; m < n; m++) {
+            visited = visited || spatial_hash_grid_neighbour_key(grid, cell, m) == key;
+        }
+        if (visited) { continue; }
+
+        let range = cell_ranges[key];
+        for (var i = range.x; i < range.y; i++) {
+            let offset = positions[sorted_ids[i]].xyz - position;
+            if (dot(offset, offset) <= RADIUS * RADIUS) {
+                count++;
+            }
+        }
+    }
+
+    neighbour_counts[particle] = count;
+}
@@ -0,0 +1,160 @@
+use oxyde::{
+    wgpu::{self, util::DeviceExt},
+    wgpu_utils::{binding_builder, buffers, ShaderComposer},
+};
+
+use oxyde_sorting::{GpuSpatialHashGrid, SpatialHashGridLayout, SPATIAL_HASH_GRID_WGSL};
+
+mod common;
+use common::{init_render_instance_and_device, read_buffer};
+
+fn check_spatial_hash_grid(layout: SpatialHashGridLayout) {
+    let (render_instance, device_handle_id) = init_render_instance_and_device();
+    let device_handle = &render_instance.devices[device_handle_id];
+    let device = &device_handle.device;
+    let queue = &device_handle.queue;
+
+    let cell_size = 0.5f32;
+    let positions = (0..1000u32)
+        .map(|i| [(i % 10) as f32 * 0.37, ((i / 10) % 10) as f32 * 0.41, (i / 100) as f32 * 0.29, 1.0])
+        .collect::<Vec<[f32; 4]>>();
+
+    let positions_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("positions buffer"),
+        contents: bytemuck::cast_slice(&positions),
+        usage: wgpu::BufferUsages::STORAGE,
+    });
+
+    let spatial_hash_grid = GpuSpatialHashGrid::new(device, &positions_buffer, cell_size, layout, 64).unwrap();
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("spatial hash grid encoder") });
+    spatial_hash_grid.dispatch_work(&mut encoder);
+    queue.submit(Some(encoder.finish()));
+
+    let cell_keys = read_buffer(device, queue, spatial_hash_grid.cell_key_buffer());
+    let sorting_ids = read_buffer(device, queue, spatial_hash_grid.sorting_id_buffer());
+    let cell_ranges = read_buffer(device, queue, spatial_hash_grid.cell_range_buffer());
+    let neighbour_counts = count_neighbours(device, queue, &spatial_hash_grid, &positions_buffer);
+
+    device.set_device_lost_callback(Box::new(|_, _| {}));
+
+    // Key of every particle as computed by the WGSL helpers
+    let expected_key = |position: &[f32; 4]| match layout {
+        SpatialHashGridLayout::Bounded { origin, cell_count } => {
+            let [x, y, z] = [0, 1, 2].map(|axis| {
+                (((position[axis] - origin[axis]) / cell_size).floor() as i32).clamp(0, cell_count[axis] as i32 - 1) as u32
+            });
+            x + cell_count[0] * (y + cell_count[1] * z)
+        },
+        SpatialHashGridLayout::Hashed { table_size } => {
+            let [x, y, z] = [0, 1, 2].map(|axis| (position[axis] / cell_size).floor() as i32 as u32);
+            (x.wrapping_mul(73856093) ^ y.wrapping_mul(19349663) ^ z.wrapping_mul(83492791)) % table_size
+        },
+    };
+    for (i, position) in positions.iter().enumerate() {
+        assert_eq!(cell_keys[i], expected_key(position), "particle {} has a wrong key", i);
+    }
+
+    // Each cell range contains exactly the particles with that key
+    for (key, range) in cell_ranges.chunks(2).enumerate() {
+        for &id in &sorting_ids[range[0] as usize..range[1] as usize] {
+            assert_eq!(cell_keys[id as usize], key as u32, "particle {} is in the range of cell {}", id, key);
+        }
+    }
+    assert_eq!(cell_ranges.last().copied(), Some(positions.len() as u32));
+
+    // Looking neighbours up through the 27 cells finds the same particles as a brute force search
+    let distance_squared = |a: &[f32; 4], b: &[f32; 4]| (0..3).map(|axis| (a[axis] - b[axis]) * (a[axis] - b[axis])).sum::<f32>();
+    for (i, position) in positions.iter().enumerate() {
+        let expected_count = positions.iter().filter(|other| distance_squared(position, other) <= NEIGHBOUR_RADIUS * NEIGHBOUR_RADIUS).count();
+        assert_eq!(neighbour_counts[i] as usize, expected_count, "particle {} has a wrong neighbour count", i);
+    }
+}
+
+// Same radius as in neighbour_count.wgsl
+const NEIGHBOUR_RADIUS: f32 = 0.45;
+
+// Count the neighbours of each particle with the 27 cell iteration of `SPATIAL_HASH_GRID_WGSL` (see shaders/neighbour_count.wgsl)
+fn count_neighbours(device: &wgpu::Device, queue: &wgpu::Queue, spatial_hash_grid: &GpuSpatialHashGrid, positions_buffer: &wgpu::Buffer) -> Vec<u32> {
+    let workgroup_size = 64;
+    let particle_count = spatial_hash_grid.particle_count();
+    let neighbour_count_buffer = buffers::create_buffer_for_size(
+        device,
+        wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        Some("neighbour count buffer"),
+        particle_count as wgpu::BufferAddress * std::mem::size_of::<u32>() as wgpu::BufferAddress,
+    );
+
+    let storage_binding_type = |read_only| wgpu::BindingType::Buffer {
+        ty: wgpu::BufferBindingType::Storage { read_only },
+        has_dynamic_offset: false,
+        min_binding_size: None,
+    };
+    let bind_group_layout_with_desc = binding_builder::BindGroupLayoutBuilder::new()
+        .add_binding_compute(storage_binding_type(true))
+        .add_binding_compute(storage_binding_type(true))
+        .add_binding_compute(storage_binding_type(true))
+        .add_binding_compute(storage_binding_type(false))
+        .create(device, None);
+    let uniform_bind_group_layout_with_desc = binding_builder::BindGroupLayoutBuilder::new()
+        .add_binding_compute(wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        })
+        .create(device, None);
+
+    let bind_group = binding_builder::BindGroupBuilder::new(&bind_group_layout_with_desc)
+        .resource(positions_buffer.as_entire_binding())
+        .resource(spatial_hash_grid.sorting_id_buffer().as_entire_binding())
+        .resource(spatial_hash_grid.cell_range_buffer().as_entire_binding())
+        .resource(neighbour_count_buffer.as_entire_binding())
+        .create(device, Some("neighbour count bind_group"));
+    let params_bind_group = binding_builder::BindGroupBuilder::new(&uniform_bind_group_layout_with_desc)
+        .resource(spatial_hash_grid.params_buffer().as_entire_binding())
+        .create(device, Some("neighbour count params bind_group"));
+
+    let shader_source = format!("{}\n{}", SPATIAL_HASH_GRID_WGSL, include_str!("shaders/neighbour_count.wgsl"));
+    let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("neighbour count shader"),
+        source: wgpu::ShaderSource::Naga(std::borrow::Cow::Owned(
+            ShaderComposer::new(&shader_source, Some("neighbour_count"))
+                .with_shader_define("WORKGROUP_SIZE", workgroup_size.into())
+                .build()
+                .unwrap(),
+        )),
+    });
+    let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some("neighbour count pipeline"),
+        layout: Some(&device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("neighbour count pipeline layout"),
+            bind_group_layouts: &[&bind_group_layout_with_desc.layout, &uniform_bind_group_layout_with_desc.layout],
+            push_constant_ranges: &[],
+        })),
+        module: &shader_module,
+        entry_point: "count_neighbours",
+    });
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("neighbour count encoder") });
+    {
+        let neighbour_count_pass = &mut encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Neighbour Count Pass"),
+            timestamp_writes: None,
+        });
+        neighbour_count_pass.set_pipeline(&pipeline);
+        neighbour_count_pass.set_bind_group(0, &bind_group, &[]);
+        neighbour_count_pass.set_bind_group(1, &params_bind_group, &[]);
+        neighbour_count_pass.dispatch_workgroups(particle_count.div_ceil(workgroup_size), 1, 1);
+    }
+    queue.submit(Some(encoder.finish()));
+
+    read_buffer(device, queue, &neighbour_count_buffer)
+}
+
+#[test]
+fn check_bounded_spatial_hash_grid() {
+    check_spatial_hash_grid(SpatialHashGridLayout::Bounded { origin: [0.0; 3], cell_count: [8, 8, 8] });
+}
+
+#[test]
+fn check_hashed_spatial_hash_grid() { check_spatial_hash_grid(SpatialHashGridLayout::Hashed { table_size: 127 }); }
@@ -1,12 +1,17 @@
-use log;
-
 use oxyde::{
-    wgpu, wgpu_utils::{
-        self, binding_builder, buffers::{self, StagingBufferWrapper}, uniform_buffer::UniformBufferWrapper, ShaderComposer
+    wgpu::{self, util::DeviceExt}, wgpu_utils::{
+        self, binding_builder, buffers, uniform_buffer::UniformBufferWrapper, ShaderComposer
     }
 };
 
-use oxyde_sorting::GpuCountingSortModule;
+use oxyde_sorting::{
+    cpu::{self, CpuCountingSortBackend},
+    block_on_readback, dump_count_snapshots, sort_ids, CountingSortBackend, CountingSortDescriptor, CountingSortOutput, CountingSortingError,
+    GpuCountingSortBackend, GpuCountingSortModule, GpuSorter, SortPipelineCache, WorkgroupSize,
+};
+
+mod common;
+use common::{init_render_instance_and_device, read_buffer};
 
 #[derive(Clone, Copy, bytemuck::Pod, bytemuck::Zeroable, Default)]
 #[repr(C)]
@@ -21,37 +26,41 @@ struct BuffersAndPipeline {
     value_buffer: wgpu::Buffer,
     count_buffer: wgpu::Buffer,
     counting_sort_module: GpuCountingSortModule,
-    value_staging_buffer: StagingBufferWrapper<u32, true>,
-    count_staging_buffer: StagingBufferWrapper<u32, true>,
-    sorting_staging_buffer: StagingBufferWrapper<u32, true>,
     init_uniforms_buffer: UniformBufferWrapper<InitUniforms>,
     value_bind_group: wgpu::BindGroup,
     init_values_pipeline: wgpu::ComputePipeline,
 }
 
-fn init_buffers_and_pipeline(device: &wgpu::Device, value_size: u32, count_size: u32, workgroup_size: u32) -> BuffersAndPipeline {
+fn init_buffers_and_pipeline(
+    device: &wgpu::Device,
+    value_size: u32,
+    count_size: u32,
+    workgroup_size: u32,
+    items_per_invocation: u32,
+) -> BuffersAndPipeline {
     let size_of_u32 = std::mem::size_of::<u32>() as u64;
     let value_buffer = buffers::create_buffer_for_size(
-        &device,
+        device,
         wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
         Some("values buffer"),
         value_size as u64 * size_of_u32,
     );
     let count_buffer = buffers::create_buffer_for_size(
-        &device,
+        device,
         wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
         Some("count buffer"),
         count_size as u64 * size_of_u32,
     );
 
-    let counting_sort_module = GpuCountingSortModule::new(&device, &value_buffer, &count_buffer, workgroup_size).unwrap();
-
-    let value_staging_buffer: StagingBufferWrapper<u32, true> = buffers::StagingBufferWrapper::new(&device, value_size as _);
-    let count_staging_buffer: StagingBufferWrapper<u32, true> = buffers::StagingBufferWrapper::new(&device, count_size as _);
-    let sorting_staging_buffer: StagingBufferWrapper<u32, true> = buffers::StagingBufferWrapper::new(&device, value_size as _);
+    let counting_sort_module = GpuCountingSortModule::new(device, &value_buffer, &count_buffer, workgroup_size)
+        .unwrap()
+        .with_items_per_invocation(device, items_per_invocation)
+        .unwrap()
+        .with_bucket_ranges(device)
+        .unwrap();
 
     let init_uniforms_buffer = UniformBufferWrapper::new(
-        &device,
+        device,
         InitUniforms {
             current_time_ms: 0,
             init_method: 2,
@@ -67,16 +76,16 @@ fn init_buffers_and_pipeline(device: &wgpu::Device, value_size: u32, count_size:
             has_dynamic_offset: false,
             min_binding_size: None,
         })
-        .create(&device, None);
+        .create(device, None);
 
     let value_bind_group = binding_builder::BindGroupBuilder::new(&value_bind_group_layout_with_desc)
         .resource(value_buffer.as_entire_binding())
-        .create(&device, Some("init random value bind_group"));
+        .create(device, Some("init random value bind_group"));
 
     let init_shader_module = &device.create_shader_module(wgpu::ShaderModuleDescriptor {
         label: Some("init shader"),
         source: wgpu::ShaderSource::Naga(std::borrow::Cow::Owned(
-            ShaderComposer::new(include_str!("shaders/init.wgsl").into(), Some("init"))
+            ShaderComposer::new(include_str!("shaders/init.wgsl"), Some("init"))
                 .with_shader_define("WORKGROUP_SIZE", workgroup_size.into())
                 .build()
                 .unwrap(),
@@ -98,73 +107,12 @@ fn init_buffers_and_pipeline(device: &wgpu::Device, value_size: u32, count_size:
         value_buffer,
         count_buffer,
         counting_sort_module,
-        value_staging_buffer,
-        count_staging_buffer,
-        sorting_staging_buffer,
         init_uniforms_buffer,
         value_bind_group,
         init_values_pipeline,
     }
 }
 
-fn init_render_instance_and_device() -> (wgpu_utils::render_handles::RenderInstance, usize) {
-    let mut render_instance = wgpu_utils::render_handles::RenderInstance::new(None, None);
-
-    // Force HighPerformance adapter for choosing Dedicated GPU as it seems to not work (Device lost: Dropped - Device is dying.) on integrated intel GPU (Intel(R) Iris(R) Xe Graphics)
-    let device_handle_id = pollster::block_on(render_instance.device(None, Some(wgpu::PowerPreference::HighPerformance)))
-        .expect("Device creation failed");
-
-    let device = &render_instance.devices[device_handle_id].device;
-
-    device.on_uncaptured_error(Box::new(|err| panic!("{}", err)));
-
-    device.set_device_lost_callback(Box::new(|device_lost_reason, str| {
-        match device_lost_reason {
-            wgpu::DeviceLostReason::ReplacedCallback => {
-                log::debug!("Device replace lost callback: {}", str);
-            }
-            _ => {
-                panic!("Device lost: {:?} - {}", device_lost_reason, str);
-            }
-        }
-    }));
-
-    (render_instance, device_handle_id)
-}
-
-fn count_values(values: &[u32], count_size: usize) -> Vec<u32> {
-    let mut count = vec![0u32; count_size];
-    for value in values.iter() {
-        count[*value as usize] += 1;
-    }
-    count
-}
-
-fn cpu_prefix_sum(count: &mut [u32]) {
-    for i in 1..count.len() {
-        count[i] += count[i - 1];
-    }
-}
-
-fn sorting_id_sort_from_count(values: &[u32], count: &[u32]) -> (Vec<u32>, Vec<u32>) {
-    let mut sorting_id = vec![0u32; values.len()];
-    let mut count_after_sort = count.to_vec();
-    for (i, value) in values.iter().enumerate() {
-        let value = *value as usize;
-        sorting_id[count_after_sort[value] as usize - 1] = i as u32;
-        count_after_sort[value] -= 1;
-    }
-    (sorting_id, count_after_sort)
-}
-
-fn counting_sort_on_cpu(values_slice: &[u32], count_size: usize) -> (Vec<u32>, Vec<u32>, Vec<u32>) {
-    let mut count_cpu = count_values(values_slice, count_size);
-    cpu_prefix_sum(count_cpu.as_mut_slice());
-    let (sorting_id_cpu, count_after_sort_cpu) = sorting_id_sort_from_count(values_slice, &count_cpu);
-    
-    (count_cpu, sorting_id_cpu, count_after_sort_cpu)
-}
-
 fn is_sorted_by_id(values: &[u32], sorting_id: &[u32]) -> bool {
     for i in 1..sorting_id.len() {
         if values[sorting_id[i] as usize] < values[sorting_id[i - 1] as usize] {
@@ -174,7 +122,10 @@ fn is_sorted_by_id(values: &[u32], sorting_id: &[u32]) -> bool {
     true
 }
 
-fn check_sorting_with_sizes(value_size: u32, count_size: u32, workgroup_size: u32) {
+fn check_sorting_with_sizes(value_size: u32, count_size: u32, workgroup_size: u32) { check_sorting_with_options(value_size, count_size, workgroup_size, 2, 1) }
+
+// init_method: 0 for a constant value, 1 for index modulo the count size and 2 for random values
+fn check_sorting_with_options(value_size: u32, count_size: u32, workgroup_size: u32, init_method: u32, items_per_invocation: u32) {
     let _ = simple_logger::SimpleLogger::new()
         .with_level(log::LevelFilter::Trace)
         .with_module_level("naga", log::LevelFilter::Info)
@@ -190,20 +141,17 @@ fn check_sorting_with_sizes(value_size: u32, count_size: u32, workgroup_size: u3
         value_buffer,
         count_buffer,
         counting_sort_module,
-        mut value_staging_buffer,
-        mut count_staging_buffer,
-        mut sorting_staging_buffer,
         mut init_uniforms_buffer,
         value_bind_group,
         init_values_pipeline,
-    } = init_buffers_and_pipeline(&device, value_size, count_size, workgroup_size);
+    } = init_buffers_and_pipeline(device, value_size, count_size, workgroup_size, items_per_invocation);
 
     init_uniforms_buffer.content_mut().current_time_ms = std::time::SystemTime::now()
         .duration_since(std::time::SystemTime::UNIX_EPOCH)
         .unwrap()
         .as_micros() as u32;
-    init_uniforms_buffer.content_mut().init_method = 2u32;
-    init_uniforms_buffer.update_content(&queue);
+    init_uniforms_buffer.content_mut().init_method = init_method;
+    init_uniforms_buffer.update_content(queue);
 
     let mut commands: Vec<wgpu::CommandBuffer> = vec![];
 
@@ -219,8 +167,8 @@ fn check_sorting_with_sizes(value_size: u32, count_size: u32, workgroup_size: u3
 
             init_pass.set_pipeline(&init_values_pipeline);
             init_pass.set_bind_group(0, &value_bind_group, &[]);
-            init_pass.set_bind_group(1, &init_uniforms_buffer.bind_group(), &[]);
-            init_pass.dispatch_workgroups((value_size + workgroup_size) / workgroup_size, 1, 1);
+            init_pass.set_bind_group(1, init_uniforms_buffer.bind_group(), &[]);
+            init_pass.dispatch_workgroups(value_size.div_ceil(workgroup_size).min(device.limits().max_compute_workgroups_per_dimension), 1, 1);
         }
 
         commands.push(init_values_command_encoder.finish());
@@ -230,72 +178,43 @@ fn check_sorting_with_sizes(value_size: u32, count_size: u32, workgroup_size: u3
         let mut counting_scan_command_encoder: wgpu::CommandEncoder =
             device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("Conting and scan encoder") });
 
-        counting_sort_module.dispatch_work(&mut counting_scan_command_encoder, &count_buffer);
+        counting_sort_module.dispatch_work(&mut counting_scan_command_encoder);
         commands.push(counting_scan_command_encoder.finish());
     }
 
-    {
-        let mut copy_buffer_command_encoder: wgpu::CommandEncoder =
-            device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("Copy buffer encoder") });
-
-        value_staging_buffer.encode_read(&mut copy_buffer_command_encoder, &value_buffer);
-        count_staging_buffer.encode_read(&mut copy_buffer_command_encoder, &count_buffer);
-        sorting_staging_buffer.encode_read(&mut copy_buffer_command_encoder, counting_sort_module.sorting_id_buffer());
-
-        commands.push(copy_buffer_command_encoder.finish());
-    }
-
-    // See https://github.com/gfx-rs/wgpu/issues/3806
-    let index = queue.submit(commands);
-    device.poll(wgpu::Maintain::WaitForSubmissionIndex(index));
-
-    let (sender_value, receiver_value) = std::sync::mpsc::channel();
-    value_staging_buffer.map_buffer(Some(move |result: Result<(), wgpu::BufferAsyncError>| {
-        let _ = sender_value.send(result).unwrap();
-    }));
-
-    let (sender_count, receiver_count) = std::sync::mpsc::channel();
-    count_staging_buffer.map_buffer(Some(move |result: Result<(), wgpu::BufferAsyncError>| {
-        let _ = sender_count.send(result).unwrap();
-    }));
-
-    sorting_staging_buffer.map_buffer(None::<fn(Result<(), wgpu::BufferAsyncError>)>);
-
-    // wait here for map_buffer to be finished (with wait the lock should be set successfully set)
-    device.poll(wgpu::Maintain::Wait);
+    queue.submit(commands);
 
-    // Read bufferAsyncError
-    receiver_value
-        .recv()
-        .expect("MPSC channel must not fail")
-        .expect("buffer reading value failed");
-    receiver_count
-        .recv()
-        .expect("MPSC channel must not fail")
-        .expect("buffer reading count failed");
-
-    // Read buffer
-    value_staging_buffer.read_and_unmap_buffer();
-    count_staging_buffer.read_and_unmap_buffer();
-    sorting_staging_buffer.read_and_unmap_buffer();
+    let values = read_buffer(device, queue, &value_buffer);
+    let count_after_sort_gpu = read_buffer(device, queue, &count_buffer);
+    let (sorting_id_gpu, bucket_ranges_gpu) = block_on_readback(device, async {
+        (
+            counting_sort_module.read_sorting_ids(device, queue).await.unwrap(),
+            counting_sort_module.read_bucket_ranges(device, queue).await.unwrap(),
+        )
+    });
 
-    let values_slice = value_staging_buffer.values_as_slice();
+    let values_slice = values.as_slice();
 
     // Do the same work as expected on CPU
-    let (_, sorting_id_cpu, count_after_sort_cpu) = counting_sort_on_cpu(values_slice, count_size as usize);
+    let CountingSortOutput {
+        sorting_ids: sorting_id_cpu,
+        bucket_starts: count_after_sort_cpu,
+        bucket_ranges: bucket_ranges_cpu,
+    } = cpu::counting_sort(values_slice, count_size).unwrap();
 
     const MAX_TO_SHOW: usize = 64;
     println!("Value Size : {} (show only last {} elements)", value_size, std::cmp::min(value_size as usize, MAX_TO_SHOW));
     println!("Count Size : {} (show only last {} elements)", count_size, std::cmp::min(count_size as usize, MAX_TO_SHOW));
-    println!("values     : {:?}", value_staging_buffer.iter().rev().take(MAX_TO_SHOW).rev().collect::<Vec<_>>());
-    println!("GPU counts : {:?}", count_staging_buffer.iter().rev().take(MAX_TO_SHOW).rev().collect::<Vec<_>>());
+    println!("values     : {:?}", values.iter().rev().take(MAX_TO_SHOW).rev().collect::<Vec<_>>());
+    println!("GPU counts : {:?}", count_after_sort_gpu.iter().rev().take(MAX_TO_SHOW).rev().collect::<Vec<_>>());
     println!("CPU counts : {:?}", count_after_sort_cpu.iter().rev().take(MAX_TO_SHOW).rev().collect::<Vec<_>>());
-    println!("GPU Sort   : {:?}", sorting_staging_buffer.iter().rev().take(MAX_TO_SHOW).rev().collect::<Vec<_>>());
+    println!("GPU Sort   : {:?}", sorting_id_gpu.iter().rev().take(MAX_TO_SHOW).rev().collect::<Vec<_>>());
     println!("CPU Sort   : {:?}", sorting_id_cpu.iter().rev().take(MAX_TO_SHOW).rev().collect::<Vec<_>>());
 
     let sorted_cpu = is_sorted_by_id(values_slice, &sorting_id_cpu);
-    let sorted_gpu = is_sorted_by_id(values_slice, sorting_staging_buffer.values_as_slice());
-    let count_after_sort_equal = count_after_sort_cpu == count_staging_buffer.values_as_slice();
+    let sorted_gpu = is_sorted_by_id(values_slice, &sorting_id_gpu);
+    let count_after_sort_equal = count_after_sort_cpu == count_after_sort_gpu;
+    let bucket_ranges_equal = bucket_ranges_cpu == bucket_ranges_gpu;
 
     // Clear device lost callback
     device.set_device_lost_callback(Box::new(|_, _| {}));
@@ -303,6 +222,7 @@ fn check_sorting_with_sizes(value_size: u32, count_size: u32, workgroup_size: u3
     assert!(sorted_cpu, "CPU sorting is not correct");
     assert!(sorted_gpu, "GPU sorting is not correct");
     assert!(count_after_sort_equal, "CPU and GPU count after sort are not equal");
+    assert!(bucket_ranges_equal, "CPU and GPU bucket ranges are not equal");
 }
 
 
@@ -334,4 +254,580 @@ fn check_64_4() { check_sorting_with_sizes(64, 64, 4); }
 fn check_20_4() { check_sorting_with_sizes(20, 20, 4); }
 
 #[test]
-fn check_64_8() { check_sorting_with_sizes(64, 4, 8); }
\ No newline at end of file
+fn check_64_8() { check_sorting_with_sizes(64, 4, 8); }
+
+#[test]
+fn check_constant_30000_64() { check_sorting_with_options(30000, 256, 64, 0, 1); }
+
+#[test]
+fn check_constant_30000_32768_64() { check_sorting_with_options(30000, 32768, 64, 0, 1); }
+
+#[test]
+fn check_index_30000_32768_64() { check_sorting_with_options(30000, 32768, 64, 1, 1); }
+
+// More workgroups than max_compute_workgroups_per_dimension (65535)
+#[test]
+fn check_4194304_4096_32() { check_sorting_with_sizes(4_194_304, 4096, 32); }
+
+#[test]
+fn check_30000_64_items_3() { check_sorting_with_options(30000, 30000, 64, 2, 3); }
+
+#[test]
+fn check_30000_64_items_8_vectorized() { check_sorting_with_options(30000, 30000, 64, 2, 8); }
+
+#[test]
+fn check_30001_64_items_8() { check_sorting_with_options(30001, 512, 64, 2, 8); }
+
+#[test]
+fn check_misaligned_binding_is_rejected() {
+    let (render_instance, device_handle_id) = init_render_instance_and_device();
+    let device = &render_instance.devices[device_handle_id].device;
+
+    let arena_buffer = buffers::create_buffer_for_size(
+        device,
+        wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        Some("arena buffer"),
+        4096,
+    );
+
+    let result = GpuCountingSortModule::new_with_bindings(
+        device,
+        wgpu::BufferBinding { buffer: &arena_buffer, offset: 4, size: wgpu::BufferSize::new(1024) },
+        wgpu::BufferBinding { buffer: &arena_buffer, offset: 2048, size: wgpu::BufferSize::new(1024) },
+        None,
+        32,
+    );
+
+    device.set_device_lost_callback(Box::new(|_, _| {}));
+
+    assert!(matches!(result, Err(CountingSortingError::MisalignedBufferOffset(4, _, "Values buffer"))));
+}
+
+#[test]
+fn check_arena_bindings() {
+    let (render_instance, device_handle_id) = init_render_instance_and_device();
+    let device_handle = &render_instance.devices[device_handle_id];
+    let device = &device_handle.device;
+    let queue = &device_handle.queue;
+
+    let alignment = device.limits().min_storage_buffer_offset_alignment as wgpu::BufferAddress;
+    let size_of_u32 = std::mem::size_of::<u32>() as wgpu::BufferAddress;
+    let (value_size, count_size) = (3000u32, 200u32);
+    let values_offset = alignment;
+    let count_offset = (values_offset + value_size as wgpu::BufferAddress * size_of_u32).next_multiple_of(alignment) + alignment;
+
+    let arena_buffer = buffers::create_buffer_for_size(
+        device,
+        wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+        Some("arena buffer"),
+        count_offset + count_size as wgpu::BufferAddress * size_of_u32 + alignment,
+    );
+    let values = (0..value_size).map(|i| i.wrapping_mul(2654435761) % count_size).collect::<Vec<u32>>();
+    queue.write_buffer(&arena_buffer, values_offset, bytemuck::cast_slice(&values));
+
+    let values_binding = wgpu::BufferBinding {
+        buffer: &arena_buffer,
+        offset: values_offset,
+        size: wgpu::BufferSize::new(value_size as u64 * size_of_u32),
+    };
+    let count_binding = wgpu::BufferBinding {
+        buffer: &arena_buffer,
+        offset: count_offset,
+        size: wgpu::BufferSize::new(count_size as u64 * size_of_u32),
+    };
+
+    // Values and counts at non-zero offsets of the same buffer
+    let counting_sort_module = GpuCountingSortModule::new_with_bindings(device, values_binding.clone(), count_binding.clone(), None, 64).unwrap();
+    assert_eq!((counting_sort_module.value_size(), counting_sort_module.count_size()), (value_size, count_size));
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("arena sort encoder") });
+    counting_sort_module.dispatch_work(&mut encoder);
+    queue.submit(Some(encoder.finish()));
+    check_sorted_ids(&values, &read_buffer(device, queue, counting_sort_module.sorting_id_buffer().unwrap()));
+
+    // Sorted keys are read from the values range of the arena buffer
+    let mut sorted_values = values.clone();
+    sorted_values.sort_unstable();
+    assert_eq!(block_on_readback(device, counting_sort_module.read_sorted_keys(device, queue, &arena_buffer)).unwrap(), sorted_values);
+
+    // Overlapping ranges of the same buffer are rejected
+    let overlapping_count_binding = wgpu::BufferBinding { buffer: &arena_buffer, offset: values_offset + alignment, size: count_binding.size };
+    assert!(matches!(
+        GpuCountingSortModule::new_with_bindings(device, values_binding.clone(), overlapping_count_binding, None, 64),
+        Err(CountingSortingError::OverlappingBindings("Values buffer", "Count buffer"))
+    ));
+    assert!(matches!(
+        GpuCountingSortModule::new_with_bindings(device, values_binding.clone(), count_binding, Some(values_binding), 64),
+        Err(CountingSortingError::OverlappingBindings("Values buffer", "Sorting id buffer"))
+    ));
+}
+
+#[test]
+fn check_invalid_workgroup_sizes_are_rejected() {
+    let (render_instance, device_handle_id) = init_render_instance_and_device();
+    let device = &render_instance.devices[device_handle_id].device;
+
+    let buffer_usage = wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST;
+    let values_buffer = buffers::create_buffer_for_size(device, buffer_usage, Some("values buffer"), 1024);
+    let count_buffer = buffers::create_buffer_for_size(device, buffer_usage, Some("count buffer"), 1024);
+
+    assert!(matches!(
+        GpuCountingSortModule::new(device, &values_buffer, &count_buffer, 48),
+        Err(CountingSortingError::InvalidWorkgroupSize(48))
+    ));
+    assert!(matches!(
+        GpuCountingSortModule::new(device, &values_buffer, &count_buffer, 1),
+        Err(CountingSortingError::InvalidWorkgroupSize(1))
+    ));
+
+    let too_big_workgroup_size = (device.limits().max_compute_invocations_per_workgroup + 1).next_power_of_two();
+    assert!(matches!(
+        GpuCountingSortModule::new(device, &values_buffer, &count_buffer, too_big_workgroup_size),
+        Err(CountingSortingError::WorkgroupSizeExceedsLimit(..))
+    ));
+}
+
+#[test]
+fn check_auto_workgroup_size() {
+    let (render_instance, device_handle_id) = init_render_instance_and_device();
+    let device = &render_instance.devices[device_handle_id].device;
+    let limits = device.limits();
+
+    let workgroup_size = WorkgroupSize::Auto.resolve(&limits).unwrap();
+    assert!(workgroup_size.is_power_of_two());
+    assert!(workgroup_size <= limits.max_compute_invocations_per_workgroup);
+    assert!(workgroup_size <= limits.max_compute_workgroup_size_x);
+
+    let buffer_usage = wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST;
+    let values_buffer = buffers::create_buffer_for_size(device, buffer_usage, Some("values buffer"), 1024);
+    let count_buffer = buffers::create_buffer_for_size(device, buffer_usage, Some("count buffer"), 1024);
+    let counting_sort_module = GpuCountingSortModule::new(device, &values_buffer, &count_buffer, WorkgroupSize::Auto).unwrap();
+    assert_eq!(counting_sort_module.workgroup_size(), workgroup_size);
+}
+
+#[test]
+fn check_descriptor_configuration() {
+    let (render_instance, device_handle_id) = init_render_instance_and_device();
+    let device = &render_instance.devices[device_handle_id].device;
+
+    let buffer_usage = wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST;
+    let values_buffer = buffers::create_buffer_for_size(device, buffer_usage, Some("values buffer"), 4096);
+    let count_buffer = buffers::create_buffer_for_size(device, buffer_usage, Some("count buffer"), 1024);
+
+    let descriptor = CountingSortDescriptor::default()
+        .with_label("particles")
+        .with_workgroup_size(64)
+        .with_items_per_invocation(4)
+        .with_bucket_ranges(true)
+        .with_output_usages(wgpu::BufferUsages::VERTEX);
+    assert_eq!(descriptor.label("sorting id buffer"), "particles sorting id buffer");
+
+    let counting_sort_module = descriptor.build(device, &values_buffer, &count_buffer).unwrap();
+    assert_eq!(counting_sort_module.descriptor(), &descriptor);
+    assert!(counting_sort_module.sorting_id_buffer().unwrap().usage().contains(wgpu::BufferUsages::VERTEX));
+    assert!(counting_sort_module.bucket_range_buffer().unwrap().usage().contains(wgpu::BufferUsages::VERTEX));
+
+    // The configuration can be reused to build sibling modules
+    let sibling_module = counting_sort_module.descriptor().build(device, &values_buffer, &count_buffer).unwrap();
+    assert_eq!(sibling_module.descriptor(), counting_sort_module.descriptor());
+
+    assert!(matches!(
+        descriptor.clone().with_items_per_invocation(0).build(device, &values_buffer, &count_buffer),
+        Err(CountingSortingError::InvalidItemsPerInvocation(0))
+    ));
+}
+
+fn check_sorted_ids(values: &[u32], sorting_ids: &[u32]) {
+    let mut sorting_ids_as_set = sorting_ids.to_vec();
+    sorting_ids_as_set.sort_unstable();
+    assert_eq!(sorting_ids_as_set, (0..values.len() as u32).collect::<Vec<_>>());
+    assert!(is_sorted_by_id(values, sorting_ids));
+}
+
+#[test]
+fn check_rebind_and_resize() {
+    let (render_instance, device_handle_id) = init_render_instance_and_device();
+    let device_handle = &render_instance.devices[device_handle_id];
+    let device = &device_handle.device;
+    let queue = &device_handle.queue;
+
+    let create_buffers = |value_size: u32, count_size: u32| {
+        let values = (0..value_size).map(|i| i.wrapping_mul(2654435761) % count_size).collect::<Vec<u32>>();
+        let values_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("values buffer"),
+            contents: bytemuck::cast_slice(&values),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let count_buffer = buffers::create_buffer_for_size(
+            device,
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC | wgpu::BufferUsages::COPY_DST,
+            Some("count buffer"),
+            count_size as wgpu::BufferAddress * std::mem::size_of::<u32>() as wgpu::BufferAddress,
+        );
+        (values, values_buffer, count_buffer)
+    };
+
+    let sort = |counting_sort_module: &GpuCountingSortModule| {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("sort encoder") });
+        counting_sort_module.dispatch_work(&mut encoder);
+        queue.submit(Some(encoder.finish()));
+        read_buffer(device, queue, counting_sort_module.sorting_id_buffer().unwrap())
+    };
+
+    let (values, values_buffer, count_buffer) = create_buffers(1024, 64);
+    let mut counting_sort_module = GpuCountingSortModule::new(device, &values_buffer, &count_buffer, 64).unwrap();
+    check_sorted_ids(&values, &sort(&counting_sort_module));
+
+    // Only the first values are sorted
+    counting_sort_module.resize(device, &values_buffer, Some(&count_buffer), 500).unwrap();
+    assert_eq!(counting_sort_module.value_size(), 500);
+    check_sorted_ids(&values[..500], &sort(&counting_sort_module));
+
+    // Bigger buffers needing more scan levels
+    let (values, values_buffer, count_buffer) = create_buffers(30000, 8192);
+    counting_sort_module.rebind(device, &values_buffer, &count_buffer).unwrap();
+    assert_eq!(counting_sort_module.count_size(), 8192);
+    check_sorted_ids(&values, &sort(&counting_sort_module));
+}
+
+#[test]
+fn check_shared_pipeline_cache() {
+    let (render_instance, device_handle_id) = init_render_instance_and_device();
+    let device_handle = &render_instance.devices[device_handle_id];
+    let device = &device_handle.device;
+    let queue = &device_handle.queue;
+
+    let pipeline_cache = std::sync::Arc::new(SortPipelineCache::new(device, Some("emitters")));
+    let descriptor = CountingSortDescriptor::default().with_workgroup_size(64).with_pipeline_cache(pipeline_cache.clone());
+
+    let emitters = (0..4u32)
+        .map(|emitter| {
+            let values = (0..2048u32).map(|i| (i * (emitter + 7)) % 1024).collect::<Vec<u32>>();
+            let values_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("values buffer"),
+                contents: bytemuck::cast_slice(&values),
+                usage: wgpu::BufferUsages::STORAGE,
+            });
+            let count_buffer = buffers::create_buffer_for_size(
+                device,
+                wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+                Some("count buffer"),
+                1024 * std::mem::size_of::<u32>() as wgpu::BufferAddress,
+            );
+            let counting_sort_module = descriptor.build(device, &values_buffer, &count_buffer).unwrap();
+            (values, values_buffer, count_buffer, counting_sort_module)
+        })
+        .collect::<Vec<_>>();
+
+    // Same sizes and options: the pipelines of the first module are reused by the others
+    let pipeline_count = pipeline_cache.pipeline_count();
+    assert!(pipeline_count > 0);
+    let _sibling_module = descriptor.build(device, &emitters[0].1, &emitters[0].2).unwrap();
+    assert_eq!(pipeline_cache.pipeline_count(), pipeline_count);
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("sort encoder") });
+    for (_, _, _, counting_sort_module) in emitters.iter() {
+        counting_sort_module.dispatch_work(&mut encoder);
+    }
+    queue.submit(Some(encoder.finish()));
+
+    for (values, _, _, counting_sort_module) in emitters.iter() {
+        check_sorted_ids(values, &read_buffer(device, queue, counting_sort_module.sorting_id_buffer().unwrap()));
+    }
+}
+
+#[test]
+fn check_pipeline_cache_shared_between_threads() {
+    let (render_instance, device_handle_id) = init_render_instance_and_device();
+    let device = &render_instance.devices[device_handle_id].device;
+
+    let size_of_u32 = std::mem::size_of::<u32>() as wgpu::BufferAddress;
+    let buffer = |size: u32| buffers::create_buffer_for_size(device, wgpu::BufferUsages::STORAGE, None, size as wgpu::BufferAddress * size_of_u32);
+    let (values_buffer, count_buffer) = (buffer(2048), buffer(1024));
+
+    let single_thread_cache = std::sync::Arc::new(SortPipelineCache::new(device, None));
+    let descriptor = CountingSortDescriptor::default().with_workgroup_size(64);
+    descriptor.clone().with_pipeline_cache(single_thread_cache.clone()).build(device, &values_buffer, &count_buffer).unwrap();
+
+    // Modules built concurrently compile their pipelines in parallel and end up with a single pipeline per specialization
+    let pipeline_cache = std::sync::Arc::new(SortPipelineCache::new(device, None));
+    let descriptor = descriptor.with_pipeline_cache(pipeline_cache.clone());
+    std::thread::scope(|scope| {
+        for _ in 0..4 {
+            scope.spawn(|| {
+                descriptor.build(device, &values_buffer, &count_buffer).unwrap();
+            });
+        }
+    });
+    assert_eq!(pipeline_cache.pipeline_count(), single_thread_cache.pipeline_count());
+}
+
+#[test]
+fn check_owned_count_buffer() {
+    let (render_instance, device_handle_id) = init_render_instance_and_device();
+    let device_handle = &render_instance.devices[device_handle_id];
+    let device = &device_handle.device;
+    let queue = &device_handle.queue;
+
+    let count_size = 300;
+    let values = (0..5000u32).map(|i| (i * 7919) % count_size).collect::<Vec<u32>>();
+    let values_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("values buffer"),
+        contents: bytemuck::cast_slice(&values),
+        usage: wgpu::BufferUsages::STORAGE,
+    });
+
+    let mut counting_sort_module = GpuCountingSortModule::new_with_count_size(device, &values_buffer, count_size, 64).unwrap();
+    assert_eq!(counting_sort_module.count_size(), count_size);
+
+    let sort = |counting_sort_module: &GpuCountingSortModule| {
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("sort encoder") });
+        counting_sort_module.dispatch_work(&mut encoder);
+        queue.submit(Some(encoder.finish()));
+        read_buffer(device, queue, counting_sort_module.sorting_id_buffer().unwrap())
+    };
+
+    // Sorting twice checks that the owned count buffer is cleared between dispatches
+    check_sorted_ids(&values, &sort(&counting_sort_module));
+    check_sorted_ids(&values, &sort(&counting_sort_module));
+
+    // After the sort the owned count buffer holds the start offset of each bucket
+    let expected_bucket_starts = cpu::counting_sort(&values, count_size).unwrap().bucket_starts;
+    assert_eq!(read_buffer(device, queue, counting_sort_module.count_buffer().unwrap()), expected_bucket_starts);
+
+    // Resizing keeps the owned count buffer
+    counting_sort_module.resize(device, &values_buffer, None, 1000).unwrap();
+    assert!(counting_sort_module.count_buffer().is_some());
+    check_sorted_ids(&values[..1000], &sort(&counting_sort_module));
+}
+
+#[test]
+fn check_sort_ids_from_host() {
+    let (render_instance, device_handle_id) = init_render_instance_and_device();
+    let device_handle = &render_instance.devices[device_handle_id];
+    let device = &device_handle.device;
+    let queue = &device_handle.queue;
+
+    let keys = (0..10000u32).map(|i| i.wrapping_mul(2654435761) % 777).collect::<Vec<u32>>();
+    let sorting_ids = sort_ids(device, queue, &keys, 777).unwrap();
+    check_sorted_ids(&keys, &sorting_ids);
+
+    assert!(sort_ids(device, queue, &[], 16).unwrap().is_empty());
+    assert!(matches!(sort_ids(device, queue, &[3, 16, 2], 16), Err(CountingSortingError::KeyOutOfRange(16, 16))));
+}
+
+#[test]
+fn check_async_readback() {
+    let (render_instance, device_handle_id) = init_render_instance_and_device();
+    let device_handle = &render_instance.devices[device_handle_id];
+    let device = &device_handle.device;
+    let queue = &device_handle.queue;
+
+    let count_size = 300u32;
+    let values = (0..5000u32).map(|i| i.wrapping_mul(2654435761) % count_size).collect::<Vec<u32>>();
+    let values_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("values buffer"),
+        contents: bytemuck::cast_slice(&values),
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+    });
+
+    let counting_sort_module = CountingSortDescriptor::default()
+        .with_bucket_ranges(true)
+        .build_with_count_size(device, &values_buffer, count_size)
+        .unwrap();
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("async readback encoder") });
+    counting_sort_module.dispatch_work(&mut encoder);
+    queue.submit(Some(encoder.finish()));
+
+    let (sorting_ids, sorted_keys, bucket_ranges) = block_on_readback(device, async {
+        (
+            counting_sort_module.read_sorting_ids(device, queue).await.unwrap(),
+            counting_sort_module.read_sorted_keys(device, queue, &values_buffer).await.unwrap(),
+            counting_sort_module.read_bucket_ranges(device, queue).await.unwrap(),
+        )
+    });
+
+    check_sorted_ids(&values, &sorting_ids);
+    assert!(sorted_keys.windows(2).all(|window| window[0] <= window[1]));
+    for (key, &[start, end]) in bucket_ranges.iter().enumerate() {
+        assert!(sorted_keys[start as usize..end as usize].iter().all(|&sorted_key| sorted_key == key as u32));
+    }
+    assert_eq!(bucket_ranges.last().unwrap()[1], values.len() as u32);
+
+    let module_without_ranges = CountingSortDescriptor::default().build_with_count_size(device, &values_buffer, count_size).unwrap();
+    assert!(matches!(
+        block_on_readback(device, module_without_ranges.read_bucket_ranges(device, queue)),
+        Err(CountingSortingError::MissingOutputBuffer(..))
+    ));
+}
+
+#[test]
+fn check_stage_timestamps() {
+    let (render_instance, device_handle_id) = init_render_instance_and_device();
+    let device_handle = &render_instance.devices[device_handle_id];
+    let device = &device_handle.device;
+    let queue = &device_handle.queue;
+
+    let count_size = 100000u32;
+    let values = (0..200000u32).map(|i| i.wrapping_mul(2654435761) % count_size).collect::<Vec<u32>>();
+    let values_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("values buffer"),
+        contents: bytemuck::cast_slice(&values),
+        usage: wgpu::BufferUsages::STORAGE,
+    });
+
+    let descriptor = CountingSortDescriptor::default().with_workgroup_size(64).with_bucket_ranges(true).with_timestamps(true);
+    let counting_sort_module = match descriptor.build_with_count_size(device, &values_buffer, count_size) {
+        Ok(counting_sort_module) => counting_sort_module,
+        Err(error) => {
+            assert!(!device.features().contains(wgpu::Features::TIMESTAMP_QUERY));
+            assert!(matches!(error, CountingSortingError::MissingDeviceFeature(wgpu::Features::TIMESTAMP_QUERY)));
+            return;
+        }
+    };
+
+    // 3 scan levels for 100000 buckets and workgroups of 64
+    assert_eq!(
+        counting_sort_module.stage_names(),
+        ["count", "scan level 0", "scan level 1", "scan level 2", "propagate level 1", "propagate level 0", "bucket ranges", "scatter"]
+    );
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("timestamps encoder") });
+    counting_sort_module.dispatch_work(&mut encoder);
+    queue.submit(Some(encoder.finish()));
+
+    let stage_durations = block_on_readback(device, counting_sort_module.read_stage_durations(device, queue)).unwrap();
+    assert_eq!(
+        stage_durations.iter().map(|stage_duration| stage_duration.stage.clone()).collect::<Vec<_>>(),
+        counting_sort_module.stage_names()
+    );
+
+    // Splitting the scan in a pass per level doesn't change the result
+    check_sorted_ids(&values, &block_on_readback(device, counting_sort_module.read_sorting_ids(device, queue)).unwrap());
+
+    let module_without_timestamps = CountingSortDescriptor::default().build_with_count_size(device, &values_buffer, count_size).unwrap();
+    assert!(matches!(
+        block_on_readback(device, module_without_timestamps.read_stage_durations(device, queue)),
+        Err(CountingSortingError::MissingOutputBuffer(..))
+    ));
+}
+
+#[test]
+fn check_debug_snapshots() {
+    let (render_instance, device_handle_id) = init_render_instance_and_device();
+    let device_handle = &render_instance.devices[device_handle_id];
+    let device = &device_handle.device;
+    let queue = &device_handle.queue;
+
+    let count_size = 3000u32;
+    let values = (0..20000u32).map(|i| i.wrapping_mul(2654435761) % count_size).collect::<Vec<u32>>();
+    let values_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("values buffer"),
+        contents: bytemuck::cast_slice(&values),
+        usage: wgpu::BufferUsages::STORAGE,
+    });
+
+    // The count buffer is provided by the caller
+    let count_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("count buffer"),
+        size: count_size as u64 * 4,
+        usage: wgpu::BufferUsages::STORAGE,
+        mapped_at_creation: false,
+    });
+
+    let counting_sort_module = CountingSortDescriptor::default()
+        .with_workgroup_size(64)
+        .with_debug_snapshots(true)
+        .build(device, &values_buffer, &count_buffer)
+        .unwrap();
+    assert_eq!(counting_sort_module.debug_snapshot_stage_names(), ["count", "scan level 0", "scan level 1", "propagate level 0"]);
+    assert_eq!(counting_sort_module.debug_snapshot_buffers().unwrap().len(), 4);
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("debug snapshots encoder") });
+    counting_sort_module.dispatch_work(&mut encoder);
+    queue.submit(Some(encoder.finish()));
+
+    let snapshots = block_on_readback(device, counting_sort_module.read_debug_snapshots(device, queue)).unwrap();
+    let histogram = cpu::histogram(&values, count_size).unwrap();
+    assert_eq!(snapshots[0].counts, histogram);
+
+    // The first scan level scans each block of workgroup_size buckets on its own
+    let mut block_scans = histogram.clone();
+    block_scans.chunks_mut(64).for_each(cpu::inclusive_scan);
+    assert_eq!(snapshots[1].counts, block_scans);
+
+    let mut scan = histogram.clone();
+    cpu::inclusive_scan(&mut scan);
+    assert_eq!(snapshots.last().unwrap().counts, scan);
+
+    let directory = std::env::temp_dir().join(format!("oxyde_sorting_snapshots_{}", std::process::id()));
+    dump_count_snapshots(&snapshots, &directory).unwrap();
+    let count_bytes = std::fs::read(directory.join("00_count.bin")).unwrap();
+    assert_eq!(bytemuck::cast_slice::<u8, u32>(&count_bytes), histogram.as_slice());
+    let scan_csv = std::fs::read_to_string(directory.join("03_propagate_level_0.csv")).unwrap();
+    assert_eq!(scan_csv.lines().nth(1), Some(format!("0,{}", scan[0]).as_str()));
+    std::fs::remove_dir_all(&directory).unwrap();
+
+    let module_without_snapshots = GpuCountingSortModule::new(device, &values_buffer, &count_buffer, 64).unwrap();
+    assert!(matches!(
+        block_on_readback(device, module_without_snapshots.read_debug_snapshots(device, queue)),
+        Err(CountingSortingError::MissingOutputBuffer(..))
+    ));
+}
+
+#[test]
+fn check_cpu_and_gpu_backends_match() {
+    let (render_instance, device_handle_id) = init_render_instance_and_device();
+    let device_handle = &render_instance.devices[device_handle_id];
+
+    let gpu_backend = GpuCountingSortBackend::new(&device_handle.device, &device_handle.queue);
+    let backends: [&dyn CountingSortBackend; 2] = [&CpuCountingSortBackend, &gpu_backend];
+
+    let keys = (0..20000u32).map(|i| i.wrapping_mul(2654435761) % 1000).collect::<Vec<u32>>();
+    let [cpu_output, gpu_output] = backends.map(|backend| backend.counting_sort(&keys, 1000).unwrap());
+
+    // Only the order of the ids within a bucket may differ
+    assert_eq!(cpu_output.bucket_starts, gpu_output.bucket_starts);
+    assert_eq!(cpu_output.bucket_ranges, gpu_output.bucket_ranges);
+    for &[start, end] in cpu_output.bucket_ranges.iter() {
+        let mut cpu_bucket = cpu_output.sorting_ids[start as usize..end as usize].to_vec();
+        let mut gpu_bucket = gpu_output.sorting_ids[start as usize..end as usize].to_vec();
+        cpu_bucket.sort_unstable();
+        gpu_bucket.sort_unstable();
+        assert_eq!(cpu_bucket, gpu_bucket);
+    }
+
+    for backend in backends {
+        assert!(matches!(backend.counting_sort(&[1, 5], 4), Err(CountingSortingError::KeyOutOfRange(5, 4))));
+        assert_eq!(backend.counting_sort(&[], 4).unwrap().bucket_ranges, vec![[0, 0]; 4]);
+    }
+}
+
+#[test]
+fn check_gpu_sorter_trait_objects() {
+    let (render_instance, device_handle_id) = init_render_instance_and_device();
+    let device_handle = &render_instance.devices[device_handle_id];
+    let device = &device_handle.device;
+    let queue = &device_handle.queue;
+
+    let count_size = 64u32;
+    let values = (0..4000u32).map(|i| i.wrapping_mul(2654435761) % count_size).collect::<Vec<u32>>();
+    let values_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("values buffer"),
+        contents: bytemuck::cast_slice(&values),
+        usage: wgpu::BufferUsages::STORAGE,
+    });
+
+    let counting_sort_module = GpuCountingSortModule::new_with_count_size(device, &values_buffer, count_size, WorkgroupSize::Auto).unwrap();
+    let sorter: Box<dyn GpuSorter> = Box::new(counting_sort_module);
+    assert_eq!(sorter.capacity(), values.len() as u32);
+    assert!(sorter.requirements().supports_key_range(count_size));
+    assert!(!sorter.requirements().supports_key_range(count_size + 1));
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("gpu sorter encoder") });
+    sorter.dispatch(&mut encoder);
+    queue.submit(Some(encoder.finish()));
+
+    check_sorted_ids(&values, &read_buffer(device, queue, sorter.output_ids().unwrap()));
+}
@@ -0,0 +1,116 @@
+use oxyde::wgpu::{self, util::DeviceExt};
+
+use oxyde_sorting::{block_on_readback, CountingSortDescriptor, CountingSortingError, GpuCountingSortModule, GpuSortVerifyModule, SortVerification};
+
+mod common;
+use common::init_render_instance_and_device;
+
+fn verify_sorting_ids(device: &wgpu::Device, queue: &wgpu::Queue, values_buffer: &wgpu::Buffer, sorting_id_buffer: &wgpu::Buffer) -> SortVerification {
+    let verify_module = GpuSortVerifyModule::new(device, values_buffer, sorting_id_buffer, 64).unwrap();
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("verify encoder") });
+    verify_module.dispatch_work(&mut encoder);
+    queue.submit(Some(encoder.finish()));
+
+    block_on_readback(device, verify_module.read_result(device, queue)).unwrap()
+}
+
+#[test]
+fn check_verify_counting_sort() {
+    let (render_instance, device_handle_id) = init_render_instance_and_device();
+    let device_handle = &render_instance.devices[device_handle_id];
+    let device = &device_handle.device;
+    let queue = &device_handle.queue;
+
+    let count_size = 1000u32;
+    let values = (0..100000u32).map(|i| i.wrapping_mul(2654435761) % count_size).collect::<Vec<u32>>();
+    let values_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("values buffer"),
+        contents: bytemuck::cast_slice(&values),
+        usage: wgpu::BufferUsages::STORAGE,
+    });
+
+    let counting_sort_module = GpuCountingSortModule::new_with_count_size(device, &values_buffer, count_size, 64).unwrap();
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("counting sort encoder") });
+    counting_sort_module.dispatch_work(&mut encoder);
+    queue.submit(Some(encoder.finish()));
+
+    let verification = verify_sorting_ids(device, queue, &values_buffer, counting_sort_module.sorting_id_buffer().unwrap());
+    assert_eq!(
+        verification,
+        SortVerification {
+            ok: true,
+            first_violation: None,
+            duplicate_count: 0,
+        }
+    );
+}
+
+#[test]
+fn check_verify_violations() {
+    let (render_instance, device_handle_id) = init_render_instance_and_device();
+    let device_handle = &render_instance.devices[device_handle_id];
+    let device = &device_handle.device;
+    let queue = &device_handle.queue;
+
+    let values = (0..1000u32).map(|i| i / 10).collect::<Vec<u32>>();
+    let values_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("values buffer"),
+        contents: bytemuck::cast_slice(&values),
+        usage: wgpu::BufferUsages::STORAGE,
+    });
+
+    let check_sorting_ids = |sorting_ids: &[u32], expected: SortVerification| {
+        let sorting_id_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("sorting id buffer"),
+            contents: bytemuck::cast_slice(sorting_ids),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        assert_eq!(verify_sorting_ids(device, queue, &values_buffer, &sorting_id_buffer), expected);
+    };
+
+    let identity = (0..1000u32).collect::<Vec<u32>>();
+    check_sorting_ids(&identity, SortVerification { ok: true, first_violation: None, duplicate_count: 0 });
+
+    // Swapping ids within a bucket keeps the keys sorted
+    let mut swapped_in_bucket = identity.clone();
+    swapped_in_bucket.swap(500, 509);
+    check_sorting_ids(&swapped_in_bucket, SortVerification { ok: true, first_violation: None, duplicate_count: 0 });
+
+    // Key 50 followed by keys 49
+    let mut unsorted = identity.clone();
+    unsorted.swap(495, 505);
+    check_sorting_ids(&unsorted, SortVerification { ok: false, first_violation: Some(496), duplicate_count: 0 });
+
+    // Id 700 at index 700 and 701 (keeping the keys sorted) and 701 missing
+    let mut duplicated = identity.clone();
+    duplicated[701] = 700;
+    check_sorting_ids(&duplicated, SortVerification { ok: false, first_violation: Some(700), duplicate_count: 1 });
+
+    let mut out_of_range = identity.clone();
+    out_of_range[42] = 1000;
+    check_sorting_ids(&out_of_range, SortVerification { ok: false, first_violation: Some(42), duplicate_count: 0 });
+}
+
+#[test]
+fn check_unsupported_options_are_rejected() {
+    let (render_instance, device_handle_id) = init_render_instance_and_device();
+    let device = &render_instance.devices[device_handle_id].device;
+
+    let buffer_usage = wgpu::BufferUsages::STORAGE;
+    let buffer = || device.create_buffer(&wgpu::BufferDescriptor { label: None, size: 4096, usage: buffer_usage, mapped_at_creation: false });
+    let (values_buffer, sorting_id_buffer) = (buffer(), buffer());
+    let descriptor = CountingSortDescriptor::default().with_workgroup_size(64);
+
+    for (descriptor, option_name) in [
+        (descriptor.clone().with_items_per_invocation(4), "items_per_invocation"),
+        (descriptor.clone().with_bucket_ranges(true), "bucket_ranges"),
+        (descriptor.clone().with_timestamps(true), "timestamps"),
+        (descriptor.clone().with_debug_snapshots(true), "debug_snapshots"),
+    ] {
+        assert!(matches!(
+            GpuSortVerifyModule::from_descriptor(device, &descriptor, &values_buffer, &sorting_id_buffer),
+            Err(CountingSortingError::UnsupportedOption("sort verification", name)) if name == option_name
+        ));
+    }
+}